@@ -0,0 +1 @@
+DROP TABLE fs_items;
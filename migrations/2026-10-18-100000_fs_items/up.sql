@@ -0,0 +1,4 @@
+CREATE TABLE fs_items (
+    path TEXT PRIMARY KEY NOT NULL,
+    creation_time DATETIME NOT NULL
+);
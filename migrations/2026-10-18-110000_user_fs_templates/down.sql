@@ -0,0 +1,2 @@
+ALTER TABLE users DROP COLUMN fs_template_version;
+ALTER TABLE users DROP COLUMN fs_template;
@@ -0,0 +1,2 @@
+ALTER TABLE users ADD COLUMN fs_template VARCHAR NULL;
+ALTER TABLE users ADD COLUMN fs_template_version INTEGER NULL;
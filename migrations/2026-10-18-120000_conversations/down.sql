@@ -0,0 +1,4 @@
+DROP INDEX messages_conversation_id;
+ALTER TABLE messages DROP COLUMN conversation_id;
+DROP TABLE conversation_participants;
+DROP TABLE conversations;
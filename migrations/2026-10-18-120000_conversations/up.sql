@@ -0,0 +1,35 @@
+CREATE TABLE conversations (
+    id INTEGER PRIMARY KEY NOT NULL,
+    creation_time DATETIME NOT NULL
+);
+
+
+CREATE TABLE conversation_participants (
+    conversation_id INTEGER REFERENCES conversations(id) ON DELETE CASCADE NOT NULL,
+    user_id INTEGER REFERENCES users(id) NOT NULL,
+    PRIMARY KEY (conversation_id, user_id)
+);
+
+
+-- sqlite can't add a not null column with a reference, but every message gets one anyway
+ALTER TABLE messages ADD COLUMN conversation_id INTEGER NULL REFERENCES conversations(id);
+
+CREATE INDEX messages_conversation_id ON messages(conversation_id);
+
+
+-- each existing thread becomes a conversation, identified by its root message
+INSERT INTO conversations (id, creation_time)
+SELECT id, sent_time FROM messages
+WHERE replying_id IS NULL OR replying_id NOT IN (SELECT id FROM messages);
+
+WITH RECURSIVE thread(message_id, root_id) AS (
+    SELECT id, id FROM conversations
+    UNION ALL
+    SELECT messages.id, thread.root_id FROM messages JOIN thread ON messages.replying_id = thread.message_id
+)
+UPDATE messages SET conversation_id = (SELECT root_id FROM thread WHERE thread.message_id = messages.id);
+
+INSERT OR IGNORE INTO conversation_participants (conversation_id, user_id)
+SELECT conversation_id, sender_id FROM messages
+UNION
+SELECT conversation_id, receiver_id FROM messages;
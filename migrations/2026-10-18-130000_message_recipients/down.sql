@@ -0,0 +1,11 @@
+DROP TABLE user_group_members;
+DROP TABLE user_groups;
+
+ALTER TABLE messages ADD COLUMN is_read BOOL NULL DEFAULT FALSE;
+
+UPDATE messages SET is_read = (
+    SELECT message_recipients.is_read FROM message_recipients
+    WHERE message_recipients.message_id = messages.id AND message_recipients.recipient_id = messages.receiver_id
+);
+
+DROP TABLE message_recipients;
@@ -0,0 +1,30 @@
+CREATE TABLE message_recipients (
+    message_id INTEGER REFERENCES messages(id) ON DELETE CASCADE NOT NULL,
+    recipient_id INTEGER REFERENCES users(id) NOT NULL,
+    is_read BOOL NULL DEFAULT FALSE,
+    PRIMARY KEY (message_id, recipient_id)
+);
+
+CREATE INDEX message_recipients_recipient_id ON message_recipients(recipient_id);
+
+
+-- the read state is per recipient now, so it moves over here
+INSERT INTO message_recipients (message_id, recipient_id, is_read)
+SELECT id, receiver_id, is_read FROM messages;
+
+ALTER TABLE messages DROP COLUMN is_read;
+
+
+CREATE TABLE user_groups (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name VARCHAR(25) UNIQUE NOT NULL,
+    owner_id INTEGER REFERENCES users(id) NOT NULL,
+    creation_time DATETIME NOT NULL
+);
+
+
+CREATE TABLE user_group_members (
+    group_id INTEGER REFERENCES user_groups(id) ON DELETE CASCADE NOT NULL,
+    user_id INTEGER REFERENCES users(id) NOT NULL,
+    PRIMARY KEY (group_id, user_id)
+);
@@ -0,0 +1,10 @@
+CREATE TABLE message_attachments (
+    id INTEGER PRIMARY KEY NOT NULL,
+    message_id INTEGER REFERENCES messages(id) ON DELETE CASCADE NOT NULL,
+    file_key CHAR(36) UNIQUE NOT NULL,
+    name VARCHAR NOT NULL,
+    size BIGINT NOT NULL,
+    mime VARCHAR NULL
+);
+
+CREATE INDEX message_attachments_message_id ON message_attachments(message_id);
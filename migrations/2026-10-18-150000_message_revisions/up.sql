@@ -0,0 +1,12 @@
+ALTER TABLE messages ADD COLUMN edited_time DATETIME NULL;
+
+
+-- the bodies a message had before being edited
+CREATE TABLE message_revisions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    message_id INTEGER REFERENCES messages(id) ON DELETE CASCADE NOT NULL,
+    body TEXT NOT NULL,
+    creation_time DATETIME NOT NULL
+);
+
+CREATE INDEX message_revisions_message_id ON message_revisions(message_id);
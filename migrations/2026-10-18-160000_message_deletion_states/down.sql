@@ -0,0 +1,2 @@
+ALTER TABLE message_recipients DROP COLUMN is_deleted;
+ALTER TABLE messages DROP COLUMN is_deleted_by_sender;
@@ -0,0 +1,6 @@
+-- every participant deletes their own copy, while the message itself is only purged once all of them have
+ALTER TABLE messages ADD COLUMN is_deleted_by_sender BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE message_recipients ADD COLUMN is_deleted BOOLEAN NOT NULL DEFAULT FALSE;
+
+UPDATE messages SET is_deleted_by_sender = TRUE WHERE is_deleted;
+UPDATE message_recipients SET is_deleted = TRUE WHERE message_id IN (SELECT id FROM messages WHERE is_deleted);
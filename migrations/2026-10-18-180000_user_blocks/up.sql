@@ -0,0 +1,11 @@
+-- who may message the user: everyone, contacts or nobody
+ALTER TABLE users ADD COLUMN message_privacy TEXT NOT NULL DEFAULT 'everyone';
+
+CREATE TABLE user_blocks (
+    blocker_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    blocked_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    creation_time DATETIME NOT NULL,
+    PRIMARY KEY (blocker_id, blocked_id)
+);
+
+CREATE INDEX user_blocks_blocked_id ON user_blocks(blocked_id);
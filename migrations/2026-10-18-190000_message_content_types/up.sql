@@ -0,0 +1,30 @@
+ALTER TABLE messages ADD COLUMN content_type TEXT NOT NULL DEFAULT 'plain';
+-- the rendered plain text, which the previews and the search use
+ALTER TABLE messages ADD COLUMN plain_body TEXT;
+UPDATE messages SET plain_body = body;
+
+ALTER TABLE message_revisions ADD COLUMN content_type TEXT NOT NULL DEFAULT 'plain';
+
+-- the index mirrors the plain bodies now, so that the markup wouldn't get searched
+DROP TRIGGER messages_fts_update;
+DROP TRIGGER messages_fts_delete;
+DROP TRIGGER messages_fts_insert;
+DROP TABLE messages_fts;
+
+CREATE VIRTUAL TABLE messages_fts USING fts5(plain_body, content='messages', content_rowid='id');
+
+INSERT INTO messages_fts(messages_fts) VALUES ('rebuild');
+
+CREATE TRIGGER messages_fts_insert AFTER INSERT ON messages BEGIN
+    INSERT INTO messages_fts(rowid, plain_body) VALUES (new.id, new.plain_body);
+END;
+
+CREATE TRIGGER messages_fts_delete AFTER DELETE ON messages BEGIN
+    INSERT INTO messages_fts(messages_fts, rowid, plain_body) VALUES ('delete', old.id, old.plain_body);
+END;
+
+-- covers the edits as well as the purges
+CREATE TRIGGER messages_fts_update AFTER UPDATE OF plain_body ON messages BEGIN
+    INSERT INTO messages_fts(messages_fts, rowid, plain_body) VALUES ('delete', old.id, old.plain_body);
+    INSERT INTO messages_fts(rowid, plain_body) VALUES (new.id, new.plain_body);
+END;
@@ -0,0 +1,10 @@
+-- an emoji or a short code like `:thumbs_up:`, each user can react with several different ones
+CREATE TABLE message_reactions (
+    message_id INTEGER REFERENCES messages(id) ON DELETE CASCADE NOT NULL,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    reaction TEXT NOT NULL,
+    creation_time DATETIME NOT NULL,
+    PRIMARY KEY (message_id, user_id, reaction)
+);
+
+CREATE INDEX message_reactions_user_id ON message_reactions(user_id);
@@ -0,0 +1,7 @@
+DROP INDEX messages_expire_time;
+DROP INDEX messages_pending_sent_time;
+
+DROP TABLE scheduled_recipients;
+
+ALTER TABLE messages DROP COLUMN expire_time;
+ALTER TABLE messages DROP COLUMN is_pending;
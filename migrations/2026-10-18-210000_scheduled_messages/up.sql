@@ -0,0 +1,14 @@
+-- a pending message is yet to be delivered, at its sent_time
+ALTER TABLE messages ADD COLUMN is_pending BOOLEAN NOT NULL DEFAULT FALSE;
+-- when the message gets purged, if ever
+ALTER TABLE messages ADD COLUMN expire_time DATETIME;
+
+-- the recipients of the pending messages, who become the actual recipients once the messages get delivered
+CREATE TABLE scheduled_recipients (
+    message_id INTEGER REFERENCES messages(id) ON DELETE CASCADE NOT NULL,
+    recipient_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    PRIMARY KEY (message_id, recipient_id)
+);
+
+CREATE INDEX messages_pending_sent_time ON messages(sent_time) WHERE is_pending;
+CREATE INDEX messages_expire_time ON messages(expire_time) WHERE expire_time IS NOT NULL;
@@ -0,0 +1,7 @@
+ALTER TABLE tokens DROP COLUMN last_used_time;
+ALTER TABLE tokens DROP COLUMN user_agent;
+ALTER TABLE tokens DROP COLUMN ip;
+ALTER TABLE tokens DROP COLUMN label;
+
+DROP INDEX tokens_id;
+ALTER TABLE tokens DROP COLUMN id;
@@ -0,0 +1,11 @@
+-- the tokens themselves can't be shown to the users, so the sessions are referred to by the id
+ALTER TABLE tokens ADD COLUMN id INTEGER NOT NULL DEFAULT 0;
+-- the rowids are unique already, unlike random numbers. the new sessions get random ids
+UPDATE tokens SET id = rowid;
+CREATE UNIQUE INDEX tokens_id ON tokens(id);
+
+-- where the session was created from, as told by the client
+ALTER TABLE tokens ADD COLUMN label TEXT;
+ALTER TABLE tokens ADD COLUMN ip TEXT;
+ALTER TABLE tokens ADD COLUMN user_agent TEXT;
+ALTER TABLE tokens ADD COLUMN last_used_time DATETIME;
@@ -0,0 +1,6 @@
+DROP TABLE refresh_tokens;
+
+-- the hashes are of no use as the tokens
+DELETE FROM tokens;
+ALTER TABLE tokens DROP COLUMN access_expiry_time;
+ALTER TABLE tokens RENAME COLUMN hashed_value TO value;
@@ -0,0 +1,16 @@
+-- the tokens were stored as they are, and they can't be hashed here, so everyone has to log in again
+DELETE FROM tokens;
+ALTER TABLE tokens RENAME COLUMN value TO hashed_value;
+
+-- the access token expires long before the session does, it's renewed with a refresh token
+ALTER TABLE tokens ADD COLUMN access_expiry_time DATETIME;
+
+-- every refresh token ever issued for a session, the used ones are kept to notice them being reused
+CREATE TABLE refresh_tokens (
+    hashed_value TEXT PRIMARY KEY NOT NULL,
+    token_id INTEGER REFERENCES tokens(id) ON DELETE CASCADE NOT NULL,
+    is_used BOOLEAN NOT NULL DEFAULT FALSE,
+    creation_time DATETIME NOT NULL
+);
+
+CREATE INDEX refresh_tokens_token_id ON refresh_tokens(token_id);
@@ -0,0 +1,2 @@
+-- how long the session can go unused, in seconds. every use pushes its expiry further, up to the lifetime
+ALTER TABLE tokens ADD COLUMN idle_timeout FLOAT;
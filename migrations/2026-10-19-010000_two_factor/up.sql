@@ -0,0 +1,27 @@
+-- the totp secret has to be kept as it is, as the codes are computed from it. it's only enabled once a code is confirmed
+CREATE TABLE two_factor (
+    user_id INTEGER PRIMARY KEY REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    secret TEXT NOT NULL,
+    is_enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    -- the codes of this step and the earlier ones are not accepted again
+    last_used_step BIGINT,
+    creation_time DATETIME NOT NULL
+);
+
+-- each of them can be used once instead of a code
+CREATE TABLE recovery_codes (
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    hashed_code TEXT NOT NULL,
+    PRIMARY KEY (user_id, hashed_code)
+);
+
+-- the logins waiting for the second factor
+CREATE TABLE login_challenges (
+    hashed_value TEXT PRIMARY KEY NOT NULL,
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    label TEXT,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    creation_time DATETIME NOT NULL
+);
+
+CREATE INDEX login_challenges_user_id ON login_challenges(user_id);
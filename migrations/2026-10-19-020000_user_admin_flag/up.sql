@@ -0,0 +1,3 @@
+-- the admins are chosen by whoever runs the server, e.g. `UPDATE users SET is_admin = TRUE WHERE username = '...';`.
+-- the "acc.admin" property is not carried over, as the users can write their properties themselves
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,4 @@
+-- the invalid codes in a row, over all the logins, as each login only allows a few of them
+ALTER TABLE two_factor ADD COLUMN failed_attempts INTEGER NOT NULL DEFAULT 0;
+-- no codes are checked until then
+ALTER TABLE two_factor ADD COLUMN locked_until DATETIME;
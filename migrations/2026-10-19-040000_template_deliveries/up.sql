@@ -0,0 +1,7 @@
+-- the template items each user has been given (or already had), relative to the template's root.
+-- they are not given again, so that whatever the user has removed stays removed
+CREATE TABLE template_deliveries (
+    user_id INTEGER REFERENCES users(id) ON DELETE CASCADE NOT NULL,
+    path TEXT NOT NULL,
+    PRIMARY KEY (user_id, path)
+);
@@ -1,7 +1,9 @@
+use std::collections::HashMap;
 use std::env::VarError;
 use std::io::ErrorKind;
 use std::path::{Path, PathBuf};
 use serde::Deserialize;
+use crate::filesystem::ScanMode;
 
 #[derive(Debug, Deserialize)]
 pub struct ServerConfig {
@@ -19,6 +21,25 @@ struct PartialDBConfig {
 #[derive(Debug, Deserialize)]
 struct PartialAuthConfig {
     pub session_lifetime: Option<u64>,
+    pub access_token_lifetime: Option<u64>,
+    pub idle_timeout: Option<u64>,
+    #[serde(default = "AuthConfig::default_sweep_interval")]
+    pub sweep_interval: u64,
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct TemplateConfig {
+    pub path: PathBuf,
+    #[serde(default = "TemplateConfig::default_version")]
+    pub version: i32,
+}
+
+
+impl TemplateConfig {
+    fn default_version() -> i32 {
+        1
+    }
 }
 
 
@@ -26,11 +47,84 @@ struct PartialAuthConfig {
 pub struct FilesystemConfig {
     pub storage_path: PathBuf,
     pub template_path: Option<PathBuf>,
+    #[serde(default)]
+    pub templates: HashMap<String, TemplateConfig>,
+    pub default_template: Option<String>,
+    /// the templates, other than the default one, which can be chosen during an acc creation
+    #[serde(default)]
+    pub signup_templates: Vec<String>,
     pub total_size: Option<u64>,
     pub user_space_size: Option<u64>,
+    pub startup_scan: Option<ScanMode>,
 } 
 
 
+impl FilesystemConfig {
+    pub const LEGACY_TEMPLATE_NAME: &'static str = "default";
+
+    /// the legacy `template_path` is treated as the first version of a template named "default"
+    fn adapt_legacy_template(&mut self) {
+        if let Some(path) = self.template_path.take() {
+            self.templates.entry(Self::LEGACY_TEMPLATE_NAME.to_string())
+                .or_insert(TemplateConfig { path, version: TemplateConfig::default_version() });
+
+            self.default_template.get_or_insert(Self::LEGACY_TEMPLATE_NAME.to_string());
+        };
+
+        if let Some(ref name) = self.default_template {
+            if !self.templates.contains_key(name) {
+                panic!("default template '{name}' should be defined in filesystem.templates");
+            };
+        };
+
+        for name in &self.signup_templates {
+            if !self.templates.contains_key(name) {
+                panic!("signup template '{name}' should be defined in filesystem.templates");
+            };
+        };
+    }
+}
+
+
+/// `count` messages per `period` seconds
+#[derive(Debug, Deserialize)]
+pub struct RateLimitConfig {
+    pub count: u32,
+    pub period: u64,
+}
+
+
+#[derive(Debug, Deserialize)]
+pub struct MessagesConfig {
+    /// in characters
+    pub max_body_length: Option<usize>,
+    pub user_send_rate: Option<RateLimitConfig>,
+    pub ip_send_rate: Option<RateLimitConfig>,
+    /// how often the scheduled messages are delivered and the expired ones purged, in seconds
+    #[serde(default = "MessagesConfig::default_scheduler_interval")]
+    pub scheduler_interval: u64,
+}
+
+
+impl MessagesConfig {
+    fn default_scheduler_interval() -> u64 {
+        10
+    }
+}
+
+
+impl Default for MessagesConfig {
+    fn default() -> Self {
+        Self {
+            max_body_length: None,
+            user_send_rate: None,
+            ip_send_rate: None,
+            scheduler_interval: Self::default_scheduler_interval(),
+        }
+    }
+}
+
+
 #[derive(Debug, Deserialize)]
 struct PartialConfig {
     pub name: String,
@@ -38,13 +132,29 @@ struct PartialConfig {
     pub filesystem: FilesystemConfig,
     pub database: PartialDBConfig,
     pub auth: PartialAuthConfig,
+    #[serde(default)]
+    pub messages: MessagesConfig,
 }
 
 
 #[derive(Debug, Deserialize)]
 pub struct AuthConfig {
     pub code: Option<String>,
+    /// in seconds, since the login
     pub session_lifetime: Option<u64>,
+    /// the v2 access tokens have to be refreshed this often, in seconds
+    pub access_token_lifetime: Option<u64>,
+    /// in seconds, since the last use
+    pub idle_timeout: Option<u64>,
+    /// how often the expired tokens are purged, in seconds
+    pub sweep_interval: u64,
+}
+
+
+impl AuthConfig {
+    fn default_sweep_interval() -> u64 {
+        3600
+    }
 }
 
 
@@ -62,6 +172,7 @@ pub struct Config {
     pub filesystem: FilesystemConfig,
     pub database: DBConfig,
     pub auth: AuthConfig,
+    pub messages: MessagesConfig,
 }
 
 
@@ -79,9 +190,11 @@ impl Config {
         
         let config_raw = Self::read(path.as_str().as_ref());
 
-        let part = toml::from_str::<PartialConfig>(&config_raw)
+        let mut part = toml::from_str::<PartialConfig>(&config_raw)
             .unwrap_or_else(|err| panic!("{path} should be a valid config file:\n{err}"));
 
+        part.filesystem.adapt_legacy_template();
+
         Self {
             name: part.name,
             server: part.server,
@@ -92,8 +205,12 @@ impl Config {
             },
             auth: AuthConfig {
                 code: get_opt_env_var(Self::AUTH_CODE_ENV_VAR),
-                session_lifetime: part.auth.session_lifetime
-            }
+                session_lifetime: part.auth.session_lifetime,
+                access_token_lifetime: part.auth.access_token_lifetime,
+                idle_timeout: part.auth.idle_timeout,
+                sweep_interval: part.auth.sweep_interval,
+            },
+            messages: part.messages,
         }
     }
     
@@ -0,0 +1,574 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde::{Deserialize, Serialize};
+
+
+/// the link schemes which are allowed, anything without a scheme is taken as relative
+const ALLOWED_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ContentType {
+    Plain,
+    Markdown,
+    /// a json list of text, mention and link spans
+    Structured,
+}
+
+
+impl ContentType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Plain => "plain",
+            Self::Markdown => "markdown",
+            Self::Structured => "structured",
+        }
+    }
+
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "plain" => Some(Self::Plain),
+            "markdown" => Some(Self::Markdown),
+            "structured" => Some(Self::Structured),
+            _ => None,
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub enum ContentError {
+    /// holds why the structured body could not be parsed
+    InvalidStructure(String),
+    /// holds the url
+    DisallowedLink(String),
+}
+
+
+impl IntoResponse for ContentError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidStructure(err) => (StatusCode::BAD_REQUEST, format!("the structured body is invalid: {err}")).into_response(),
+            Self::DisallowedLink(url) => (StatusCode::BAD_REQUEST, format!("the link '{url}' is not allowed")).into_response(),
+        }
+    }
+}
+
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum Span {
+    Text { text: String },
+    Mention { username: String },
+    Link {
+        url: String,
+        /// the url is shown if there is no text
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        text: Option<String>,
+    },
+}
+
+
+/// a sanitized message body, along with its plain text rendering
+#[derive(Debug, Clone)]
+pub struct Body {
+    pub content_type: ContentType,
+    pub text: String,
+    /// what the previews and the search use, so that they would not contain any markup
+    pub plain_text: String,
+}
+
+
+impl Body {
+    pub fn new(content_type: ContentType, raw: &str) -> Result<Self, ContentError> {
+        let text = match content_type {
+            ContentType::Plain => strip_control_chars(raw),
+            ContentType::Markdown => sanitize_markdown(&strip_control_chars(raw))?,
+            ContentType::Structured => sanitize_structured(raw)?,
+        };
+
+        let plain_text = match content_type {
+            ContentType::Plain => text.clone(),
+            ContentType::Markdown => markdown_to_plain_text(&text),
+            ContentType::Structured => structured_to_plain_text(&text),
+        };
+
+        Ok(Self { content_type, text, plain_text })
+    }
+}
+
+
+/// keeps the newlines and tabs only. the bidi overrides are removed as well, as they can make the text read differently than it is
+fn strip_control_chars(s: &str) -> String {
+    s.replace("\r\n", "\n")
+        .chars()
+        .filter(|&c| c == '\n' || c == '\t' || !(c.is_control() || ('\u{202a}'..='\u{202e}').contains(&c) || ('\u{2066}'..='\u{2069}').contains(&c)))
+        .collect()
+}
+
+
+fn is_allowed_url(url: &str) -> bool {
+    let url = url.trim();
+    // the part which could be taken for the scheme. the renderers decode the escapes and the entities, so those can't be trusted there
+    let head = &url[..url.find(['/', '?', '#']).unwrap_or(url.len())];
+
+    if head.contains(['&', '\\']) {
+        return false;
+    };
+
+    match head.split_once(':') {
+        Some((scheme, _)) => ALLOWED_SCHEMES.contains(&scheme.to_ascii_lowercase().as_str()),
+        None => true,
+    }
+}
+
+
+/// the spans are checked and written out again, so that only the known fields would be kept
+fn sanitize_structured(raw: &str) -> Result<String, ContentError> {
+    let spans = serde_json::from_str::<Vec<Span>>(raw).map_err(|err| ContentError::InvalidStructure(err.to_string()))?;
+
+    let mut sanitized: Vec<Span> = Vec::with_capacity(spans.len());
+
+    for span in spans {
+        let span = match span {
+            Span::Text { text } => Span::Text { text: strip_control_chars(&text) },
+            Span::Mention { username } => Span::Mention { username: strip_control_chars(&username) },
+            Span::Link { url, text } => {
+                if !is_allowed_url(&url) {
+                    return Err(ContentError::DisallowedLink(url));
+                };
+
+                Span::Link { url: strip_control_chars(url.trim()), text: text.map(|t| strip_control_chars(&t)) }
+            },
+        };
+
+        // the adjacent text spans are merged and the empty ones dropped
+        match (sanitized.last_mut(), span) {
+            (_, Span::Text { text }) if text.is_empty() => (),
+            (Some(Span::Text { text: prev }), Span::Text { text }) => prev.push_str(&text),
+            (_, span) => sanitized.push(span),
+        };
+    };
+
+    Ok(serde_json::to_string(&sanitized).expect("the spans should be serializable"))
+}
+
+
+fn structured_to_plain_text(text: &str) -> String {
+    let spans = serde_json::from_str::<Vec<Span>>(text).expect("the structured body should be sanitized already");
+
+    spans.into_iter().map(|span| match span {
+        Span::Text { text } => text,
+        Span::Mention { username } => format!("@{username}"),
+        Span::Link { url, text } => text.unwrap_or(url),
+    }).collect()
+}
+
+
+/// the raw html is escaped, so that it would be shown as text, and the links are checked
+fn sanitize_markdown(text: &str) -> Result<String, ContentError> {
+    let mut sanitized = String::with_capacity(text.len());
+
+    let mut line_start = 0;
+
+    for line in text.split_inclusive('\n') {
+        // the link reference definitions, like `[id]: url`. the url can be on the next line
+        if let Some(url) = link_definition_url(&text[line_start..]) {
+            if !is_allowed_url(url) {
+                return Err(ContentError::DisallowedLink(url.to_string()));
+            };
+        };
+
+        let mut rest = line;
+
+        while let Some(c) = rest.chars().next() {
+            if c == '<' {
+                let len = match autolink(rest) {
+                    Some((url, len)) if url.contains([':', '@']) && is_allowed_url(url) => {
+                        sanitized.push_str(&rest[..len]);
+                        len
+                    },
+                    _ => {
+                        let is_tag = rest[1..].starts_with(|c: char| c.is_ascii_alphabetic() || matches!(c, '/' | '!' | '?'));
+                        sanitized.push_str(if is_tag { "&lt;" } else { "<" });
+                        1
+                    },
+                };
+
+                rest = &rest[len..];
+                continue;
+            };
+
+            if rest.starts_with("](") {
+                // the rest of the text, as the destination can be on the next line
+                let url = link_destination(&text[line_start + line.len() - rest.len() + 2..]);
+
+                if !is_allowed_url(url) {
+                    return Err(ContentError::DisallowedLink(url.to_string()));
+                };
+            };
+
+            sanitized.push(c);
+            rest = &rest[c.len_utf8()..];
+        };
+
+        line_start += line.len();
+    };
+
+    Ok(sanitized)
+}
+
+
+/// the url and the length of the whole `<url>`
+fn autolink(s: &str) -> Option<(&str, usize)> {
+    let end = s.find('>')?;
+    let url = &s[1..end];
+
+    if url.is_empty() || url.contains(|c: char| c.is_whitespace() || c == '<') {
+        return None;
+    };
+
+    Some((url, end + 1))
+}
+
+
+/// the part after `](` or `]:`, up to the closing parenthesis or the title.
+/// as in commonmark, it can be preceded by one line ending, and the parentheses in it can be nested
+fn link_destination(s: &str) -> &str {
+    let s = s.trim_start_matches([' ', '\t']);
+    let s = s.strip_prefix('\n').unwrap_or(s).trim_start_matches([' ', '\t']);
+
+    if let Some(s) = s.strip_prefix('<') {
+        return &s[..s.find(['>', '\n']).unwrap_or(s.len())];
+    };
+
+    let mut depth = 0;
+    let mut chars = s.char_indices();
+
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '\\' => { chars.next(); },
+            '(' => depth += 1,
+            ')' if depth == 0 => return &s[..i],
+            ')' => depth -= 1,
+            c if c.is_whitespace() || c.is_control() => return &s[..i],
+            _ => (),
+        };
+    };
+
+    s
+}
+
+
+/// expects the text from the start of a line, the label has to be on that line
+fn link_definition_url(text: &str) -> Option<&str> {
+    let line = &text[..text.find('\n').unwrap_or(text.len())];
+    let label_start = line.len() - line.trim_start_matches(' ').len();
+    let label_end = line[label_start..].strip_prefix('[')?.find("]:")? + label_start + 1;
+
+    Some(link_destination(&text[label_end + 2..]))
+}
+
+
+/// approximates how the markdown would read once rendered
+fn markdown_to_plain_text(text: &str) -> String {
+    let mut lines = Vec::new();
+    let mut is_in_code_block = false;
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            is_in_code_block = !is_in_code_block;
+            continue;
+        };
+
+        if is_in_code_block {
+            lines.push(line.to_string());
+            continue;
+        };
+
+        if link_definition_url(line).is_some() || is_thematic_break(trimmed) {
+            continue;
+        };
+
+        let mut content = trimmed;
+
+        // the blockquotes can be nested
+        while let Some(quoted) = content.strip_prefix('>') {
+            content = quoted.trim_start();
+        };
+
+        let heading_level = content.chars().take_while(|&c| c == '#').count();
+        if (1..=6).contains(&heading_level) && content[heading_level..].chars().next().is_none_or(char::is_whitespace) {
+            content = content[heading_level..].trim();
+        };
+
+        lines.push(inline_to_plain_text(content));
+    };
+
+    lines.join("\n").trim().to_string()
+}
+
+
+fn is_thematic_break(line: &str) -> bool {
+    let chars = line.chars().filter(|c| !c.is_whitespace()).collect::<Vec<_>>();
+
+    chars.len() >= 3 && ['-', '*', '_'].iter().any(|&m| chars.iter().all(|&c| c == m))
+}
+
+
+fn inline_to_plain_text(s: &str) -> String {
+    let chars = s.chars().collect::<Vec<_>>();
+    let mut plain = String::with_capacity(s.len());
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let prev = i.checked_sub(1).map(|p| chars[p]);
+        let next = chars.get(i + 1).copied();
+
+        match c {
+            '\\' if next.is_some_and(|n| n.is_ascii_punctuation()) => {
+                plain.push(next.expect("the next char was just checked"));
+                i += 2;
+            },
+            '`' => {
+                let run = chars[i..].iter().take_while(|&&c| c == '`').count();
+                let content_start = i + run;
+
+                // the code span is kept as is, up to the closing run of the same length
+                match (content_start..chars.len()).find(|&j| chars[j..].iter().take_while(|&&c| c == '`').count() == run && chars[j - 1] != '`') {
+                    Some(end) => {
+                        plain.extend(&chars[content_start..end]);
+                        i = end + run;
+                    },
+                    None => {
+                        plain.extend(&chars[i..content_start]);
+                        i = content_start;
+                    },
+                };
+            },
+            '!' if next == Some('[') => {
+                i += 1;
+            },
+            '[' => {
+                match find_link_end(&chars, i) {
+                    Some((text_end, link_end)) => {
+                        plain.push_str(&inline_to_plain_text(&chars[i + 1..text_end].iter().collect::<String>()));
+                        i = link_end;
+                    },
+                    None => {
+                        plain.push(c);
+                        i += 1;
+                    },
+                };
+            },
+            '<' => {
+                let rest = chars[i..].iter().collect::<String>();
+
+                match autolink(&rest) {
+                    Some((url, len)) => {
+                        plain.push_str(url);
+                        i += rest[..len].chars().count();
+                    },
+                    None => {
+                        plain.push(c);
+                        i += 1;
+                    },
+                };
+            },
+            '&' => {
+                let rest = chars[i..].iter().take(6).collect::<String>();
+                let entity = [("&lt;", '<'), ("&gt;", '>'), ("&amp;", '&'), ("&quot;", '"'), ("&#39;", '\'')].into_iter()
+                    .find(|(e, _)| rest.starts_with(e));
+
+                match entity {
+                    Some((e, decoded)) => {
+                        plain.push(decoded);
+                        i += e.len();
+                    },
+                    None => {
+                        plain.push(c);
+                        i += 1;
+                    },
+                };
+            },
+            // the emphasis markers are dropped when they are next to some text, but an underscore within a word is just an underscore
+            '*' | '_' | '~' => {
+                let run = chars[i..].iter().take_while(|&&m| m == c).count();
+                let before = prev;
+                let after = chars.get(i + run).copied();
+                let touches_text = before.is_some_and(|b| !b.is_whitespace()) || after.is_some_and(|a| !a.is_whitespace());
+                let is_intraword = before.is_some_and(char::is_alphanumeric) && after.is_some_and(char::is_alphanumeric);
+                let is_marker = touches_text && !(c == '_' && is_intraword) && !(c == '~' && run < 2);
+
+                if !is_marker {
+                    plain.extend(&chars[i..i + run]);
+                };
+
+                i += run;
+            },
+            c => {
+                plain.push(c);
+                i += 1;
+            },
+        };
+    };
+
+    plain
+}
+
+
+/// the closing bracket of the text and where the whole `[text](url)` ends
+fn find_link_end(chars: &[char], start: usize) -> Option<(usize, usize)> {
+    let mut depth = 0;
+    let mut text_end = None;
+
+    for (j, &c) in chars.iter().enumerate().skip(start) {
+        match c {
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+
+                if depth == 0 {
+                    text_end = Some(j);
+                    break;
+                };
+            },
+            _ => (),
+        };
+    };
+
+    let text_end = text_end?;
+
+    if chars.get(text_end + 1) != Some(&'(') {
+        return None;
+    };
+
+    let link_end = chars.iter().enumerate().skip(text_end + 2).find(|(_, &c)| c == ')').map(|(j, _)| j + 1)?;
+
+    Some((text_end, link_end))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::{is_allowed_url, Body, ContentError, ContentType};
+
+    fn markdown(raw: &str) -> Body {
+        Body::new(ContentType::Markdown, raw).unwrap()
+    }
+
+    fn structured(raw: &str) -> Result<Body, ContentError> {
+        Body::new(ContentType::Structured, raw)
+    }
+
+    #[test]
+    fn control_chars_are_stripped() {
+        let body = Body::new(ContentType::Plain, "a\r\nb\tc\u{7}d\u{202e}e\u{2066}f").unwrap();
+
+        assert_eq!(body.text, "a\nb\tcdef");
+        assert_eq!(body.plain_text, body.text);
+    }
+
+    #[test]
+    fn only_safe_urls_are_allowed() {
+        for url in ["https://example.com", "HTTP://example.com", "mailto:a@b.c", "/relative/path", "page?a=b:c", "#top", " https://x.y "] {
+            assert!(is_allowed_url(url), "{url}");
+        };
+
+        for url in ["javascript:alert(1)", "JavaScript:alert(1)", "data:text/html,x", "vbscript:x", "java&#115;cript:x", "javascript&colon;x", "\\\\host\\share"] {
+            assert!(!is_allowed_url(url), "{url}");
+        };
+    }
+
+    #[test]
+    fn markdown_html_is_escaped() {
+        assert_eq!(markdown("<script>alert(1)</script>").text, "&lt;script>alert(1)&lt;/script>");
+        assert_eq!(markdown("<!-- x --> <img src=x>").text, "&lt;!-- x --> &lt;img src=x>");
+        // not a tag, so it's left as is
+        assert_eq!(markdown("1 < 2").text, "1 < 2");
+        assert_eq!(markdown("<https://example.com>").text, "<https://example.com>");
+    }
+
+    #[test]
+    fn markdown_links_are_checked() {
+        assert!(Body::new(ContentType::Markdown, "[x](https://example.com)").is_ok());
+
+        for raw in [
+            "[x](javascript:alert(1))",
+            "[x]( <javascript:alert(1)> )",
+            "![x](data:image/svg+xml,x)",
+            "[x]: javascript:alert(1)",
+            "<javascript:alert(1)>",
+            "[x](\njavascript:alert(1))",
+            "[x](\n  <javascript:alert(1)>)",
+            "[id]:\njavascript:alert(1)\n\n[x][id]",
+        ] {
+            match Body::new(ContentType::Markdown, raw) {
+                Err(ContentError::DisallowedLink(_)) => {},
+                // an autolink which isn't allowed is just escaped instead
+                Ok(body) if raw.starts_with('<') => assert_eq!(body.text, "&lt;javascript:alert(1)>"),
+                res => panic!("{raw} was not refused: {res:?}"),
+            };
+        };
+
+        // the whole destination is shown, along with its parentheses
+        assert!(matches!(
+            Body::new(ContentType::Markdown, "[x](javascript:alert((1)) more)"),
+            Err(ContentError::DisallowedLink(url)) if url == "javascript:alert((1))"
+        ));
+        // a destination after a blank line is not a destination anymore
+        assert!(Body::new(ContentType::Markdown, "[x](\n\njavascript:alert(1))").is_ok());
+    }
+
+    #[test]
+    fn markdown_previews_are_plain() {
+        for (raw, plain) in [
+            ("# Title\n\nsome **bold** and _em_ and ~~gone~~ text", "Title\n\nsome bold and em and gone text"),
+            ("> quoted\n>> twice", "quoted\ntwice"),
+            ("a [link](https://example.com) and ![image](/a.png)", "a link and image"),
+            ("see <https://example.com>", "see https://example.com"),
+            ("`*kept*` and snake_case_name", "*kept* and snake_case_name"),
+            ("```\n**code**\n```", "**code**"),
+            ("escaped \\*stars\\* and 1 &lt; 2", "escaped *stars* and 1 < 2"),
+            ("above\n\n---\n\nbelow\n\n[id]: https://example.com", "above\n\n\nbelow"),
+            ("2 * 3 * 4", "2 * 3 * 4"),
+        ] {
+            assert_eq!(markdown(raw).plain_text, plain, "{raw}");
+        };
+    }
+
+    #[test]
+    fn structured_spans_are_normalized() {
+        let body = structured(r#"[
+            {"type": "text", "text": "hi "},
+            {"type": "text", "text": ""},
+            {"type": "text", "text": "there\u0007 "},
+            {"type": "mention", "username": "someone"},
+            {"type": "link", "url": " https://example.com ", "text": "site"},
+            {"type": "link", "url": "/a"}
+        ]"#).unwrap();
+
+        assert_eq!(
+            body.text,
+            r#"[{"type":"text","text":"hi there "},{"type":"mention","username":"someone"},{"type":"link","url":"https://example.com","text":"site"},{"type":"link","url":"/a"}]"#
+        );
+        assert_eq!(body.plain_text, "hi there @someonesite/a");
+    }
+
+    #[test]
+    fn structured_bodies_are_validated() {
+        assert!(matches!(structured(r#"[{"type": "link", "url": "javascript:x"}]"#), Err(ContentError::DisallowedLink(_))));
+
+        for raw in [
+            "not json",
+            r#"{"type": "text", "text": "x"}"#,
+            r#"[{"type": "script", "text": "x"}]"#,
+            r#"[{"type": "text", "text": "x", "html": "<b>"}]"#,
+        ] {
+            assert!(matches!(structured(raw), Err(ContentError::InvalidStructure(_))), "{raw}");
+        };
+    }
+}
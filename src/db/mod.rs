@@ -3,9 +3,17 @@ mod functions;
 mod models;
 
 
-pub use models::users::{User, UserCreationError, UserInteractionError};
-pub use models::tokens::Token;
-pub use models::messages::Message;
+pub use models::users::{User, UserCreationError, MessagePrivacy};
+pub use models::tokens::{Token, TokenAuth, TokenClient, TokenLifetimes, IssuedToken, TokenRefreshError};
+pub use models::messages::{Message, MessageRecipient, MessageRevision, MessageFilter, MessageFolder, MessageSchedule, MessageSendError};
+pub use models::message_attachments::{MessageAttachment, NewMessageAttachment};
+pub use models::fs_items::FSItem;
+pub use models::template_deliveries::TemplateDelivery;
+pub use models::conversations::{Conversation, ConversationOverview};
+pub use models::user_groups::{UserGroup, UserGroupCreationError};
+pub use models::user_blocks::{UserBlock, BlockStats};
+pub use models::message_reactions::MessageReaction;
+pub use models::two_factor::{TwoFactor, TwoFactorError, LoginChallenge, LoginChallengeError};
 
 
 use diesel::sqlite::SqliteConnection;
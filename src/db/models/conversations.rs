@@ -0,0 +1,202 @@
+use std::collections::HashMap;
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Integer, Nullable};
+use crate::db;
+use super::gen_id;
+use super::super::schema::{self, conversations::dsl::*, conversation_participants, message_recipients, messages, users};
+
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::conversations)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct Conversation {
+    pub id: i32,
+    pub creation_time: NaiveDateTime,
+}
+
+
+pub struct ConversationOverview {
+    pub conversation: Conversation,
+    pub participants: Vec<db::User>,
+    pub last_message: Option<db::Message>,
+    pub last_message_recipients: Vec<db::MessageRecipient>,
+    pub last_message_attachments: Vec<db::MessageAttachment>,
+    pub last_message_reactions: Vec<db::MessageReaction>,
+    pub unread_count: i64,
+}
+
+
+#[derive(QueryableByName)]
+struct ConversationActivity {
+    #[diesel(sql_type = Integer)]
+    conversation_id: i32,
+    #[diesel(sql_type = Nullable<Integer>)]
+    last_message_id: Option<i32>,
+    #[diesel(sql_type = BigInt)]
+    unread_count: i64,
+}
+
+
+impl Conversation {
+    pub fn create(conn: &mut SqliteConnection, participants: &[i32]) -> Self {
+        conn.transaction(|conn| {
+            let conversation = diesel::insert_into(conversations)
+                .values(&Self { id: gen_id(), creation_time: Utc::now().naive_utc() })
+                .get_result::<Self>(conn)?;
+
+            Self::add_participants(conn, conversation.id, participants);
+
+            diesel::QueryResult::Ok(conversation)
+        }).unwrap()
+    }
+
+    pub fn get(conn: &mut SqliteConnection, id_: i32) -> Option<Self> {
+        conversations
+            .find(id_)
+            .select(Self::as_select())
+            .get_result(conn)
+            .optional()
+            .unwrap()
+    }
+
+    /// already present participants are left as is
+    pub fn add_participants(conn: &mut SqliteConnection, id_: i32, participants: &[i32]) {
+        for user_id in participants {
+            diesel::insert_or_ignore_into(conversation_participants::table)
+                .values((
+                    conversation_participants::conversation_id.eq(id_),
+                    conversation_participants::user_id.eq(user_id),
+                ))
+                .execute(conn)
+                .unwrap();
+        };
+    }
+
+    pub fn is_participant(&self, conn: &mut SqliteConnection, user: &db::User) -> bool {
+        diesel::select(diesel::dsl::exists(
+            conversation_participants::table.find((self.id, user.id))
+        ))
+            .get_result(conn)
+            .unwrap()
+    }
+
+    pub fn get_participants(&self, conn: &mut SqliteConnection) -> Vec<db::User> {
+        conversation_participants::table
+            .filter(conversation_participants::conversation_id.eq(self.id))
+            .inner_join(users::table)
+            .select(db::User::as_select())
+            .get_results(conn)
+            .unwrap()
+    }
+
+    /// the conversations of a user, the most recently active first.
+    /// only the messages the user has access to, and has not deleted, are considered
+    pub fn get_overviews_of_user(conn: &mut SqliteConnection, user: &db::User, count: i64, offset: u64) -> Vec<ConversationOverview> {
+        let activities = diesel::sql_query(r#"
+            SELECT
+                conversations.id AS conversation_id,
+                (
+                    SELECT messages.id FROM messages
+                    WHERE messages.conversation_id = conversations.id
+                        AND NOT messages.is_deleted
+                        AND ((messages.sender_id = ?1 AND NOT messages.is_deleted_by_sender) OR EXISTS (
+                            SELECT 1 FROM message_recipients
+                            WHERE message_recipients.message_id = messages.id AND message_recipients.recipient_id = ?1
+                                AND NOT message_recipients.is_deleted
+                        ))
+                    ORDER BY messages.sent_time DESC
+                    LIMIT 1
+                ) AS last_message_id,
+                (
+                    SELECT MAX(messages.sent_time) FROM messages
+                    WHERE messages.conversation_id = conversations.id
+                        AND NOT messages.is_deleted
+                        AND ((messages.sender_id = ?1 AND NOT messages.is_deleted_by_sender) OR EXISTS (
+                            SELECT 1 FROM message_recipients
+                            WHERE message_recipients.message_id = messages.id AND message_recipients.recipient_id = ?1
+                                AND NOT message_recipients.is_deleted
+                        ))
+                ) AS last_activity_time,
+                (
+                    SELECT COUNT(*) FROM messages
+                    JOIN message_recipients ON message_recipients.message_id = messages.id
+                    WHERE messages.conversation_id = conversations.id
+                        AND message_recipients.recipient_id = ?1
+                        AND message_recipients.is_read = FALSE
+                        AND NOT message_recipients.is_deleted
+                ) AS unread_count
+            FROM conversations
+            JOIN conversation_participants ON conversation_participants.conversation_id = conversations.id
+            WHERE conversation_participants.user_id = ?1
+            ORDER BY COALESCE(last_activity_time, conversations.creation_time) DESC
+            LIMIT ?2 OFFSET ?3
+        "#)
+            .bind::<Integer, _>(user.id)
+            .bind::<BigInt, _>(count)
+            .bind::<BigInt, _>(offset as i64)  // todo return err if it doesnt fit
+            .load::<ConversationActivity>(conn)
+            .unwrap();
+
+        let ids = activities.iter().map(|a| a.conversation_id).collect::<Vec<_>>();
+
+        let mut conversations_ = conversations
+            .filter(id.eq_any(&ids))
+            .select(Self::as_select())
+            .get_results(conn)
+            .unwrap()
+            .into_iter()
+            .map(|c| (c.id, c))
+            .collect::<HashMap<_, _>>();
+
+        let mut last_messages = messages::table
+            .filter(messages::id.eq_any(activities.iter().filter_map(|a| a.last_message_id)))
+            .select(db::Message::as_select())
+            .get_results(conn)
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.id, m))
+            .collect::<HashMap<_, _>>();
+
+        let mut last_messages_recipients = HashMap::<i32, Vec<db::MessageRecipient>>::new();
+        for recipient in message_recipients::table
+            .filter(message_recipients::message_id.eq_any(last_messages.keys()))
+            .select(db::MessageRecipient::as_select())
+            .get_results(conn)
+            .unwrap()
+        {
+            last_messages_recipients.entry(recipient.message_id).or_default().push(recipient);
+        };
+
+        let mut last_messages_attachments = HashMap::<i32, Vec<db::MessageAttachment>>::new();
+        for attachment in db::MessageAttachment::get_all_of_messages(conn, &last_messages.keys().copied().collect::<Vec<_>>()) {
+            last_messages_attachments.entry(attachment.message_id).or_default().push(attachment);
+        };
+
+        let mut last_messages_reactions = HashMap::<i32, Vec<db::MessageReaction>>::new();
+        for reaction in db::MessageReaction::get_all_of_messages(conn, &last_messages.keys().copied().collect::<Vec<_>>()) {
+            last_messages_reactions.entry(reaction.message_id).or_default().push(reaction);
+        };
+
+        let mut participants = HashMap::<i32, Vec<db::User>>::new();
+        for (conversation_id, participant) in conversation_participants::table
+            .filter(conversation_participants::conversation_id.eq_any(&ids))
+            .inner_join(users::table)
+            .select((conversation_participants::conversation_id, db::User::as_select()))
+            .get_results::<(i32, db::User)>(conn)
+            .unwrap()
+        {
+            participants.entry(conversation_id).or_default().push(participant);
+        };
+
+        activities.into_iter().map(|a| ConversationOverview {
+            conversation: conversations_.remove(&a.conversation_id).expect("the ids were just selected"),
+            participants: participants.remove(&a.conversation_id).unwrap_or_default(),
+            last_message: a.last_message_id.and_then(|id_| last_messages.remove(&id_)),
+            last_message_recipients: a.last_message_id.and_then(|id_| last_messages_recipients.remove(&id_)).unwrap_or_default(),
+            last_message_attachments: a.last_message_id.and_then(|id_| last_messages_attachments.remove(&id_)).unwrap_or_default(),
+            last_message_reactions: a.last_message_id.and_then(|id_| last_messages_reactions.remove(&id_)).unwrap_or_default(),
+            unread_count: a.unread_count,
+        }).collect()
+    }
+}
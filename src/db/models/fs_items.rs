@@ -0,0 +1,78 @@
+use chrono::NaiveDateTime;
+use diesel::prelude::*;
+use super::super::schema::{self, fs_items::dsl::*};
+
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::fs_items)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct FSItem {
+    pub path: String,
+    pub creation_time: NaiveDateTime,
+}
+
+
+impl FSItem {
+    pub fn get(conn: &mut SqliteConnection, path_: &str) -> Option<Self> {
+        fs_items
+            .find(path_)
+            .select(Self::as_select())
+            .get_result(conn)
+            .optional()
+            .unwrap()
+    }
+
+    pub fn get_all_paths(conn: &mut SqliteConnection) -> Vec<String> {
+        fs_items
+            .select(path)
+            .get_results(conn)
+            .unwrap()
+    }
+
+    /// replaces whatever was recorded for those paths before, as the items are new now
+    pub fn record(conn: &mut SqliteConnection, paths: &[String], creation_time_: NaiveDateTime) {
+        conn.transaction(|conn| {
+            for path_ in paths {
+                diesel::replace_into(fs_items)
+                    .values(&Self { path: path_.clone(), creation_time: creation_time_ })
+                    .execute(conn)?;
+            };
+
+            diesel::QueryResult::Ok(())
+        }).unwrap();
+    }
+
+    /// moves the records of an item and everything under it
+    pub fn move_tree(conn: &mut SqliteConnection, source: &str, target: &str) {
+        conn.transaction(|conn| {
+            Self::delete_tree(conn, target);
+
+            let items = fs_items
+                .filter(path.eq(source).or(path.like(subtree_pattern(source)).escape('\\')))
+                .select(Self::as_select())
+                .get_results(conn)?;
+
+            for item in items {
+                diesel::update(fs_items.find(&item.path))
+                    .set(path.eq(format!("{target}{}", &item.path[source.len()..])))
+                    .execute(conn)?;
+            };
+
+            diesel::QueryResult::Ok(())
+        }).unwrap();
+    }
+
+    /// deletes the records of an item and everything under it
+    pub fn delete_tree(conn: &mut SqliteConnection, path_: &str) {
+        diesel::delete(fs_items.filter(path.eq(path_).or(path.like(subtree_pattern(path_)).escape('\\'))))
+            .execute(conn)
+            .unwrap();
+    }
+}
+
+
+fn subtree_pattern(path_: &str) -> String {
+    let escaped = path_.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_");
+
+    format!("{escaped}/%")
+}
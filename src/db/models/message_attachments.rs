@@ -0,0 +1,98 @@
+use diesel::prelude::*;
+use super::gen_id;
+use super::super::schema::{self, message_attachments::dsl::*, messages};
+
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::message_attachments)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct MessageAttachment {
+    pub id: i32,
+    pub message_id: i32,
+    /// what the file is stored as in the attachment store
+    pub file_key: String,
+    pub name: String,
+    pub size: i64,
+    pub mime: Option<String>,
+}
+
+
+/// a file which is already in the attachment store, but is yet to be attached to a message
+pub struct NewMessageAttachment {
+    pub file_key: String,
+    pub name: String,
+    pub size: i64,
+    pub mime: Option<String>,
+}
+
+
+impl MessageAttachment {
+    pub(super) fn attach(conn: &mut SqliteConnection, message_id_: i32, attachments: &[NewMessageAttachment]) -> QueryResult<()> {
+        for attachment in attachments {
+            diesel::insert_into(message_attachments)
+                .values(&Self {
+                    id: gen_id(),
+                    message_id: message_id_,
+                    file_key: attachment.file_key.clone(),
+                    name: attachment.name.clone(),
+                    size: attachment.size,
+                    mime: attachment.mime.clone(),
+                })
+                .execute(conn)?;
+        };
+
+        Ok(())
+    }
+
+    /// returns the keys of the files, which are not needed anymore
+    pub(super) fn detach_all(conn: &mut SqliteConnection, message_id_: i32) -> QueryResult<Vec<String>> {
+        diesel::delete(message_attachments.filter(message_id.eq(message_id_)))
+            .returning(file_key)
+            .get_results(conn)
+    }
+
+    pub fn get(conn: &mut SqliteConnection, id_: i32) -> Option<Self> {
+        message_attachments
+            .find(id_)
+            .select(Self::as_select())
+            .get_result(conn)
+            .optional()
+            .unwrap()
+    }
+
+    pub fn get_all_of_messages(conn: &mut SqliteConnection, messages_ids: &[i32]) -> Vec<Self> {
+        message_attachments
+            .filter(message_id.eq_any(messages_ids))
+            .select(Self::as_select())
+            .get_results(conn)
+            .unwrap()
+    }
+
+    pub fn get_all_in_conversation(conn: &mut SqliteConnection, conversation_id: i32) -> Vec<Self> {
+        message_attachments
+            .inner_join(messages::table)
+            .filter(messages::conversation_id.eq(conversation_id))
+            .select(Self::as_select())
+            .get_results(conn)
+            .unwrap()
+    }
+
+    pub fn get_all_file_keys(conn: &mut SqliteConnection) -> Vec<String> {
+        message_attachments
+            .select(file_key)
+            .get_results(conn)
+            .unwrap()
+    }
+
+    /// how much of the storage the attachments sent by a user take up
+    pub fn get_total_size_sent_by(conn: &mut SqliteConnection, user_id: i32) -> u64 {
+        message_attachments
+            .inner_join(messages::table)
+            .filter(messages::sender_id.eq(user_id))
+            .select(size)
+            .get_results::<i64>(conn)
+            .unwrap()
+            .into_iter()
+            .sum::<i64>() as u64
+    }
+}
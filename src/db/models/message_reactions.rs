@@ -0,0 +1,110 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use crate::db;
+use super::super::schema::{self, message_reactions::dsl::*, messages};
+
+
+/// how long the short codes and the emoji sequences can be, in chars
+const MAX_SHORT_CODE_LENGTH: usize = 32;
+const MAX_EMOJI_LENGTH: usize = 16;
+
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::message_reactions)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct MessageReaction {
+    pub message_id: i32,
+    pub user_id: i32,
+    pub reaction: String,
+    pub creation_time: NaiveDateTime,
+}
+
+
+/// the users who reacted the same way, the earliest first
+pub struct ReactionSummary {
+    pub reaction: String,
+    pub users_ids: Vec<i32>,
+}
+
+
+impl MessageReaction {
+    /// either an emoji, or a short code like `:thumbs_up:`
+    pub fn is_valid(reaction_: &str) -> bool {
+        if let Some(code) = reaction_.strip_prefix(':').and_then(|r| r.strip_suffix(':')) {
+            return (1..=MAX_SHORT_CODE_LENGTH).contains(&code.len())
+                && code.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '+' | '-'));
+        };
+
+        // anything which isn't plain text is taken for an emoji, there is no point keeping up with the unicode's list of them
+        (1..=MAX_EMOJI_LENGTH).contains(&reaction_.chars().count())
+            && reaction_.chars().all(|c| !c.is_ascii() && !c.is_whitespace() && !c.is_control())
+    }
+
+    /// returns whether the reaction is new.
+    /// WARNING: EXPECTS THE REACTION TO BE VALID
+    pub fn add(conn: &mut SqliteConnection, message: &db::Message, user: &db::User, reaction_: &str) -> bool {
+        diesel::insert_or_ignore_into(message_reactions)
+            .values(&Self {
+                message_id: message.id,
+                user_id: user.id,
+                reaction: reaction_.to_string(),
+                creation_time: Utc::now().naive_utc(),
+            })
+            .execute(conn)
+            .unwrap() > 0
+    }
+
+    /// returns whether the user had reacted so at all
+    pub fn remove(conn: &mut SqliteConnection, message: &db::Message, user: &db::User, reaction_: &str) -> bool {
+        diesel::delete(message_reactions.find((message.id, user.id, reaction_)))
+            .execute(conn)
+            .unwrap() > 0
+    }
+
+    pub(super) fn remove_all_of_message(conn: &mut SqliteConnection, message_id_: i32) -> QueryResult<usize> {
+        diesel::delete(message_reactions.filter(message_id.eq(message_id_)))
+            .execute(conn)
+    }
+
+    pub(super) fn remove_all_by_user(conn: &mut SqliteConnection, user_id_: i32) {
+        diesel::delete(message_reactions.filter(user_id.eq(user_id_)))
+            .execute(conn)
+            .unwrap();
+    }
+
+    /// the oldest reactions first
+    pub fn get_all_of_messages(conn: &mut SqliteConnection, messages_ids: &[i32]) -> Vec<Self> {
+        message_reactions
+            .filter(message_id.eq_any(messages_ids))
+            .order_by(creation_time.asc())
+            .select(Self::as_select())
+            .get_results(conn)
+            .unwrap()
+    }
+
+    /// the oldest reactions first
+    pub fn get_all_in_conversation(conn: &mut SqliteConnection, conversation_id: i32) -> Vec<Self> {
+        message_reactions
+            .inner_join(messages::table)
+            .filter(messages::conversation_id.eq(conversation_id))
+            .order_by(creation_time.asc())
+            .select(Self::as_select())
+            .get_results(conn)
+            .unwrap()
+    }
+
+    /// groups the reactions of a single message, in the order they were first used.
+    /// WARNING: EXPECTS THE REACTIONS TO BE THE OLDEST FIRST
+    pub fn summarize(reactions: Vec<Self>) -> Vec<ReactionSummary> {
+        let mut summaries = Vec::<ReactionSummary>::new();
+
+        for r in reactions {
+            match summaries.iter_mut().find(|s| s.reaction == r.reaction) {
+                Some(summary) => summary.users_ids.push(r.user_id),
+                None => summaries.push(ReactionSummary { reaction: r.reaction, users_ids: vec![r.user_id] }),
+            };
+        };
+
+        summaries
+    }
+}
@@ -1,10 +1,16 @@
+use std::collections::HashMap;
 use chrono::{NaiveDateTime, Utc};
 use diesel::prelude::*;
+use diesel::sql_types::{BigInt, Integer, Text};
+use crate::content::{Body, ContentType};
 use crate::db;
 use super::gen_id;
 use super::super::schema::{
     self,
-    messages::dsl::{*, is_deleted},
+    messages::dsl::{*, id, is_deleted},
+    message_recipients,
+    message_revisions,
+    scheduled_recipients,
     users::{self, dsl::*}
 };
 
@@ -19,31 +25,264 @@ pub struct Message {
     pub body: Option<String>,
     pub replying_id: Option<i32>,
     pub sent_time: NaiveDateTime,
+    /// whether the message got purged, which happens once everyone has deleted it
+    pub is_deleted: bool,
+    pub conversation_id: Option<i32>,
+    pub edited_time: Option<NaiveDateTime>,
+    pub is_deleted_by_sender: bool,
+    pub content_type: String,
+    /// the body rendered as plain text
+    pub plain_body: Option<String>,
+    /// whether the message is yet to be delivered, at its sent time. until then only the sender has access to it
+    pub is_pending: bool,
+    pub expire_time: Option<NaiveDateTime>,
+}
+
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::message_recipients)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct MessageRecipient {
+    pub message_id: i32,
+    pub recipient_id: i32,
     pub is_read: Option<bool>,
     pub is_deleted: bool,
 }
 
 
+/// a body the message used to have, before it was edited
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::message_revisions)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct MessageRevision {
+    pub id: i32,
+    pub message_id: i32,
+    pub body: String,
+    /// when this body was written, rather than when it was replaced
+    pub creation_time: NaiveDateTime,
+    pub content_type: String,
+}
+
+
+impl MessageRevision {
+    pub fn get_content_type(&self) -> ContentType {
+        ContentType::from_str(&self.content_type).expect("the content type should be valid")
+    }
+}
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageFolder {
+    /// the received messages
+    Inbox,
+    Sent,
+}
+
+
+/// narrows down which messages are listed, everything is allowed by default
+#[derive(Default)]
+pub struct MessageFilter {
+    pub folder: Option<MessageFolder>,
+    /// only the messages exchanged with this user
+    pub correspondent_id: Option<i32>,
+    pub since: Option<NaiveDateTime>,
+    pub until: Option<NaiveDateTime>,
+    pub is_unread_only: bool,
+    /// only the messages coming after this (sent time, id) in the listing order, so that the pages wouldn't shift when messages are added or removed
+    pub after: Option<(NaiveDateTime, i32)>,
+}
+
+
+pub struct MessageSearchHit {
+    pub message: Message,
+    /// a html excerpt of the body, with the matched terms wrapped in `<mark>`
+    pub snippet: String,
+}
+
+
+#[derive(QueryableByName)]
+struct SearchMatch {
+    #[diesel(sql_type = Integer)]
+    message_id: i32,
+    #[diesel(sql_type = Text)]
+    snippet: String,
+}
+
+
+// the bodies can contain anything, so the matches are marked with chars which are escaped later
+const SNIPPET_MATCH_START: char = '\u{2}';
+const SNIPPET_MATCH_END: char = '\u{3}';
+
+
+/// by default the message is delivered right away, and kept until it's deleted
+#[derive(Default, Clone, Copy)]
+pub struct MessageSchedule {
+    /// a time which has already passed is the same as none
+    pub deliver_time: Option<NaiveDateTime>,
+    /// when the message gets purged
+    pub expire_time: Option<NaiveDateTime>,
+}
+
+
+pub enum MessageSendError {
+    /// holds the username of the recipient
+    RecipientDoesNotAccept(String),
+    ExpiresBeforeDelivery,
+}
+
+
+#[derive(Debug)]
 pub enum MessageInteractionError {
-    MessageIsDeleted
+    MessageIsDeleted,
+    NotARecipient,
 }
 
 
 impl Message {
-    pub fn send(conn: &mut SqliteConnection, sender: &db::User, receiver: &db::User, replying_to: Option<&Message>, contents: &str) -> Self {
-        diesel::insert_into(messages)
-            .values(&Message {
-                id: gen_id(),
-                sender_id: sender.id,
-                receiver_id: receiver.id,
-                body: Some(contents.to_string()),
-                replying_id: replying_to.map(|msg| msg.id),
-                sent_time: Utc::now().naive_local(),
-                is_read: Some(false),
-                is_deleted: false,
-            })
-            .get_result(conn)
-            .unwrap()
+    /// the first recipient is the primary one, which is what v1 knows as the receiver.
+    /// a reply continues the conversation of the message it replies to, otherwise a new conversation is started.
+    /// nothing is sent if any of the recipients does not accept messages from the sender.
+    /// the recipients of a scheduled message only join the conversation once it's delivered
+    pub fn send(
+        conn: &mut SqliteConnection,
+        sender: &db::User,
+        recipients: &[db::User],
+        replying_to: Option<&Message>,
+        contents: &Body,
+        attachments: &[db::NewMessageAttachment],
+        schedule: MessageSchedule,
+    ) -> Result<Self, MessageSendError> {
+        let receiver = recipients.first().expect("a message should have at least one recipient");
+        
+        let now = Utc::now().naive_local();
+        let deliver_time = schedule.deliver_time.filter(|t| *t > now);
+        
+        if schedule.expire_time.is_some_and(|t| t <= deliver_time.unwrap_or(now)) {
+            return Err(MessageSendError::ExpiresBeforeDelivery);
+        };
+        
+        // blocked senders get the same error as everyone else who is not accepted, so that the block would not be revealed
+        if let Some(refusing) = recipients.iter().find(|r| !r.accepts_messages_from(conn, sender)) {
+            return Err(MessageSendError::RecipientDoesNotAccept(refusing.get_username()));
+        };
+        
+        let recipients_ids = recipients.iter().map(|u| u.id).collect::<Vec<_>>();
+        
+        let message = conn.transaction(|conn| {
+            let participants = if deliver_time.is_some() { vec![sender.id] } else { [&[sender.id], recipients_ids.as_slice()].concat() };
+            let conversation_id_ = match replying_to.and_then(|msg| msg.conversation_id) {
+                Some(conversation_id_) => {
+                    db::Conversation::add_participants(conn, conversation_id_, &participants);
+                    conversation_id_
+                },
+                None => db::Conversation::create(conn, &participants).id,
+            };
+
+            let message = diesel::insert_into(messages)
+                .values(&Message {
+                    id: gen_id(),
+                    sender_id: sender.id,
+                    receiver_id: receiver.id,
+                    body: Some(contents.text.clone()),
+                    replying_id: replying_to.map(|msg| msg.id),
+                    sent_time: deliver_time.unwrap_or(now),
+                    is_deleted: false,
+                    conversation_id: Some(conversation_id_),
+                    edited_time: None,
+                    is_deleted_by_sender: false,
+                    content_type: contents.content_type.as_str().to_string(),
+                    plain_body: Some(contents.plain_text.clone()),
+                    is_pending: deliver_time.is_some(),
+                    expire_time: schedule.expire_time,
+                })
+                .get_result::<Self>(conn)?;
+            
+            for recipient_id_ in recipients_ids {
+                if message.is_pending {
+                    diesel::insert_or_ignore_into(scheduled_recipients::table)
+                        .values((
+                            scheduled_recipients::message_id.eq(message.id),
+                            scheduled_recipients::recipient_id.eq(recipient_id_),
+                        ))
+                        .execute(conn)?;
+                } else {
+                    diesel::insert_or_ignore_into(message_recipients::table)
+                        .values(&MessageRecipient { message_id: message.id, recipient_id: recipient_id_, is_read: Some(false), is_deleted: false })
+                        .execute(conn)?;
+                };
+            };
+            
+            db::MessageAttachment::attach(conn, message.id, attachments)?;
+            
+            diesel::QueryResult::Ok(message)
+        }).unwrap();
+        
+        Ok(message)
+    }
+    
+    /// delivers the pending messages which are due, returns how many there were
+    pub fn deliver_due(conn: &mut SqliteConnection) -> usize {
+        let mut due = messages
+            .filter(is_pending.eq(true))
+            .filter(sent_time.le(Utc::now().naive_local()))
+            .select(Self::as_select())
+            .get_results(conn)
+            .unwrap();
+        
+        for message in due.iter_mut() {
+            message.deliver(conn);
+        };
+        
+        due.len()
+    }
+    
+    /// the recipients who have blocked the sender, or such, in the meantime are left out
+    fn deliver(&mut self, conn: &mut SqliteConnection) {
+        let sender = db::User::get(conn, self.sender_id).expect("the sender should exist");
+        
+        let mut recipients = Vec::new();
+        for recipient_id_ in self.get_recipients(conn).into_iter().map(|r| r.recipient_id) {
+            match db::User::get(conn, recipient_id_) {
+                Some(recipient) if !recipient.is_deleted && recipient.accepts_messages_from(conn, &sender) => recipients.push(recipient.id),
+                _ => (),
+            };
+        };
+        
+        conn.transaction(|conn| {
+            db::Conversation::add_participants(conn, self.conversation_id.expect("every message belongs to a conversation"), &recipients);
+            
+            for recipient_id_ in &recipients {
+                diesel::insert_or_ignore_into(message_recipients::table)
+                    .values(&MessageRecipient { message_id: self.id, recipient_id: *recipient_id_, is_read: Some(false), is_deleted: false })
+                    .execute(conn)?;
+            };
+            
+            diesel::delete(scheduled_recipients::table.filter(scheduled_recipients::message_id.eq(self.id)))
+                .execute(conn)?;
+            
+            diesel::update(messages.find(self.id))
+                .set(is_pending.eq(false))
+                .execute(conn)
+        }).unwrap();
+        
+        self.is_pending = false;
+    }
+    
+    /// purges the messages which have expired, returns the keys of their attached files
+    pub fn expire_due(conn: &mut SqliteConnection) -> Vec<String> {
+        let expired = messages
+            .filter(is_deleted.eq(false))
+            .filter(expire_time.le(Utc::now().naive_local()))
+            .select(Self::as_select())
+            .get_results(conn)
+            .unwrap();
+        
+        let mut file_keys = Vec::new();
+        for mut message in expired {
+            file_keys.append(&mut conn.transaction(|conn| message.purge(conn)).unwrap());
+        };
+        
+        file_keys
     }
     
     pub fn get(conn: &mut SqliteConnection, id_: i32) -> Option<Self> {
@@ -54,93 +293,411 @@ impl Message {
             .unwrap()
     }
     
-    pub fn mark_as_read(&mut self, conn: &mut SqliteConnection) -> Result<(), MessageInteractionError> {
+    pub fn set_read_state(&mut self, conn: &mut SqliteConnection, user: &db::User, is_read_: bool) -> Result<(), MessageInteractionError> {
         if self.is_deleted {
             return Err(MessageInteractionError::MessageIsDeleted);
         };
         
-        diesel::update(messages.find(self.id))
-            .set(is_read.eq(Some(true)))
+        let updated = diesel::update(message_recipients::table.find((self.id, user.id)).filter(message_recipients::is_deleted.eq(false)))
+            .set(message_recipients::is_read.eq(Some(is_read_)))
             .execute(conn)
             .unwrap();
         
+        if updated == 0 {
+            return Err(MessageInteractionError::NotARecipient);
+        };
+        
+        Ok(())
+    }
+    
+    /// the messages the user is not a recipient of, or has deleted, are skipped.
+    /// returns how many messages were updated
+    pub fn set_read_state_of_many(conn: &mut SqliteConnection, user: &db::User, ids: &[i32], is_read_: bool) -> usize {
+        diesel::update(
+            message_recipients::table
+                .filter(message_recipients::recipient_id.eq(user.id))
+                .filter(message_recipients::message_id.eq_any(ids))
+                .filter(message_recipients::is_deleted.eq(false))
+                .filter(message_recipients::is_read.is_not_null())
+        )
+            .set(message_recipients::is_read.eq(Some(is_read_)))
+            .execute(conn)
+            .unwrap()
+    }
+    
+    pub fn count_unread_of(conn: &mut SqliteConnection, user: &db::User) -> i64 {
+        message_recipients::table
+            .filter(message_recipients::recipient_id.eq(user.id))
+            .filter(message_recipients::is_read.eq(Some(false)))
+            .filter(message_recipients::is_deleted.eq(false))
+            .count()
+            .get_result(conn)
+            .unwrap()
+    }
+    
+    pub fn get_content_type(&self) -> ContentType {
+        ContentType::from_str(&self.content_type).expect("the content type should be valid")
+    }
+    
+    /// the current body is kept as a revision
+    pub fn edit(&mut self, conn: &mut SqliteConnection, contents: &Body) -> Result<(), MessageInteractionError> {
+        let Some(old_body) = self.body.clone() else {
+            return Err(MessageInteractionError::MessageIsDeleted);
+        };
+        
+        let now = Utc::now().naive_local();
+        
+        conn.transaction(|conn| {
+            diesel::insert_into(message_revisions::table)
+                .values(&MessageRevision {
+                    id: gen_id(),
+                    message_id: self.id,
+                    body: old_body,
+                    creation_time: self.edited_time.unwrap_or(self.sent_time),
+                    content_type: self.content_type.clone(),
+                })
+                .execute(conn)?;
+            
+            diesel::update(messages.find(self.id))
+                .set((
+                    body.eq(Some(&contents.text)),
+                    content_type.eq(contents.content_type.as_str()),
+                    plain_body.eq(Some(&contents.plain_text)),
+                    edited_time.eq(Some(now)),
+                ))
+                .execute(conn)
+        }).unwrap();
+        
+        self.body = Some(contents.text.clone());
+        self.content_type = contents.content_type.as_str().to_string();
+        self.plain_body = Some(contents.plain_text.clone());
+        self.edited_time = Some(now);
+        
         Ok(())
     }
     
-    pub fn is_accessible_to(&self, user: &db::User) -> bool {
-        self.sender_id == user.id || self.receiver_id == user.id
+    /// the oldest revisions first
+    pub fn get_revisions(&self, conn: &mut SqliteConnection) -> Vec<MessageRevision> {
+        message_revisions::table
+            .filter(message_revisions::message_id.eq(self.id))
+            .order_by(message_revisions::creation_time.asc())
+            .select(MessageRevision::as_select())
+            .get_results(conn)
+            .unwrap()
+    }
+    
+    /// none if the message is deleted, or if the user is not one of its recipients
+    pub fn is_read_by(&self, conn: &mut SqliteConnection, user_id: i32) -> Option<bool> {
+        message_recipients::table
+            .find((self.id, user_id))
+            .select(message_recipients::is_read)
+            .get_result(conn)
+            .optional()
+            .unwrap()
+            .flatten()
+    }
+    
+    pub fn is_accessible_to(&self, conn: &mut SqliteConnection, user: &db::User) -> bool {
+        self.sender_id == user.id || diesel::select(diesel::dsl::exists(
+            message_recipients::table.find((self.id, user.id))
+        ))
+            .get_result(conn)
+            .unwrap()
+    }
+    
+    /// whether the user does not have their copy of the message anymore.
+    /// WARNING: EXPECTS THE MESSAGE TO BE ACCESSIBLE TO THE USER
+    pub fn is_deleted_for(&self, conn: &mut SqliteConnection, user: &db::User) -> bool {
+        if self.is_deleted {
+            return true;
+        };
+        
+        if self.sender_id == user.id && !self.is_deleted_by_sender {
+            return false;
+        };
+        
+        message_recipients::table
+            .find((self.id, user.id))
+            .select(message_recipients::is_deleted)
+            .get_result::<bool>(conn)
+            .optional()
+            .unwrap()
+            .unwrap_or(true)
+    }
+    
+    /// the pending messages have the scheduled recipients instead, without a read state
+    pub fn get_recipients(&self, conn: &mut SqliteConnection) -> Vec<MessageRecipient> {
+        if self.is_pending {
+            return scheduled_recipients::table
+                .filter(scheduled_recipients::message_id.eq(self.id))
+                .select(scheduled_recipients::recipient_id)
+                .get_results::<i32>(conn)
+                .unwrap()
+                .into_iter()
+                .map(|recipient_id_| MessageRecipient { message_id: self.id, recipient_id: recipient_id_, is_read: None, is_deleted: false })
+                .collect();
+        };
+        
+        message_recipients::table
+            .filter(message_recipients::message_id.eq(self.id))
+            .select(MessageRecipient::as_select())
+            .get_results(conn)
+            .unwrap()
     }
 
-    pub fn get_body_preview(&self, preview_length: usize) -> Result<&str, MessageInteractionError> {
-        Ok(&self.body.as_ref().ok_or(MessageInteractionError::MessageIsDeleted)?
-            [..preview_length.min(self.body.as_ref().expect("the body was already checked is deleted").len())])
+    /// the same as `get_recipients`, but for many messages at once
+    pub fn get_recipients_of_all(conn: &mut SqliteConnection, messages_: &[Self]) -> Vec<MessageRecipient> {
+        let (pending, delivered) = messages_.iter().partition::<Vec<_>, _>(|msg| msg.is_pending);
+        
+        let mut recipients = message_recipients::table
+            .filter(message_recipients::message_id.eq_any(delivered.iter().map(|msg| msg.id).collect::<Vec<_>>()))
+            .select(MessageRecipient::as_select())
+            .get_results(conn)
+            .unwrap();
+        
+        recipients.extend(scheduled_recipients::table
+            .filter(scheduled_recipients::message_id.eq_any(pending.iter().map(|msg| msg.id).collect::<Vec<_>>()))
+            .select((scheduled_recipients::message_id, scheduled_recipients::recipient_id))
+            .get_results::<(i32, i32)>(conn)
+            .unwrap()
+            .into_iter()
+            .map(|(message_id_, recipient_id_)| MessageRecipient { message_id: message_id_, recipient_id: recipient_id_, is_read: None, is_deleted: false }));
+        
+        recipients
+    }
+
+    /// the start of the plain text, so that it would never cut through any markup
+    pub fn get_body_preview(&self, preview_length: usize) -> Result<String, MessageInteractionError> {
+        Ok(self.plain_body.as_ref().ok_or(MessageInteractionError::MessageIsDeleted)?
+            .chars()
+            .take(preview_length)
+            .collect())
     }
     
     pub fn get_replying_msg(&self, conn: &mut SqliteConnection) -> Option<Self> {
         self.replying_id.map(|id_| Self::get(conn, id_).unwrap())
     }
     
-    pub fn get_all_not_deleted_made_by_user(conn: &mut SqliteConnection, user: &db::User) -> Vec<Self> {
-        messages
+    pub fn get_all_not_deleted_accessible_to_user(conn: &mut SqliteConnection, user: &db::User, filter: &MessageFilter, descending_order: bool, count: i64, offset: u64) -> Vec<Self> {
+        let is_kept_by_sender = sender_id.eq(user.id).and(is_deleted_by_sender.eq(false));
+        let kept_received_ids = message_recipients::table
             .filter(
-                sender_id.eq(user.id)
-                    .and(is_deleted.eq(false))
+                message_recipients::recipient_id.eq(user.id)
+                    .and(message_recipients::is_deleted.eq(false))
             )
+            .select(message_recipients::message_id);
+        
+        let mut stmt = messages
+            .filter(is_deleted.eq(false))
+            .into_boxed();
+        
+        stmt = match filter.folder {
+            None => stmt.filter(is_kept_by_sender.or(id.eq_any(kept_received_ids))),
+            Some(MessageFolder::Inbox) => stmt.filter(id.eq_any(kept_received_ids)),
+            Some(MessageFolder::Sent) => stmt.filter(is_kept_by_sender),
+        };
+        
+        if let Some(correspondent_id) = filter.correspondent_id {
+            stmt = stmt.filter(
+                sender_id.eq(correspondent_id)
+                    .or(sender_id.eq(user.id).and(id.eq_any(
+                        message_recipients::table
+                            .filter(message_recipients::recipient_id.eq(correspondent_id))
+                            .select(message_recipients::message_id)
+                    )))
+            );
+        };
+        
+        if let Some(since) = filter.since {
+            stmt = stmt.filter(sent_time.ge(since));
+        };
+        
+        if let Some(until) = filter.until {
+            stmt = stmt.filter(sent_time.lt(until));
+        };
+        
+        if filter.is_unread_only {
+            stmt = stmt.filter(id.eq_any(
+                message_recipients::table
+                    .filter(
+                        message_recipients::recipient_id.eq(user.id)
+                            .and(message_recipients::is_read.eq(Some(false)))
+                            .and(message_recipients::is_deleted.eq(false))
+                    )
+                    .select(message_recipients::message_id)
+            ));
+        };
+        
+        if let Some((after_time, after_id)) = filter.after {
+            stmt = if descending_order {
+                stmt.filter(sent_time.lt(after_time).or(sent_time.eq(after_time).and(id.lt(after_id))))
+            } else {
+                stmt.filter(sent_time.gt(after_time).or(sent_time.eq(after_time).and(id.gt(after_id))))
+            };
+        };
+        
+        // the id keeps the order of the messages sent at the same time stable
+        stmt = if descending_order {
+            stmt.order_by((sent_time.desc(), id.desc()))
+        } else {
+            stmt.order_by((sent_time.asc(), id.asc()))
+        };
+        
+        stmt
+            .offset(offset as i64)  // todo return err if it doesnt fit
+            .limit(count)
             .select(Self::as_select())
             .get_results(conn)
             .unwrap()
     }
-
-    pub fn get_all_not_deleted_accessible_to_user(conn: &mut SqliteConnection, user: &db::User, descending_order: bool, count: i64, offset: u64) -> Vec<Self> {
-        let base_stmt = messages
-            .filter(
-                sender_id.eq(user.id)
-                    .or(receiver_id.eq(user.id))
-                    .and(is_deleted.eq(false))
-            )
-            .offset(offset as i64)  // todo return err if it doesnt fit
-            .limit(count);
-
-
-        if descending_order {
-            base_stmt
-                .order_by(sent_time.desc())
-                .select(Self::as_select())
-                .get_results(conn)
-        } else {
-            base_stmt
-                .order_by(sent_time.asc())
-                .select(Self::as_select())
-                .get_results(conn)
-        }.unwrap()
+    
+    /// every term of the query has to be present in the body. if there are no terms, nothing is found.
+    /// only the messages the user has not deleted are searched
+    pub fn search(conn: &mut SqliteConnection, user: &db::User, query: &str, descending_order: bool, count: i64, offset: u64) -> Vec<MessageSearchHit> {
+        // each term is quoted, so that the fts5 query syntax would not apply
+        let fts_query = query.split_whitespace()
+            .map(|term| format!("\"{}\"", term.replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(" ");
+        
+        if fts_query.is_empty() {
+            return Vec::new();
+        };
+        
+        let matches = diesel::sql_query(format!(r#"
+            SELECT messages.id AS message_id, snippet(messages_fts, 0, ?2, ?3, '...', 16) AS snippet
+            FROM messages_fts
+            JOIN messages ON messages.id = messages_fts.rowid
+            WHERE messages_fts MATCH ?1
+                AND NOT messages.is_deleted
+                AND ((messages.sender_id = ?4 AND NOT messages.is_deleted_by_sender) OR EXISTS (
+                    SELECT 1 FROM message_recipients
+                    WHERE message_recipients.message_id = messages.id AND message_recipients.recipient_id = ?4
+                        AND NOT message_recipients.is_deleted
+                ))
+            ORDER BY messages.sent_time {}
+            LIMIT ?5 OFFSET ?6
+        "#, if descending_order { "DESC" } else { "ASC" }))
+            .bind::<Text, _>(fts_query)
+            .bind::<Text, _>(SNIPPET_MATCH_START.to_string())
+            .bind::<Text, _>(SNIPPET_MATCH_END.to_string())
+            .bind::<Integer, _>(user.id)
+            .bind::<BigInt, _>(count)
+            .bind::<BigInt, _>(offset as i64)  // todo return err if it doesnt fit
+            .load::<SearchMatch>(conn)
+            .unwrap();
+        
+        let mut found_messages = messages
+            .filter(id.eq_any(matches.iter().map(|m| m.message_id)))
+            .select(Self::as_select())
+            .get_results(conn)
+            .unwrap()
+            .into_iter()
+            .map(|m| (m.id, m))
+            .collect::<HashMap<_, _>>();
+        
+        matches.into_iter().map(|m| MessageSearchHit {
+            message: found_messages.remove(&m.message_id).expect("the ids were just selected"),
+            snippet: to_html_snippet(&m.snippet),
+        }).collect()
+    }
+    
+    /// the whole conversation at once, the oldest messages first
+    pub fn get_all_in_conversation(conn: &mut SqliteConnection, conversation_id_: i32) -> Vec<Self> {
+        messages
+            .filter(conversation_id.eq(conversation_id_))
+            .order_by(sent_time.asc())
+            .select(Self::as_select())
+            .get_results(conn)
+            .unwrap()
     }
     
+    /// the pending replies are left out
     pub fn get_all_not_deleted_replies(&self, conn: &mut SqliteConnection) -> Vec<Self> {
         messages
             .filter(replying_id.eq(self.id))
+            .filter(is_pending.eq(false))
             .get_results(conn)
             .unwrap()
     }
 
-    pub fn delete(&mut self, conn: &mut SqliteConnection) {
-        // xxx or should it return UserInteractionError::UserIsDeleted error?
-        if self.is_deleted {
-            return;
+    /// deletes the user's copy of the message, and purges the message once nobody has it anymore.
+    /// returns the keys of the attached files, which should be removed from the attachment store now.
+    /// WARNING: EXPECTS THE MESSAGE TO BE ACCESSIBLE TO THE USER
+    pub fn delete_for(&mut self, conn: &mut SqliteConnection, user: &db::User) -> Result<Vec<String>, MessageInteractionError> {
+        if self.is_deleted_for(conn, user) {
+            return Err(MessageInteractionError::MessageIsDeleted);
         };
         
+        let file_keys = conn.transaction(|conn| {
+            // there is no one else to keep a copy of it yet
+            if self.is_pending && self.sender_id == user.id {
+                return self.purge(conn);
+            };
+            
+            if self.sender_id == user.id {
+                diesel::update(messages.find(self.id))
+                    .set(is_deleted_by_sender.eq(true))
+                    .execute(conn)?;
+                
+                self.is_deleted_by_sender = true;
+            };
+            
+            // the sender can be one of the recipients as well
+            diesel::update(message_recipients::table.find((self.id, user.id)))
+                .set(message_recipients::is_deleted.eq(true))
+                .execute(conn)?;
+            
+            let is_kept_by_recipients = diesel::select(diesel::dsl::exists(
+                message_recipients::table
+                    .filter(message_recipients::message_id.eq(self.id))
+                    .filter(message_recipients::is_deleted.eq(false))
+            ))
+                .get_result::<bool>(conn)?;
+            
+            if self.is_deleted_by_sender && !is_kept_by_recipients {
+                self.purge(conn)
+            } else {
+                Ok(Vec::new())
+            }
+        }).unwrap();
+        
+        Ok(file_keys)
+    }
+    
+    /// returns the keys of the attached files
+    fn purge(&mut self, conn: &mut SqliteConnection) -> QueryResult<Vec<String>> {
         diesel::update(messages.find(self.id))
             .set((
                 body.eq(Option::<String>::None),
-                is_read.eq(Option::<bool>::None),
+                plain_body.eq(Option::<String>::None),
                 is_deleted.eq(true),
+                is_pending.eq(false),
             ))
-            .execute(conn)
-            .unwrap();
+            .execute(conn)?;
+        
+        diesel::delete(scheduled_recipients::table.filter(scheduled_recipients::message_id.eq(self.id)))
+            .execute(conn)?;
+        
+        diesel::update(message_recipients::table.filter(message_recipients::message_id.eq(self.id)))
+            .set(message_recipients::is_read.eq(Option::<bool>::None))
+            .execute(conn)?;
+        
+        // the old bodies are not any less private than the current one
+        diesel::delete(message_revisions::table.filter(message_revisions::message_id.eq(self.id)))
+            .execute(conn)?;
+        
+        db::MessageReaction::remove_all_of_message(conn, self.id)?;
+        
+        let file_keys = db::MessageAttachment::detach_all(conn, self.id)?;
         
         self.body = None;
-        self.is_read = None;
+        self.plain_body = None;
         
         self.is_deleted = true;
+        self.is_pending = false;
+        
+        Ok(file_keys)
     }
 
     pub fn get_sender(&self, conn: &mut SqliteConnection) -> db::User {
@@ -159,3 +716,35 @@ impl Message {
             .unwrap()
     }
 }
+
+
+impl MessageRecipient {
+    pub fn get_all_in_conversation(conn: &mut SqliteConnection, conversation_id_: i32) -> Vec<Self> {
+        message_recipients::table
+            .inner_join(messages)
+            .filter(conversation_id.eq(conversation_id_))
+            .select(Self::as_select())
+            .get_results(conn)
+            .unwrap()
+    }
+}
+
+
+fn to_html_snippet(snippet: &str) -> String {
+    let mut html = String::with_capacity(snippet.len());
+    
+    for c in snippet.chars() {
+        match c {
+            SNIPPET_MATCH_START => html.push_str("<mark>"),
+            SNIPPET_MATCH_END => html.push_str("</mark>"),
+            '&' => html.push_str("&amp;"),
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        };
+    };
+    
+    html
+}
@@ -1,8 +1,22 @@
 pub mod users;
 pub mod tokens;
 pub mod messages;
+pub mod fs_items;
+pub mod template_deliveries;
+pub mod conversations;
+pub mod user_groups;
+pub mod message_attachments;
+pub mod user_blocks;
+pub mod message_reactions;
+pub mod two_factor;
 
 
 fn gen_id() -> i32 {
     rand::random::<i32>().abs()
 }
+
+
+/// for the random secrets, like the tokens, which are only looked up by the hash
+fn hash_secret(value: &str) -> String {
+    hmac_sha512::Hash::hash(value).map(|b| format!("{b:0>2x}")).concat()
+}
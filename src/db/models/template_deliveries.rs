@@ -0,0 +1,39 @@
+use diesel::prelude::*;
+use super::super::schema::{self, template_deliveries::dsl::*};
+
+
+/// a template item which a user has been given, the path is relative to the template's root
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::template_deliveries)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct TemplateDelivery {
+    pub user_id: i32,
+    pub path: String,
+}
+
+
+impl TemplateDelivery {
+    pub fn get_all_paths_of_user(conn: &mut SqliteConnection, user_id_: i32) -> Vec<String> {
+        template_deliveries
+            .filter(user_id.eq(user_id_))
+            .select(path)
+            .get_results(conn)
+            .unwrap()
+    }
+
+    pub fn record(conn: &mut SqliteConnection, user_id_: i32, paths: &[String]) {
+        conn.transaction(|conn| {
+            for path_ in paths {
+                diesel::insert_or_ignore_into(template_deliveries)
+                    .values(&Self { user_id: user_id_, path: path_.clone() })
+                    .execute(conn)?;
+            };
+
+            diesel::QueryResult::Ok(())
+        }).unwrap();
+    }
+
+    pub(super) fn remove_all_of_user(conn: &mut SqliteConnection, user_id_: i32) {
+        diesel::delete(template_deliveries.filter(user_id.eq(user_id_))).execute(conn).unwrap();
+    }
+}
@@ -1,19 +1,80 @@
+use std::collections::HashMap;
 use std::ops::Add;
 use std::time::Duration;
 use chrono::{NaiveDateTime, TimeDelta, Utc};
 use diesel::prelude::*;
 use crate::db;
-use super::super::schema::{self, tokens::dsl::*, users::dsl::*};
+use super::super::schema::{self, tokens::dsl::*, users::dsl::*, refresh_tokens};
 
 
+/// how much of what the client told about itself is kept, in chars
+const MAX_LABEL_LENGTH: usize = 64;
+const MAX_USER_AGENT_LENGTH: usize = 256;
+
+
+/// a session. only the hashes of its access and refresh tokens are stored, the tokens themselves are given out once
 #[derive(Queryable, Selectable)]
 #[diesel(table_name = schema::tokens)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 pub struct Token {
-    pub value: String,
+    #[allow(dead_code)]
+    pub hashed_value: String,
     pub owner_id: i32,
     pub lifetime: Option<f32>,
-    pub creation_time: NaiveDateTime
+    pub creation_time: NaiveDateTime,
+    pub id: i32,
+    pub label: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub last_used_time: Option<NaiveDateTime>,
+    pub access_expiry_time: Option<NaiveDateTime>,
+    pub idle_timeout: Option<f32>,
+}
+
+
+/// where the token is being created from, all of it is optional
+#[derive(Default)]
+pub struct TokenClient {
+    /// the name the user gave to the device or the app
+    pub label: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+
+/// None is for no limit
+#[derive(Clone, Copy, Default)]
+pub struct TokenLifetimes {
+    /// the whole session at most, neither using nor refreshing it can extend that
+    pub session: Option<Duration>,
+    pub access: Option<Duration>,
+    /// how long the session can go unused
+    pub idle: Option<Duration>,
+}
+
+
+/// the token along with the values, which are never seen again once returned
+pub struct IssuedToken {
+    pub token: Token,
+    pub access_token: String,
+    pub refresh_token: String,
+}
+
+
+/// what the password gets one
+pub enum TokenAuth {
+    Issued(IssuedToken),
+    /// the value of the login challenge, which has to be completed with a code from the second factor
+    SecondFactorRequired(String),
+}
+
+
+#[derive(Debug)]
+pub enum TokenRefreshError {
+    NotFound,
+    /// the session is revoked then, as either the token or the session was stolen
+    Reused,
+    Expired,
 }
 
 
@@ -21,46 +82,116 @@ pub struct Token {
 #[diesel(table_name = schema::tokens)]
 #[diesel(check_for_backend(diesel::sqlite::Sqlite))]
 struct NewToken {
-    pub value: String,
+    pub hashed_value: String,
     pub owner_id: i32,
     pub lifetime: Option<f32>,
-    pub creation_time: NaiveDateTime
+    pub creation_time: NaiveDateTime,
+    pub id: i32,
+    pub label: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub access_expiry_time: Option<NaiveDateTime>,
+    pub idle_timeout: Option<f32>,
+}
+
+
+#[derive(Insertable)]
+#[diesel(table_name = schema::refresh_tokens)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+struct NewRefreshToken {
+    pub hashed_value: String,
+    pub token_id: i32,
+    pub creation_time: NaiveDateTime,
 }
 
 
 impl Token {
-    pub fn get(conn: &mut SqliteConnection, v: &str) -> Option<Self> {
+    fn gen_value() -> String {
+        uuid::Uuid::new_v4().to_string()
+    }
+
+    fn get_access_expiry_time(now: NaiveDateTime, access_lifetime: Option<Duration>) -> Option<NaiveDateTime> {
+        access_lifetime.map(|l| now.add(TimeDelta::from_std(l).unwrap()))
+    }
+
+    pub fn get(conn: &mut SqliteConnection, access_token: &str) -> Option<Self> {
         tokens
-            .find(v)
+            .find(super::hash_secret(access_token))
             .select(Self::as_select())
             .first(conn)
             .optional()
             .unwrap()
     }
-    
+
+    /// by the id, which unlike the value can be shown around
+    pub fn get_of_owner(conn: &mut SqliteConnection, owner: &db::User, id_: i32) -> Option<Self> {
+        tokens
+            .filter(schema::tokens::id.eq(id_).and(owner_id.eq(owner.id)))
+            .select(Self::as_select())
+            .first(conn)
+            .optional()
+            .unwrap()
+    }
+
+    /// the newest first
     pub fn get_all_by_owner(conn: &mut SqliteConnection, owner: &db::User) -> Vec<Self> {
         tokens
             .filter(owner_id.eq(owner.id))
+            .order_by(schema::tokens::creation_time.desc())
+            .select(Self::as_select())
             .get_results(conn)
             .unwrap()
     }
-    
-    pub fn new(conn: &mut SqliteConnection, db::User { id: owner_id_, .. }: &db::User, lifetime_: Option<Duration>) -> Self {
-        diesel::insert_into(tokens)
-            .values(&NewToken {
-                value: uuid::Uuid::new_v4().to_string(),
-                lifetime: lifetime_.map(|d| d.as_secs_f32()),
-                owner_id: *owner_id_,
-                creation_time: Utc::now().naive_local(),
-            })
+
+    /// the ones which can expire at all, by the id, for going over all of them
+    pub fn get_page_of_expiring(conn: &mut SqliteConnection, after_id: i32, count: i64) -> Vec<Self> {
+        tokens
+            .filter(schema::tokens::id.gt(after_id))
+            .filter(lifetime.is_not_null().or(idle_timeout.is_not_null()))
+            .order_by(schema::tokens::id.asc())
+            .limit(count)
+            .select(Self::as_select())
+            .get_results(conn)
+            .unwrap()
+    }
+
+    pub fn count(conn: &mut SqliteConnection) -> i64 {
+        tokens
+            .count()
             .get_result(conn)
             .unwrap()
-            
-    } 
-    
-    pub fn auth(conn: &mut SqliteConnection, username_: &str, password: &str, lifetime_: Option<Duration>) -> Option<Self> {
+    }
+
+    pub fn issue(conn: &mut SqliteConnection, db::User { id: owner_id_, .. }: &db::User, lifetimes: TokenLifetimes, client: TokenClient) -> IssuedToken {
+        let truncate = |s: String, max_length: usize| s.chars().take(max_length).collect::<String>();
+        let now = Utc::now().naive_local();
+        let access_token = Self::gen_value();
+
+        conn.transaction(|conn| {
+            let token = diesel::insert_into(tokens)
+                .values(&NewToken {
+                    hashed_value: super::hash_secret(&access_token),
+                    lifetime: lifetimes.session.map(|d| d.as_secs_f32()),
+                    owner_id: *owner_id_,
+                    creation_time: now,
+                    id: super::gen_id(),
+                    label: client.label.map(|l| truncate(l.trim().to_string(), MAX_LABEL_LENGTH)).filter(|l| !l.is_empty()),
+                    ip: client.ip,
+                    user_agent: client.user_agent.map(|ua| truncate(ua, MAX_USER_AGENT_LENGTH)),
+                    access_expiry_time: Self::get_access_expiry_time(now, lifetimes.access),
+                    idle_timeout: lifetimes.idle.map(|d| d.as_secs_f32()),
+                })
+                .get_result::<Self>(conn)?;
+
+            let refresh_token = token.add_refresh_token(conn, now)?;
+
+            diesel::QueryResult::Ok(IssuedToken { token, access_token, refresh_token })
+        }).unwrap()
+    }
+
+    pub fn auth(conn: &mut SqliteConnection, username_: &str, password: &str, lifetimes: TokenLifetimes, client: TokenClient) -> Option<TokenAuth> {
         let hashed_password_ = db::User::hash_password(password);
-        
+
         let user = users
             .filter(username.eq(username_)
                 .and(hashed_password.eq(hashed_password_)))
@@ -68,8 +199,91 @@ impl Token {
             .get_result(conn)
             .optional()
             .unwrap()?;
-        
-        Some(Self::new(conn, &user, lifetime_))
+
+        if db::TwoFactor::is_enabled_for(conn, &user) {
+            return Some(TokenAuth::SecondFactorRequired(db::LoginChallenge::create(conn, &user, client.label)));
+        };
+
+        Some(TokenAuth::Issued(Self::issue(conn, &user, lifetimes, client)))
+    }
+
+    /// the second step of the login. the session gets the label given in the first one
+    pub fn auth_second_factor(conn: &mut SqliteConnection, challenge: &str, code: &str, lifetimes: TokenLifetimes, client: TokenClient) -> Result<IssuedToken, db::LoginChallengeError> {
+        let (challenge, user) = db::LoginChallenge::complete(conn, challenge, code)?;
+
+        Ok(Self::issue(conn, &user, lifetimes, TokenClient { label: challenge.label, ..client }))
+    }
+
+    fn add_refresh_token(&self, conn: &mut SqliteConnection, now: NaiveDateTime) -> QueryResult<String> {
+        let refresh_token = Self::gen_value();
+
+        diesel::insert_into(refresh_tokens::table)
+            .values(&NewRefreshToken {
+                hashed_value: super::hash_secret(&refresh_token),
+                token_id: self.id,
+                creation_time: now,
+            })
+            .execute(conn)?;
+
+        Ok(refresh_token)
+    }
+
+    /// gives out a new access token and a new refresh token in place of the used one.
+    /// the session keeps its expiry time
+    pub fn refresh(conn: &mut SqliteConnection, refresh_token: &str, access_lifetime: Option<Duration>) -> Result<IssuedToken, TokenRefreshError> {
+        let hashed_refresh_token = super::hash_secret(refresh_token);
+
+        let (token_id, is_used) = refresh_tokens::table
+            .find(&hashed_refresh_token)
+            .select((refresh_tokens::token_id, refresh_tokens::is_used))
+            .get_result::<(i32, bool)>(conn)
+            .optional()
+            .unwrap()
+            .ok_or(TokenRefreshError::NotFound)?;
+
+        // the session could have been revoked just now
+        let mut token = tokens
+            .filter(schema::tokens::id.eq(token_id))
+            .select(Self::as_select())
+            .get_result(conn)
+            .optional()
+            .unwrap()
+            .ok_or(TokenRefreshError::NotFound)?;
+
+        // checking the flag again while setting it, in case the same token is being used at this very moment
+        let is_marked = !is_used && diesel::update(refresh_tokens::table.find(&hashed_refresh_token).filter(refresh_tokens::is_used.eq(false)))
+            .set(refresh_tokens::is_used.eq(true))
+            .execute(conn)
+            .unwrap() > 0;
+
+        if !is_marked {
+            log::warn!("a refresh token of the session {token_id} was reused, the session is revoked");
+            token.delete(conn);
+            return Err(TokenRefreshError::Reused);
+        };
+
+        if token.is_expired() {
+            token.delete(conn);
+            return Err(TokenRefreshError::Expired);
+        };
+
+        let now = Utc::now().naive_local();
+        let access_token = Self::gen_value();
+
+        let refresh_token = conn.transaction(|conn| {
+            token = diesel::update(tokens.filter(schema::tokens::id.eq(token.id)))
+                .set((
+                    hashed_value.eq(super::hash_secret(&access_token)),
+                    access_expiry_time.eq(Self::get_access_expiry_time(now, access_lifetime)),
+                    // refreshing is using it as well
+                    last_used_time.eq(now),
+                ))
+                .get_result::<Self>(conn)?;
+
+            token.add_refresh_token(conn, now)
+        }).unwrap();
+
+        Ok(IssuedToken { token, access_token, refresh_token })
     }
 
     pub fn get_owner(&self, conn: &mut SqliteConnection) -> db::User {
@@ -80,21 +294,59 @@ impl Token {
             .unwrap()
     }
 
-    pub fn is_expired(&self) -> bool {
-        if let Some(lifetime_) = self.lifetime {
-            if self.creation_time.add(TimeDelta::from_std(Duration::from_secs_f32(lifetime_)).unwrap()) < Utc::now().naive_local() {
-                return true;
-            };
-        };
+    /// the earlier of the end of its lifetime and of its idle timeout
+    pub fn get_expiry_time(&self) -> Option<NaiveDateTime> {
+        let after = |time: NaiveDateTime, seconds: f32| time.add(TimeDelta::from_std(Duration::from_secs_f32(seconds)).unwrap());
+        
+        let lifetime_end = self.lifetime.map(|lifetime_| after(self.creation_time, lifetime_));
+        let idle_end = self.idle_timeout.map(|timeout| after(self.last_used_time.unwrap_or(self.creation_time), timeout));
         
-        false
+        match (lifetime_end, idle_end) {
+            (Some(l), Some(i)) => Some(l.min(i)),
+            (l, i) => l.or(i),
+        }
     }
-    
-    pub fn is_valid(&self) -> bool {
-        !self.is_expired()
+
+    /// whether the whole session is over
+    pub fn is_expired(&self) -> bool {
+        self.get_expiry_time().is_some_and(|t| t < Utc::now().naive_local())
     }
-    
+
+    /// the session can still go on after a refresh
+    pub fn is_access_expired(&self) -> bool {
+        self.access_expiry_time.is_some_and(|t| t < Utc::now().naive_local())
+    }
+
+    /// saves when each of the tokens was last used, by their ids, all at once
+    pub fn set_last_used_times(conn: &mut SqliteConnection, times: &HashMap<i32, NaiveDateTime>) {
+        conn.transaction(|conn| {
+            for (id_, time) in times {
+                // the tokens deleted in the meantime are just not found, and the refreshed ones can have a later time already
+                diesel::update(tokens.filter(schema::tokens::id.eq(id_).and(last_used_time.is_null().or(last_used_time.lt(time)))))
+                    .set(last_used_time.eq(time))
+                    .execute(conn)?;
+            };
+
+            diesel::QueryResult::Ok(())
+        }).unwrap()
+    }
+
+    /// the refresh tokens go along with it
     pub fn delete(self, conn: &mut SqliteConnection) {
-        diesel::delete(tokens.find(&self.value)).execute(conn).unwrap();
+        diesel::delete(tokens.filter(schema::tokens::id.eq(self.id))).execute(conn).unwrap();
+    }
+
+    /// returns how many were there to delete
+    pub fn delete_all(conn: &mut SqliteConnection, ids: &[i32]) -> usize {
+        diesel::delete(tokens.filter(schema::tokens::id.eq_any(ids)))
+            .execute(conn)
+            .unwrap()
+    }
+
+    /// logs the owner off everywhere else, returns how many tokens were deleted
+    pub fn delete_all_others_of_owner(&self, conn: &mut SqliteConnection) -> usize {
+        diesel::delete(tokens.filter(owner_id.eq(self.owner_id).and(schema::tokens::id.ne(self.id))))
+            .execute(conn)
+            .unwrap()
     }
 }
@@ -0,0 +1,312 @@
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use diesel::prelude::*;
+use crate::{db, totp};
+use super::super::schema::{self, two_factor::dsl::*, recovery_codes, login_challenges};
+
+
+/// how many recovery codes the user gets at once, and how long they are
+const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_LENGTH: usize = 10;
+
+/// how long the second step of the login can be done for, and how many codes can be tried in it
+const CHALLENGE_LIFETIME: TimeDelta = TimeDelta::minutes(5);
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+/// how many invalid codes in a row lock the second factor, and for how long.
+/// a new login gives new attempts, so these are counted over all of them
+const MAX_FAILED_ATTEMPTS: i32 = 10;
+const LOCKOUT_DURATION: TimeDelta = TimeDelta::minutes(15);
+
+
+/// the totp of the user, which is only used for the logins once it's enabled
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::two_factor)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct TwoFactor {
+    pub user_id: i32,
+    /// base32 encoded
+    pub secret: String,
+    pub is_enabled: bool,
+    pub last_used_step: Option<i64>,
+    pub creation_time: NaiveDateTime,
+    pub failed_attempts: i32,
+    pub locked_until: Option<NaiveDateTime>,
+}
+
+
+#[derive(Debug)]
+pub enum TwoFactorError {
+    AlreadyEnabled,
+    NotEnrolled,
+    NotEnabled,
+    InvalidCode,
+    /// too many invalid codes were given
+    Locked,
+}
+
+
+/// a login which is waiting for the second factor
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::login_challenges)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct LoginChallenge {
+    pub hashed_value: String,
+    pub user_id: i32,
+    /// the label of the session-to-be
+    pub label: Option<String>,
+    pub attempts: i32,
+    pub creation_time: NaiveDateTime,
+}
+
+
+#[derive(Debug)]
+pub enum LoginChallengeError {
+    /// either it never existed, it has expired or it ran out of attempts
+    NotFound,
+    InvalidCode,
+    Locked,
+}
+
+
+impl TwoFactor {
+    pub fn get(conn: &mut SqliteConnection, user: &db::User) -> Option<Self> {
+        two_factor
+            .find(user.id)
+            .select(Self::as_select())
+            .get_result(conn)
+            .optional()
+            .unwrap()
+    }
+
+    pub fn is_enabled_for(conn: &mut SqliteConnection, user: &db::User) -> bool {
+        Self::get(conn, user).is_some_and(|tf| tf.is_enabled)
+    }
+
+    /// gives the user a new secret, which has to be confirmed with a code to be enabled.
+    /// an earlier unconfirmed one is replaced
+    pub fn enroll(conn: &mut SqliteConnection, user: &db::User) -> Result<Self, TwoFactorError> {
+        if Self::is_enabled_for(conn, user) {
+            return Err(TwoFactorError::AlreadyEnabled);
+        };
+
+        Ok(diesel::replace_into(two_factor)
+            .values(&Self {
+                user_id: user.id,
+                secret: totp::encode_base32(&totp::generate_secret()),
+                is_enabled: false,
+                last_used_step: None,
+                creation_time: Utc::now().naive_utc(),
+                failed_attempts: 0,
+                locked_until: None,
+            })
+            .get_result(conn)
+            .unwrap())
+    }
+
+    pub fn get_uri(&self, issuer: &str, account: &str) -> String {
+        totp::get_uri(&totp::decode_base32(&self.secret).expect("the secret should be valid base32"), issuer, account)
+    }
+
+    /// returns the recovery codes, which are never seen again
+    pub fn enable(conn: &mut SqliteConnection, user: &db::User, code: &str) -> Result<Vec<String>, TwoFactorError> {
+        let tf = Self::get(conn, user).ok_or(TwoFactorError::NotEnrolled)?;
+        if tf.is_enabled {
+            return Err(TwoFactorError::AlreadyEnabled);
+        };
+
+        // the recovery codes don't exist yet, it has to be the authenticator
+        if !tf.use_code(conn, code) {
+            return Err(TwoFactorError::InvalidCode);
+        };
+
+        Ok(conn.transaction(|conn| {
+            diesel::update(two_factor.find(user.id))
+                .set(is_enabled.eq(true))
+                .execute(conn)?;
+
+            Self::replace_recovery_codes(conn, user)
+        }).unwrap())
+    }
+
+    /// WARNING: EXPECTS THE CODE TO BE CHECKED ALREADY
+    fn replace_recovery_codes(conn: &mut SqliteConnection, user: &db::User) -> QueryResult<Vec<String>> {
+        diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user.id)))
+            .execute(conn)?;
+
+        let codes = (0..RECOVERY_CODE_COUNT)
+            .map(|_| {
+                let code = totp::encode_base32(&totp::generate_secret())[..RECOVERY_CODE_LENGTH].to_lowercase();
+                format!("{}-{}", &code[..RECOVERY_CODE_LENGTH / 2], &code[RECOVERY_CODE_LENGTH / 2..])
+            })
+            .collect::<Vec<_>>();
+
+        for code in &codes {
+            diesel::insert_into(recovery_codes::table)
+                .values((recovery_codes::user_id.eq(user.id), recovery_codes::hashed_code.eq(Self::hash_recovery_code(code))))
+                .execute(conn)?;
+        };
+
+        Ok(codes)
+    }
+
+    fn hash_recovery_code(code: &str) -> String {
+        // they are meant to be typed in, so the case and the dashes don't matter
+        super::hash_secret(&code.chars().filter(|c| c.is_ascii_alphanumeric()).collect::<String>().to_lowercase())
+    }
+
+    /// checks the code from the authenticator, which can't be used again then
+    fn use_code(&self, conn: &mut SqliteConnection, code: &str) -> bool {
+        let secret_ = totp::decode_base32(&self.secret).expect("the secret should be valid base32");
+        let Some(step) = totp::verify(&secret_, code, self.last_used_step) else {
+            return false;
+        };
+
+        // the same code could be being used at this very moment
+        diesel::update(two_factor.find(self.user_id).filter(last_used_step.is_null().or(last_used_step.lt(step))))
+            .set(last_used_step.eq(step))
+            .execute(conn)
+            .unwrap() > 0
+    }
+
+    fn use_recovery_code(conn: &mut SqliteConnection, user: &db::User, code: &str) -> bool {
+        diesel::delete(recovery_codes::table.find((user.id, Self::hash_recovery_code(code))))
+            .execute(conn)
+            .unwrap() > 0
+    }
+
+    /// either a code from the authenticator, or one of the recovery codes, which is used up then
+    pub fn verify(conn: &mut SqliteConnection, user: &db::User, code: &str) -> Result<(), TwoFactorError> {
+        let tf = Self::get(conn, user).filter(|tf| tf.is_enabled).ok_or(TwoFactorError::NotEnabled)?;
+        let now = Utc::now().naive_utc();
+
+        // the attempt is counted before the code is checked, so that the guesses made in parallel couldn't all get through
+        let failed = diesel::update(two_factor
+            .find(user.id)
+            .filter(failed_attempts.lt(MAX_FAILED_ATTEMPTS))
+            .filter(locked_until.is_null().or(locked_until.le(now))))
+            .set(failed_attempts.eq(failed_attempts + 1))
+            .returning(failed_attempts)
+            .get_result::<i32>(conn)
+            .optional()
+            .unwrap()
+            .ok_or(TwoFactorError::Locked)?;
+
+        if tf.use_code(conn, code) || Self::use_recovery_code(conn, user, code) {
+            diesel::update(two_factor.find(user.id))
+                .set((failed_attempts.eq(0), locked_until.eq(Option::<NaiveDateTime>::None)))
+                .execute(conn)
+                .unwrap();
+
+            return Ok(());
+        };
+
+        if failed >= MAX_FAILED_ATTEMPTS {
+            diesel::update(two_factor.find(user.id))
+                .set((failed_attempts.eq(0), locked_until.eq(now + LOCKOUT_DURATION)))
+                .execute(conn)
+                .unwrap();
+        };
+
+        Err(TwoFactorError::InvalidCode)
+    }
+
+    pub fn disable(conn: &mut SqliteConnection, user: &db::User, code: &str) -> Result<(), TwoFactorError> {
+        Self::verify(conn, user, code)?;
+
+        Self::remove_all_of_user(conn, user.id);
+
+        Ok(())
+    }
+
+    /// the old ones stop working
+    pub fn regenerate_recovery_codes(conn: &mut SqliteConnection, user: &db::User, code: &str) -> Result<Vec<String>, TwoFactorError> {
+        Self::verify(conn, user, code)?;
+
+        Ok(Self::replace_recovery_codes(conn, user).unwrap())
+    }
+
+    pub fn count_recovery_codes(conn: &mut SqliteConnection, user: &db::User) -> i64 {
+        recovery_codes::table
+            .filter(recovery_codes::user_id.eq(user.id))
+            .count()
+            .get_result(conn)
+            .unwrap()
+    }
+
+    pub(super) fn remove_all_of_user(conn: &mut SqliteConnection, user_id_: i32) {
+        diesel::delete(two_factor.find(user_id_)).execute(conn).unwrap();
+        diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user_id_))).execute(conn).unwrap();
+        diesel::delete(login_challenges::table.filter(login_challenges::user_id.eq(user_id_))).execute(conn).unwrap();
+    }
+}
+
+
+impl LoginChallenge {
+    /// returns the value to pass back along with the code
+    pub(super) fn create(conn: &mut SqliteConnection, user: &db::User, label: Option<String>) -> String {
+        let value = uuid::Uuid::new_v4().to_string();
+
+        diesel::insert_into(login_challenges::table)
+            .values(&Self {
+                hashed_value: super::hash_secret(&value),
+                user_id: user.id,
+                label,
+                attempts: 0,
+                creation_time: Utc::now().naive_utc(),
+            })
+            .execute(conn)
+            .unwrap();
+
+        value
+    }
+
+    /// the challenge is used up on success, or once it runs out of attempts
+    pub(super) fn complete(conn: &mut SqliteConnection, value: &str, code: &str) -> Result<(Self, db::User), LoginChallengeError> {
+        // nothing else can write in between, so the attempts of the challenge and of the user can't be raced past
+        conn.immediate_transaction(|conn| QueryResult::Ok(Self::attempt(conn, value, code))).unwrap()
+    }
+
+    /// WARNING: EXPECTS TO BE RUN IN A TRANSACTION
+    fn attempt(conn: &mut SqliteConnection, value: &str, code: &str) -> Result<(Self, db::User), LoginChallengeError> {
+        // the attempt is taken before the code is checked
+        let challenge = diesel::update(login_challenges::table
+            .find(super::hash_secret(value))
+            .filter(login_challenges::attempts.lt(MAX_CHALLENGE_ATTEMPTS))
+            .filter(login_challenges::creation_time.gt(Utc::now().naive_utc() - CHALLENGE_LIFETIME)))
+            .set(login_challenges::attempts.eq(login_challenges::attempts + 1))
+            .returning(Self::as_returning())
+            .get_result(conn)
+            .optional()
+            .unwrap()
+            .ok_or(LoginChallengeError::NotFound)?;
+
+        let user = db::User::get(conn, challenge.user_id).expect("the challenges are deleted along with the users");
+
+        match TwoFactor::verify(conn, &user, code) {
+            // the 2fa could have been disabled in the meantime, and then the password was enough
+            Ok(()) | Err(TwoFactorError::NotEnabled) => {
+                challenge.delete(conn);
+                Ok((challenge, user))
+            },
+            Err(TwoFactorError::Locked) => Err(LoginChallengeError::Locked),
+            Err(_) => {
+                if challenge.attempts >= MAX_CHALLENGE_ATTEMPTS {
+                    challenge.delete(conn);
+                };
+
+                Err(LoginChallengeError::InvalidCode)
+            },
+        }
+    }
+
+    fn delete(&self, conn: &mut SqliteConnection) {
+        diesel::delete(login_challenges::table.find(&self.hashed_value)).execute(conn).unwrap();
+    }
+
+    /// returns how many were deleted
+    pub fn delete_expired(conn: &mut SqliteConnection) -> usize {
+        diesel::delete(login_challenges::table.filter(login_challenges::creation_time.lt(Utc::now().naive_utc() - CHALLENGE_LIFETIME)))
+            .execute(conn)
+            .unwrap()
+    }
+}
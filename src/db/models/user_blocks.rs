@@ -0,0 +1,82 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::dsl::{count_distinct, count_star};
+use diesel::prelude::*;
+use crate::db;
+use super::super::schema::{self, user_blocks::dsl::*, users};
+
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::user_blocks)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct UserBlock {
+    pub blocker_id: i32,
+    pub blocked_id: i32,
+    pub creation_time: NaiveDateTime,
+}
+
+
+pub struct BlockStats {
+    pub total: i64,
+    /// how many users have blocked someone
+    pub blockers: i64,
+    /// how many users were blocked by someone
+    pub blocked: i64,
+    /// the users blocked by the most others, along with by how many
+    pub most_blocked: Vec<(db::User, i64)>,
+}
+
+
+impl UserBlock {
+    /// blocking someone again is left as is
+    pub fn block(conn: &mut SqliteConnection, blocker: &db::User, blocked: &db::User) {
+        diesel::insert_or_ignore_into(user_blocks)
+            .values(&Self {
+                blocker_id: blocker.id,
+                blocked_id: blocked.id,
+                creation_time: Utc::now().naive_utc(),
+            })
+            .execute(conn)
+            .unwrap();
+    }
+    
+    /// returns whether the user was blocked at all
+    pub fn unblock(conn: &mut SqliteConnection, blocker: &db::User, blocked: &db::User) -> bool {
+        diesel::delete(user_blocks.find((blocker.id, blocked.id)))
+            .execute(conn)
+            .unwrap() > 0
+    }
+    
+    pub fn exists(conn: &mut SqliteConnection, blocker_id_: i32, blocked_id_: i32) -> bool {
+        diesel::select(diesel::dsl::exists(user_blocks.find((blocker_id_, blocked_id_))))
+            .get_result(conn)
+            .unwrap()
+    }
+    
+    pub fn get_all_blocked_by(conn: &mut SqliteConnection, blocker: &db::User) -> Vec<db::User> {
+        user_blocks
+            .filter(blocker_id.eq(blocker.id))
+            .inner_join(users::table.on(users::id.eq(blocked_id)))
+            .order_by(creation_time.asc())
+            .select(db::User::as_select())
+            .get_results(conn)
+            .unwrap()
+    }
+    
+    pub fn get_stats(conn: &mut SqliteConnection, top_count: i64) -> BlockStats {
+        let (total, blockers, blocked) = user_blocks
+            .select((count_star(), count_distinct(blocker_id), count_distinct(blocked_id)))
+            .get_result::<(i64, i64, i64)>(conn)
+            .unwrap();
+        
+        let most_blocked = user_blocks
+            .inner_join(users::table.on(users::id.eq(blocked_id)))
+            .group_by(users::id)
+            .select((db::User::as_select(), count_star()))
+            .order_by(count_star().desc())
+            .limit(top_count)
+            .get_results::<(db::User, i64)>(conn)
+            .unwrap();
+        
+        BlockStats { total, blockers, blocked, most_blocked }
+    }
+}
@@ -0,0 +1,118 @@
+use chrono::{NaiveDateTime, Utc};
+use diesel::prelude::*;
+use diesel::result::DatabaseErrorKind;
+use crate::db;
+use super::gen_id;
+use super::super::schema::{self, user_groups::dsl::*, user_group_members, users};
+
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = schema::user_groups)]
+#[diesel(check_for_backend(diesel::sqlite::Sqlite))]
+pub struct UserGroup {
+    pub id: i32,
+    pub name: String,
+    pub owner_id: i32,
+    pub creation_time: NaiveDateTime,
+}
+
+
+pub enum UserGroupCreationError {
+    SuchNameIsAlreadyUsed,
+}
+
+
+impl UserGroup {
+    pub fn create(conn: &mut SqliteConnection, owner: &db::User, name_: &str) -> Result<Self, UserGroupCreationError> {
+        let res = diesel::insert_into(user_groups)
+            .values(&Self {
+                id: gen_id(),
+                name: name_.to_string(),
+                owner_id: owner.id,
+                creation_time: Utc::now().naive_utc(),
+            })
+            .get_result(conn);
+
+        match res {
+            Ok(group) => Ok(group),
+            Err(diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _)) => Err(UserGroupCreationError::SuchNameIsAlreadyUsed),
+            err @ Err(_) => { err.unwrap(); unreachable!("'err' is an Err variant, so unwrap must fail") }
+        }
+    }
+
+    pub fn get_by_name(conn: &mut SqliteConnection, name_: &str) -> Option<Self> {
+        user_groups
+            .filter(name.eq(name_))
+            .select(Self::as_select())
+            .get_result(conn)
+            .optional()
+            .unwrap()
+    }
+
+    /// the groups a user either owns or is a member of
+    pub fn get_all_of_user(conn: &mut SqliteConnection, user: &db::User) -> Vec<Self> {
+        user_groups
+            .filter(
+                owner_id.eq(user.id)
+                    .or(id.eq_any(
+                        user_group_members::table
+                            .filter(user_group_members::user_id.eq(user.id))
+                            .select(user_group_members::group_id)
+                    ))
+            )
+            .order_by(name.asc())
+            .select(Self::as_select())
+            .get_results(conn)
+            .unwrap()
+    }
+
+    pub fn delete(self, conn: &mut SqliteConnection) {
+        // members will be deleted by a cascade
+        diesel::delete(user_groups.find(self.id))
+            .execute(conn)
+            .unwrap();
+    }
+
+    pub fn is_owned_by(&self, user: &db::User) -> bool {
+        self.owner_id == user.id
+    }
+
+    /// whether a user can address the group, and see who is in it
+    pub fn is_accessible_to(&self, conn: &mut SqliteConnection, user: &db::User) -> bool {
+        self.is_owned_by(user) || diesel::select(diesel::dsl::exists(
+            user_group_members::table.find((self.id, user.id))
+        ))
+            .get_result(conn)
+            .unwrap()
+    }
+
+    /// already present members are left as is
+    pub fn add_members(&self, conn: &mut SqliteConnection, members: &[db::User]) {
+        for member in members {
+            diesel::insert_or_ignore_into(user_group_members::table)
+                .values((
+                    user_group_members::group_id.eq(self.id),
+                    user_group_members::user_id.eq(member.id),
+                ))
+                .execute(conn)
+                .unwrap();
+        };
+    }
+
+    pub fn remove_member(&self, conn: &mut SqliteConnection, member: &db::User) {
+        diesel::delete(user_group_members::table.find((self.id, member.id)))
+            .execute(conn)
+            .unwrap();
+    }
+
+    /// the deleted users are left out
+    pub fn get_members(&self, conn: &mut SqliteConnection) -> Vec<db::User> {
+        user_group_members::table
+            .filter(user_group_members::group_id.eq(self.id))
+            .inner_join(users::table)
+            .filter(users::is_deleted.eq(false))
+            .select(db::User::as_select())
+            .get_results(conn)
+            .unwrap()
+    }
+}
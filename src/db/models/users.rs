@@ -6,7 +6,7 @@ use chrono::NaiveDateTime;
 use diesel::result::DatabaseErrorKind;
 use crate::db;
 use super::gen_id;
-use super::super::schema::{self, users::dsl::*};
+use super::super::schema::{self, users::dsl::*, conversation_participants};
 
 
 #[derive(Queryable, Selectable, Insertable)]
@@ -18,7 +18,42 @@ pub struct User {
     pub hashed_password: Option<String>,
     pub creation_time: NaiveDateTime,
     pub properties: Option<String>,  // todo use serde json
-    pub is_deleted: bool
+    pub is_deleted: bool,
+    pub fs_template: Option<String>,
+    pub fs_template_version: Option<i32>,
+    pub message_privacy: String,
+    /// only ever set by whoever runs the server
+    pub is_admin: bool,
+}
+
+
+/// who may message a user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessagePrivacy {
+    Everyone,
+    /// the users sharing a conversation with them
+    Contacts,
+    Nobody,
+}
+
+
+impl MessagePrivacy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Everyone => "everyone",
+            Self::Contacts => "contacts",
+            Self::Nobody => "nobody",
+        }
+    }
+    
+    pub fn from_str(s: &str) -> Option<Self> {
+        match s {
+            "everyone" => Some(Self::Everyone),
+            "contacts" => Some(Self::Contacts),
+            "nobody" => Some(Self::Nobody),
+            _ => None,
+        }
+    }
 }
 
 
@@ -39,15 +74,22 @@ impl User {
         hmac_sha512::Hash::hash(password).map(|b| format!("{b:0>2x}")).concat()
     }
 
-    pub fn create(conn: &mut SqliteConnection, username_: &str, password: &str, properties_: Option<&serde_json::Value>) -> Result<Self, UserCreationError> {
+    pub fn create(conn: &mut SqliteConnection, username_: &str, password: &str, properties_: Option<&serde_json::Value>, fs_template_: Option<&str>) -> Result<Self, UserCreationError> {
         let r = diesel::insert_into(users)
             .values(&User {
                 id: gen_id(),
                 username: Some(username_.to_string()),
                 hashed_password: Some(Self::hash_password(password)),
                 creation_time: chrono::Utc::now().naive_local(),
-                properties: Some(properties_.map(|p| p.to_string()).unwrap_or(include_str!("../../../assets/user_properties.default.json").into())),
+                properties: Some(Self::sync_properties(
+                    properties_.cloned().unwrap_or_else(|| serde_json::from_str(include_str!("../../../assets/user_properties.default.json")).unwrap()),
+                    false
+                ).to_string()),
                 is_deleted: false,
+                fs_template: fs_template_.map(str::to_string),
+                fs_template_version: None,
+                message_privacy: MessagePrivacy::Everyone.as_str().to_string(),
+                is_admin: false,
             })
             .get_result(conn);
 
@@ -58,19 +100,30 @@ impl User {
         }
     }
     
-    pub fn delete(&mut self, conn: &mut SqliteConnection) {
+    /// returns the keys of the files attached to the user's messages, which should be removed from the attachment store now
+    pub fn delete(&mut self, conn: &mut SqliteConnection) -> Vec<String> {
         // xxx or should it return UserInteractionError::UserIsDeleted error?
         if self.is_deleted {
-            return;
+            return Vec::new();
         };
         
         // tokens will be deleted by a cascade
         
-        // ...but messages are not going to delete themselves
-        for mut message in db::Message::get_all_not_deleted_made_by_user(conn, self) {
-            message.delete(conn);
+        // ...but messages are not going to delete themselves. the others keep their copies though
+        let mut file_keys = Vec::new();
+        for mut message in db::Message::get_all_not_deleted_accessible_to_user(conn, self, &db::MessageFilter::default(), false, -1, 0) {
+            file_keys.append(&mut message.delete_for(conn, self).expect("the message is not deleted for the user"));
         };
         
+        // the reactions would only show up as someone who doesn't exist anymore
+        db::MessageReaction::remove_all_by_user(conn, self.id);
+        
+        // there is nothing left to log in to
+        db::TwoFactor::remove_all_of_user(conn, self.id);
+
+        // they only make sense along with the files, which are gone
+        db::TemplateDelivery::remove_all_of_user(conn, self.id);
+
         // ...then delete the user
         diesel::update(users.find(self.id))
             .set((
@@ -87,6 +140,8 @@ impl User {
         self.hashed_password = None;
         self.properties = None;
         self.is_deleted = true;
+        
+        file_keys
     }
     
     pub fn get(conn: &mut SqliteConnection, id_: i32) -> Option<Self> {
@@ -97,6 +152,14 @@ impl User {
             .unwrap()
     }
     
+    pub fn get_all_by_ids(conn: &mut SqliteConnection, ids: &[i32]) -> Vec<Self> {
+        users
+            .filter(id.eq_any(ids))
+            .select(Self::as_select())
+            .get_results(conn)
+            .unwrap()
+    }
+    
     pub fn get_by_username(conn: &mut SqliteConnection, username_: &str) -> Option<Self> {
         users
             .filter(username.eq(username_))
@@ -105,12 +168,52 @@ impl User {
             .unwrap()
     }
 
+    /// returns users whose files are set up using an older version of the template.
+    /// users without a template are considered to be using the default one
+    pub fn get_all_with_outdated_fs_template(conn: &mut SqliteConnection, template: &str, version: i32, is_default: bool) -> Vec<Self> {
+        let mut query = users
+            .filter(is_deleted.eq(false))
+            .filter(fs_template_version.is_null().or(fs_template_version.lt(version)))
+            .into_boxed();
+        
+        query = if is_default {
+            query.filter(fs_template.eq(template).or(fs_template.is_null()))
+        } else {
+            query.filter(fs_template.eq(template))
+        };
+        
+        query
+            .select(Self::as_select())
+            .get_results(conn)
+            .unwrap()
+    }
+    
+    pub fn set_fs_template(conn: &mut SqliteConnection, id_: i32, template: &str, version: i32) {
+        diesel::update(users.find(id_))
+            .set((
+                fs_template.eq(template),
+                fs_template_version.eq(version)
+            ))
+            .execute(conn)
+            .unwrap();
+    }
+
+    /// the clients read "acc.admin" from the properties, but they can write them too,
+    /// so it always mirrors the real value instead
+    fn sync_properties(mut prop: serde_json::Value, is_admin_: bool) -> serde_json::Value {
+        if let Some(acc) = prop.get_mut("acc").and_then(serde_json::Value::as_object_mut) {
+            acc.insert("admin".to_string(), serde_json::Value::Bool(is_admin_));
+        };
+        
+        prop
+    }
+
     pub fn map_properties_as_json(&self) -> Option<Result<serde_json::Value, serde_json::Error>> {
         self.properties.as_ref().map(|prop_raw| serde_json::from_str(prop_raw))
     }
     
     pub fn get_username(&self) -> String {
-        self.username.as_ref().map(String::clone).unwrap_or_else(|| format!("deleted#{}", self.id))
+        self.username.clone().unwrap_or_else(|| format!("deleted#{}", self.id))
     }
     
     pub fn get_all(conn: &mut SqliteConnection) -> Vec<Self> {
@@ -130,7 +233,7 @@ impl User {
     
     pub fn set_properties(&mut self, conn: &mut SqliteConnection, new_prop: serde_json::Value) -> Result<(), UserInteractionError> {
         if let Some(ref mut prop) = self.properties {
-            let json_new_prop = new_prop.to_string();
+            let json_new_prop = Self::sync_properties(new_prop, self.is_admin).to_string();
 
             diesel::update(users.find(self.id))
                 .set(properties.eq(&json_new_prop))
@@ -176,4 +279,54 @@ impl User {
             Err(UserInteractionError::UserIsDeleted)
         }
     }
+    
+    pub fn get_message_privacy(&self) -> MessagePrivacy {
+        MessagePrivacy::from_str(&self.message_privacy).expect("only the known privacy settings are stored")
+    }
+    
+    pub fn set_message_privacy(&mut self, conn: &mut SqliteConnection, privacy: MessagePrivacy) -> Result<(), UserInteractionError> {
+        if self.is_deleted {
+            return Err(UserInteractionError::UserIsDeleted);
+        };
+        
+        diesel::update(users.find(self.id))
+            .set(message_privacy.eq(privacy.as_str()))
+            .execute(conn)
+            .unwrap();
+        
+        self.message_privacy = privacy.as_str().to_string();
+        
+        Ok(())
+    }
+    
+    /// takes both the blocks and the privacy setting into account
+    pub fn accepts_messages_from(&self, conn: &mut SqliteConnection, sender: &User) -> bool {
+        if self.id == sender.id {
+            return true;
+        };
+        
+        if db::UserBlock::exists(conn, self.id, sender.id) {
+            return false;
+        };
+        
+        match self.get_message_privacy() {
+            MessagePrivacy::Everyone => true,
+            MessagePrivacy::Nobody => false,
+            MessagePrivacy::Contacts => {
+                let conversations = conversation_participants::table
+                    .filter(conversation_participants::user_id.eq(self.id))
+                    .select(conversation_participants::conversation_id)
+                    .get_results::<i32>(conn)
+                    .unwrap();
+                
+                diesel::select(diesel::dsl::exists(
+                    conversation_participants::table
+                        .filter(conversation_participants::user_id.eq(sender.id))
+                        .filter(conversation_participants::conversation_id.eq_any(conversations))
+                ))
+                    .get_result(conn)
+                    .unwrap()
+            },
+        }
+    }
 }
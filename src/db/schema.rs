@@ -1,3 +1,73 @@
+diesel::table! {
+    conversation_participants (conversation_id, user_id) {
+        conversation_id -> Integer,
+        user_id -> Integer,
+    }
+}
+
+diesel::table! {
+    conversations (id) {
+        id -> Integer,
+        creation_time -> Timestamp,
+    }
+}
+
+diesel::table! {
+    fs_items (path) {
+        path -> Text,
+        creation_time -> Timestamp,
+    }
+}
+
+diesel::table! {
+    login_challenges (hashed_value) {
+        hashed_value -> Text,
+        user_id -> Integer,
+        label -> Nullable<Text>,
+        attempts -> Integer,
+        creation_time -> Timestamp,
+    }
+}
+
+diesel::table! {
+    message_attachments (id) {
+        id -> Integer,
+        message_id -> Integer,
+        file_key -> Text,
+        name -> Text,
+        size -> BigInt,
+        mime -> Nullable<Text>,
+    }
+}
+
+diesel::table! {
+    message_reactions (message_id, user_id, reaction) {
+        message_id -> Integer,
+        user_id -> Integer,
+        reaction -> Text,
+        creation_time -> Timestamp,
+    }
+}
+
+diesel::table! {
+    message_recipients (message_id, recipient_id) {
+        message_id -> Integer,
+        recipient_id -> Integer,
+        is_read -> Nullable<Bool>,
+        is_deleted -> Bool,
+    }
+}
+
+diesel::table! {
+    message_revisions (id) {
+        id -> Integer,
+        message_id -> Integer,
+        body -> Text,
+        creation_time -> Timestamp,
+        content_type -> Text,
+    }
+}
+
 diesel::table! {
     messages (id) {
         id -> Integer,
@@ -6,17 +76,96 @@ diesel::table! {
         body -> Nullable<Text>,
         replying_id -> Nullable<Integer>,
         sent_time -> Timestamp,
-        is_read -> Nullable<Bool>,
         is_deleted -> Bool,
+        conversation_id -> Nullable<Integer>,
+        edited_time -> Nullable<Timestamp>,
+        is_deleted_by_sender -> Bool,
+        content_type -> Text,
+        plain_body -> Nullable<Text>,
+        is_pending -> Bool,
+        expire_time -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    recovery_codes (user_id, hashed_code) {
+        user_id -> Integer,
+        hashed_code -> Text,
+    }
+}
+
+diesel::table! {
+    refresh_tokens (hashed_value) {
+        hashed_value -> Text,
+        token_id -> Integer,
+        is_used -> Bool,
+        creation_time -> Timestamp,
     }
 }
 
 diesel::table! {
-    tokens (value) {
-        value -> Text,
+    scheduled_recipients (message_id, recipient_id) {
+        message_id -> Integer,
+        recipient_id -> Integer,
+    }
+}
+
+diesel::table! {
+    template_deliveries (user_id, path) {
+        user_id -> Integer,
+        path -> Text,
+    }
+}
+
+diesel::table! {
+    tokens (hashed_value) {
+        hashed_value -> Text,
         owner_id -> Integer,
         lifetime -> Nullable<Float>,
         creation_time -> Timestamp,
+        id -> Integer,
+        label -> Nullable<Text>,
+        ip -> Nullable<Text>,
+        user_agent -> Nullable<Text>,
+        last_used_time -> Nullable<Timestamp>,
+        access_expiry_time -> Nullable<Timestamp>,
+        idle_timeout -> Nullable<Float>,
+    }
+}
+
+diesel::table! {
+    two_factor (user_id) {
+        user_id -> Integer,
+        secret -> Text,
+        is_enabled -> Bool,
+        last_used_step -> Nullable<BigInt>,
+        creation_time -> Timestamp,
+        failed_attempts -> Integer,
+        locked_until -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    user_group_members (group_id, user_id) {
+        group_id -> Integer,
+        user_id -> Integer,
+    }
+}
+
+diesel::table! {
+    user_groups (id) {
+        id -> Integer,
+        name -> Text,
+        owner_id -> Integer,
+        creation_time -> Timestamp,
+    }
+}
+
+diesel::table! {
+    user_blocks (blocker_id, blocked_id) {
+        blocker_id -> Integer,
+        blocked_id -> Integer,
+        creation_time -> Timestamp,
     }
 }
 
@@ -28,13 +177,51 @@ diesel::table! {
         creation_time -> Timestamp,
         properties -> Nullable<Text>,  // todo somehow convey it that Json is convertible to serde's json (as per docs)
         is_deleted -> Bool,
+        fs_template -> Nullable<Text>,
+        fs_template_version -> Nullable<Integer>,
+        message_privacy -> Text,
+        is_admin -> Bool,
     }
 }
 
+diesel::joinable!(conversation_participants -> conversations (conversation_id));
+diesel::joinable!(conversation_participants -> users (user_id));
+diesel::joinable!(login_challenges -> users (user_id));
+diesel::joinable!(message_attachments -> messages (message_id));
+diesel::joinable!(message_reactions -> messages (message_id));
+diesel::joinable!(message_reactions -> users (user_id));
+diesel::joinable!(message_recipients -> messages (message_id));
+diesel::joinable!(message_recipients -> users (recipient_id));
+diesel::joinable!(message_revisions -> messages (message_id));
+diesel::joinable!(messages -> conversations (conversation_id));
+diesel::joinable!(recovery_codes -> users (user_id));
+diesel::joinable!(scheduled_recipients -> messages (message_id));
+diesel::joinable!(scheduled_recipients -> users (recipient_id));
+diesel::joinable!(template_deliveries -> users (user_id));
 diesel::joinable!(tokens -> users (owner_id));
+diesel::joinable!(two_factor -> users (user_id));
+diesel::joinable!(user_group_members -> user_groups (group_id));
+diesel::joinable!(user_group_members -> users (user_id));
+diesel::joinable!(user_groups -> users (owner_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    conversation_participants,
+    conversations,
+    fs_items,
+    login_challenges,
+    message_attachments,
+    message_reactions,
+    message_recipients,
+    message_revisions,
     messages,
+    recovery_codes,
+    refresh_tokens,
+    scheduled_recipients,
+    template_deliveries,
     tokens,
+    two_factor,
+    user_blocks,
+    user_group_members,
+    user_groups,
     users,
 );
@@ -0,0 +1,55 @@
+use futures::Stream;
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+
+/// how many events can be waiting for the slowest listener, before it starts missing them
+const CHANNEL_CAPACITY: usize = 1024;
+
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    Reaction {
+        message_id: i32,
+        /// who reacted
+        username: String,
+        reaction: String,
+        /// false if the reaction was removed
+        added: bool,
+    },
+}
+
+
+/// passes the events on to the users who are listening right now, nothing is kept for the others
+#[derive(Debug)]
+pub struct EventHub {
+    sender: broadcast::Sender<(i32, Event)>,
+}
+
+
+impl EventHub {
+    pub fn new() -> Self {
+        Self { sender: broadcast::channel(CHANNEL_CAPACITY).0 }
+    }
+
+    pub fn publish(&self, user_id: i32, event: Event) {
+        // it's fine for no one to be listening
+        let _ = self.sender.send((user_id, event));
+    }
+
+    /// the events meant for the user, from now on
+    pub fn subscribe(&self, user_id: i32) -> impl Stream<Item = Event> {
+        futures::stream::unfold(self.sender.subscribe(), move |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok((id, event)) if id == user_id => return Some((event, receiver)),
+                    Ok(_) => continue,
+                    // the missed events are skipped, the listener can catch up by refetching
+                    Err(RecvError::Lagged(missed)) => log::debug!("an event listener missed {missed} events"),
+                    Err(RecvError::Closed) => return None,
+                };
+            };
+        })
+    }
+}
@@ -0,0 +1,67 @@
+use std::path::{Path, PathBuf};
+use super::{pinned, Filesystem, FSError, FSRes};
+
+
+/// a snapshot of a file, made when it got attached to a message
+#[derive(Debug)]
+pub struct StoredAttachment {
+    pub key: String,
+    pub name: String,
+    pub size: u64,
+    pub mime: Option<String>,
+}
+
+
+impl Filesystem {
+    /// copies a file into the attachment store, so that it would stay the same no matter what happens to the original.
+    /// WARNING: DOES NOT CHECK THE USER'S STORAGE
+    pub async fn store_attachment(&self, source: &Path) -> FSRes<StoredAttachment> {
+        let source = self.construct_path(source)?;
+        let metadata = pinned::PinnedItem::new(&source).and_then(|p| p.symlink_metadata()).map_err(FSError::from_hfs)?;
+
+        if !metadata.is_file() {
+            return Err(FSError::NotAFile);
+        };
+
+        if let Some(total_size) = self.total_size {
+            if self.get_item_size(".".as_ref()).await? + metadata.len() > total_size {
+                return Err(FSError::NotEnoughStorage);
+            }
+        };
+
+        let key = uuid::Uuid::new_v4().to_string();
+        let target = self.construct_path(&Self::attachment_path(&key))?;
+
+        tokio::fs::create_dir_all(target.parent().unwrap()).await.map_err(FSError::HFS)?;
+        let size = {
+            let (source, target) = (source.clone(), target.clone());
+            tokio::task::spawn_blocking(move || pinned::copy_file(&source, &target)).await.unwrap().map_err(FSError::from_hfs)?
+        };
+
+        Ok(StoredAttachment {
+            key,
+            name: source.file_name().unwrap().to_string_lossy().to_string(),
+            size,
+            mime: self.mime_cache.get_mime(&source).await?,
+        })
+    }
+
+    pub async fn read_attachment(&self, key: &str) -> FSRes<Vec<u8>> {
+        self.read_file(&Self::attachment_path(key)).await
+    }
+
+    /// whatever fails to be removed is left for the integrity scan
+    pub async fn remove_attachments(&self, keys: &[String]) {
+        for key in keys {
+            match tokio::fs::remove_file(self.storage_path.join(Self::attachment_path(key))).await {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound =>
+                    log::warn!("failed to remove attachment {key}: {err}"),
+                _ => {},
+            };
+        };
+    }
+
+    fn attachment_path(key: &str) -> PathBuf {
+        Path::new(Self::ATTACHMENTS_DIR_NAME).join(key)
+    }
+}
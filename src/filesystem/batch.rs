@@ -0,0 +1,206 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use super::{ConflictPolicy, Filesystem, FSError, FSRes};
+
+
+#[derive(Debug, Clone)]
+pub enum FSOperation {
+    Copy { source: PathBuf, target: PathBuf, conflict: ConflictPolicy },
+    Move { source: PathBuf, target: PathBuf, conflict: ConflictPolicy },
+    Remove { path: PathBuf },
+}
+
+
+#[derive(Debug)]
+pub enum FSOperationOutcome {
+    Done,
+    Failed(FSError),
+    RolledBack,
+    RollbackFailed(FSError),
+    Skipped,
+}
+
+
+/// what has to be done in order to revert an already executed operation
+struct Undo {
+    operation: FSOperation,
+    /// where the overwritten target (or the removed item) was put aside to
+    stash: Option<PathBuf>,
+}
+
+
+impl FSOperation {
+    pub fn map_paths(self, mut f: impl FnMut(&Path) -> FSRes<PathBuf>) -> FSRes<Self> {
+        Ok(match self {
+            Self::Copy { source, target, conflict } => Self::Copy { source: f(&source)?, target: f(&target)?, conflict },
+            Self::Move { source, target, conflict } => Self::Move { source: f(&source)?, target: f(&target)?, conflict },
+            Self::Remove { path } => Self::Remove { path: f(&path)? },
+        })
+    }
+}
+
+
+/// executes the operations one by one, and on the first failure reverts the already executed ones.
+/// if there is a limit to the free size, the whole batch is checked against it beforehand, and then each operation
+/// once again right before it's executed, as it might work with the items made by the previous ones.
+/// WARNING: EXPECTS ALREADY SCOPED PATHS
+pub(super) async fn run(fs: &Filesystem, operations: Vec<FSOperation>, mut free_size: Option<u64>) -> FSRes<Vec<FSOperationOutcome>> {
+    if let Some(free_size) = free_size {
+        let mut required_size = 0;
+
+        for operation in &operations {
+            required_size += get_required_size(fs, operation).await?;
+        };
+
+        if required_size > free_size {
+            return Err(FSError::NotEnoughStorage);
+        };
+    };
+
+    let staging_path = fs.create_staging_dir().await?;
+
+    let mut outcomes = Vec::with_capacity(operations.len());
+    let mut undos = Vec::with_capacity(operations.len());
+    let mut has_failed = false;
+
+    for (i, operation) in operations.into_iter().enumerate() {
+        if has_failed {
+            outcomes.push(FSOperationOutcome::Skipped);
+            continue;
+        };
+
+        let res = match reserve_size(fs, &operation, &mut free_size).await {
+            Ok(()) => execute(fs, operation, &staging_path.join(i.to_string())).await,
+            Err(err) => Err(err),
+        };
+
+        match res {
+            Ok(undo) => {
+                undos.push(undo);
+                outcomes.push(FSOperationOutcome::Done);
+            },
+            Err(err) => {
+                outcomes.push(FSOperationOutcome::Failed(err));
+                has_failed = true;
+            }
+        };
+    };
+
+    if has_failed {
+        // undos are made only for the operations before the failed one, so their indices match
+        for (i, undo) in undos.into_iter().enumerate().rev() {
+            outcomes[i] = match revert(fs, undo).await {
+                Ok(()) => FSOperationOutcome::RolledBack,
+                Err(err) => FSOperationOutcome::RollbackFailed(err),
+            };
+        };
+    };
+
+    // this is also where the removed items actually get deleted
+    if let Err(err) = fs.remove_item(&staging_path).await {
+        log::warn!("failed to clean up batch staging directory {}: {err}", staging_path.display());
+    };
+
+    Ok(outcomes)
+}
+
+
+async fn reserve_size(fs: &Filesystem, operation: &FSOperation, free_size: &mut Option<u64>) -> FSRes<()> {
+    let Some(free_size) = free_size else {
+        return Ok(());
+    };
+
+    *free_size = free_size.checked_sub(get_required_size(fs, operation).await?).ok_or(FSError::NotEnoughStorage)?;
+
+    Ok(())
+}
+
+
+/// how much more space the operation takes up until the batch is over: the copy itself, and the snapshot
+/// of an overwritten target. nothing is freed up in the meantime, as the overwritten and the removed items
+/// are kept in the staging directory until then. the items, which don't exist (yet), take up nothing
+async fn get_required_size(fs: &Filesystem, operation: &FSOperation) -> FSRes<u64> {
+    let (copied, overwritten) = match operation {
+        FSOperation::Copy { source, target, conflict } => (Some(source), Some(target).filter(|_| *conflict == ConflictPolicy::Overwrite)),
+        FSOperation::Move { target, conflict, .. } => (None, Some(target).filter(|_| *conflict == ConflictPolicy::Overwrite)),
+        FSOperation::Remove { .. } => (None, None),
+    };
+
+    let mut size = 0;
+
+    for path in copied.into_iter().chain(overwritten) {
+        size += match fs.get_item_size(path).await {
+            Err(FSError::HFS(err)) if err.kind() == ErrorKind::NotFound => 0,
+            res => res?,
+        };
+    };
+
+    Ok(size)
+}
+
+
+async fn execute(fs: &Filesystem, operation: FSOperation, stash_path: &Path) -> FSRes<Undo> {
+    // only an overwrite can destroy an existing target
+    let stash = match operation {
+        FSOperation::Copy { ref target, conflict: ConflictPolicy::Overwrite, .. } |
+        FSOperation::Move { ref target, conflict: ConflictPolicy::Overwrite, .. } =>
+            stash_copy(fs, target, stash_path).await?,
+        _ => None,
+    };
+
+    // the target is replaced with the actual one, as it might have been renamed due to a conflict
+    Ok(match operation {
+        FSOperation::Copy { source, target, conflict } => {
+            let target = fs.copy_item(&source, &target, conflict).await?;
+            Undo { operation: FSOperation::Copy { source, target, conflict }, stash }
+        },
+        FSOperation::Move { source, target, conflict } => {
+            let target = fs.move_item(&source, &target, conflict).await?;
+            Undo { operation: FSOperation::Move { source, target, conflict }, stash }
+        },
+        FSOperation::Remove { path } => {
+            fs.move_item(&path, stash_path, ConflictPolicy::Fail).await?;
+            Undo { operation: FSOperation::Remove { path }, stash: Some(stash_path.to_path_buf()) }
+        },
+    })
+}
+
+
+async fn revert(fs: &Filesystem, Undo { operation, stash }: Undo) -> FSRes<()> {
+    match operation {
+        FSOperation::Copy { target, .. } => {
+            fs.remove_item(&target).await?;
+            restore(fs, stash, &target).await
+        },
+        FSOperation::Move { source, target, .. } => {
+            fs.move_item(&target, &source, ConflictPolicy::Fail).await?;
+            restore(fs, stash, &target).await
+        },
+        FSOperation::Remove { path } => restore(fs, stash, &path).await,
+    }
+}
+
+
+/// puts a copy of an item aside, if it exists, so that it could be restored after being overwritten
+async fn stash_copy(fs: &Filesystem, path: &Path, stash_path: &Path) -> FSRes<Option<PathBuf>> {
+    if !fs.construct_path(path)?.exists() {
+        return Ok(None);
+    };
+
+    fs.snapshot_item(path, stash_path).await?;
+
+    Ok(Some(stash_path.to_path_buf()))
+}
+
+
+async fn restore(fs: &Filesystem, stash: Option<PathBuf>, path: &Path) -> FSRes<()> {
+    let Some(stash) = stash else {
+        return Ok(());
+    };
+
+    if fs.construct_path(path)?.exists() {
+        fs.remove_item(path).await?;
+    };
+
+    fs.move_item(&stash, path, ConflictPolicy::Fail).await.map(|_| ())
+}
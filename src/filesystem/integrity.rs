@@ -0,0 +1,230 @@
+use std::collections::{HashMap, HashSet};
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use chrono::Utc;
+use diesel::QueryResult;
+use serde::Deserialize;
+use crate::db;
+use super::{walk_tree, Filesystem, FSError, FSRes};
+
+
+/// batch operations are short, so anything staged for longer than this has been left behind.
+/// the same goes for the attachments, which are stored right before their message is sent
+const STALE_STAGING_AGE: Duration = Duration::from_secs(60 * 60);
+
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScanMode {
+    Report,
+    Fix,
+}
+
+
+#[derive(Debug, Default)]
+pub struct IntegrityReport {
+    /// user directories without an existing user
+    pub orphaned_dirs: Vec<PathBuf>,
+    /// existing users without a directory. it's only reported, as the directories are created on the first access
+    pub users_without_dirs: Vec<i32>,
+    pub stale_staging_items: Vec<PathBuf>,
+    /// stored attachments without a message
+    pub orphaned_attachments: Vec<PathBuf>,
+    /// recorded items which do not exist anymore
+    pub dangling_records: Vec<String>,
+    /// existing items which were never recorded
+    pub unrecorded_items: Vec<String>,
+    pub is_fixed: bool,
+}
+
+
+impl IntegrityReport {
+    /// a user without a directory is not a problem on its own
+    pub fn is_clean(&self) -> bool {
+        self.orphaned_dirs.is_empty()
+            && self.stale_staging_items.is_empty()
+            && self.orphaned_attachments.is_empty()
+            && self.dangling_records.is_empty()
+            && self.unrecorded_items.is_empty()
+    }
+}
+
+
+impl std::fmt::Display for IntegrityReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} orphaned dirs, {} users without dirs, {} stale staging items, {} orphaned attachments, {} dangling records, {} unrecorded items{}",
+            self.orphaned_dirs.len(),
+            self.users_without_dirs.len(),
+            self.stale_staging_items.len(),
+            self.orphaned_attachments.len(),
+            self.dangling_records.len(),
+            self.unrecorded_items.len(),
+            if self.is_fixed { " (fixed)" } else { "" }
+        )
+    }
+}
+
+
+impl Filesystem {
+    /// finds whatever got out of sync between the storage and the db, and optionally fixes it
+    pub async fn scan_integrity(&self, mode: ScanMode) -> FSRes<IntegrityReport> {
+        let mut report = IntegrityReport::default();
+
+        let users = self.with_conn(db::User::get_all).await
+            .into_iter()
+            .map(|u| (u.id, u))
+            .collect::<HashMap<_, _>>();
+
+        let mut user_dirs = HashSet::new();
+
+        let mut root_iter = tokio::fs::read_dir(&self.storage_path).await.map_err(FSError::HFS)?;
+        while let Some(item) = root_iter.next_entry().await.map_err(FSError::HFS)? {
+            let Some(user_id) = item.file_name().to_str().and_then(|n| n.parse::<i32>().ok()) else {
+                continue;
+            };
+
+            match users.get(&user_id) {
+                Some(user) if !user.is_deleted => { user_dirs.insert(user_id); },
+                _ => report.orphaned_dirs.push(PathBuf::from(item.file_name())),
+            };
+        };
+
+        report.users_without_dirs = users.values()
+            .filter(|u| !u.is_deleted && !user_dirs.contains(&u.id))
+            .map(|u| u.id)
+            .collect();
+        report.users_without_dirs.sort();
+
+        report.stale_staging_items = self.find_stale_items(Self::STAGING_DIR_NAME, |_| true).await?;
+
+        let attachment_keys = self.with_conn(db::MessageAttachment::get_all_file_keys).await.into_iter().collect::<HashSet<_>>();
+        report.orphaned_attachments = self.find_stale_items(
+            Self::ATTACHMENTS_DIR_NAME,
+            |name| !name.to_str().is_some_and(|n| attachment_keys.contains(n))
+        ).await?;
+
+        let (dangling_records, unrecorded_items) = self.find_ledger_drift(&user_dirs).await?;
+        report.dangling_records = dangling_records;
+        report.unrecorded_items = unrecorded_items;
+
+        if mode == ScanMode::Fix {
+            self.fix(&report).await?;
+            report.is_fixed = true;
+        };
+
+        Ok(report)
+    }
+
+    /// the items of a dir, which match the filter and haven't been touched for a while
+    async fn find_stale_items(&self, dir_name: &str, filter: impl Fn(&OsStr) -> bool) -> FSRes<Vec<PathBuf>> {
+        let mut stale_items = Vec::new();
+
+        let mut dir_iter = match tokio::fs::read_dir(self.storage_path.join(dir_name)).await {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(stale_items),
+            res => res.map_err(FSError::HFS)?,
+        };
+
+        while let Some(item) = dir_iter.next_entry().await.map_err(FSError::HFS)? {
+            if !filter(&item.file_name()) {
+                continue;
+            };
+
+            let modified = item.metadata().await.map_err(FSError::HFS)?.modified().map_err(FSError::HFS)?;
+
+            if SystemTime::now().duration_since(modified).unwrap_or_default() > STALE_STAGING_AGE {
+                stale_items.push(Path::new(dir_name).join(item.file_name()));
+            };
+        };
+
+        Ok(stale_items)
+    }
+
+    /// returns: (dangling records, unrecorded items)
+    async fn find_ledger_drift(&self, user_dirs: &HashSet<i32>) -> FSRes<(Vec<String>, Vec<String>)> {
+        let records = self.with_conn(db::FSItem::get_all_paths).await.into_iter().collect::<HashSet<_>>();
+
+        let storage_path = self.storage_path.clone();
+        let user_dirs = user_dirs.iter().map(|id| storage_path.join(id.to_string())).collect::<Vec<_>>();
+
+        let existing = tokio::task::spawn_blocking(move || {
+            let mut existing = HashSet::new();
+
+            for user_dir in user_dirs {
+                existing.insert(user_dir.clone());
+                existing.extend(walk_tree(&user_dir)?);
+            };
+
+            std::io::Result::Ok(existing)
+        }).await.unwrap().map_err(FSError::HFS)?
+            .into_iter()
+            .map(|p| self.item_key(&p))
+            .collect::<HashSet<_>>();
+
+        let mut dangling_records = Vec::new();
+
+        for record in records.iter().filter(|r| !existing.contains(*r)) {
+            // items of orphaned dirs are going to be dealt with along with their dirs
+            if !tokio::fs::try_exists(self.storage_path.join(record)).await.map_err(FSError::HFS)? {
+                dangling_records.push(record.clone());
+            };
+        };
+        dangling_records.sort();
+
+        let mut unrecorded_items = existing.difference(&records).cloned().collect::<Vec<_>>();
+        unrecorded_items.sort();
+
+        Ok((dangling_records, unrecorded_items))
+    }
+
+    async fn fix(&self, report: &IntegrityReport) -> FSRes<()> {
+        for path in &report.orphaned_dirs {
+            self.remove_orphaned_dir(path).await?;
+        };
+
+        for path in report.stale_staging_items.iter().chain(&report.orphaned_attachments) {
+            self.remove_item(path).await?;
+        };
+
+        let dangling_records = report.dangling_records.clone();
+        self.with_conn(move |conn| {
+            for path in dangling_records {
+                db::FSItem::delete_tree(conn, &path);
+            };
+        }).await;
+
+        // the same fallback as when the time info is requested for an unrecorded item
+        for key in &report.unrecorded_items {
+            let path = self.storage_path.join(key);
+            let metadata = tokio::fs::symlink_metadata(&path).await.map_err(FSError::HFS)?;
+            let created = metadata.created().or_else(|_| metadata.modified()).map_err(FSError::HFS)?;
+
+            self.record_items(vec![path], chrono::DateTime::<Utc>::from(created).naive_utc()).await;
+        };
+
+        Ok(())
+    }
+
+    /// the ids are random, so a new user could have got the same one since the scan.
+    /// it's checked once more while the db is locked for writing, and the dir is set aside under that same lock
+    async fn remove_orphaned_dir(&self, path: &Path) -> FSRes<()> {
+        let Some(user_id) = path.to_str().and_then(|n| n.parse::<i32>().ok()) else {
+            return Ok(());
+        };
+
+        let staging_path = self.create_staging_dir().await?;
+        let (source, target) = (self.construct_path(path)?, self.construct_path(&staging_path.join("orphaned"))?);
+
+        self.with_conn(move |conn| conn.immediate_transaction(|conn| {
+            if db::User::get(conn, user_id).is_some_and(|u| !u.is_deleted) {
+                return QueryResult::Ok(Ok(()));
+            };
+
+            QueryResult::Ok(std::fs::rename(&source, &target))
+        }).unwrap()).await.map_err(FSError::HFS)?;
+
+        self.remove_item(&staging_path).await
+    }
+}
@@ -0,0 +1,215 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+use tokio::io::AsyncReadExt;
+use super::{FSError, FSRes};
+use super::pinned::PinnedItem;
+
+
+/// how many bytes from the start of a file are looked at when sniffing
+const SNIFF_LENGTH: usize = 8192;
+
+/// after this many entries the cache just starts over, so that it wouldn't grow forever
+const MAX_CACHE_ENTRIES: usize = 16384;
+
+const OCTET_STREAM_MIME_TYPE: &str = "application/octet-stream";
+
+/// types which a browser would execute scripts in, if served inline from the api's origin
+const ACTIVE_MIME_TYPES: &[&str] = &[
+    "text/html",
+    "application/xhtml+xml",
+    "image/svg+xml",
+    "text/xml",
+    "application/xml",
+    "text/javascript",
+    "application/javascript",
+    "application/x-shockwave-flash",
+];
+
+
+#[derive(Debug)]
+struct CachedMime {
+    len: u64,
+    modified: SystemTime,
+    mime: Option<String>,
+}
+
+
+#[derive(Debug, Default)]
+pub struct MimeCache {
+    entries: Mutex<HashMap<PathBuf, CachedMime>>,
+}
+
+
+impl MimeCache {
+    /// WARNING: EXPECTS AN ALREADY CONSTRUCTED PATH
+    pub async fn get_mime(&self, path: &Path) -> FSRes<Option<String>> {
+        let pinned = PinnedItem::new(path).map_err(FSError::from_hfs)?;
+        let metadata = pinned.symlink_metadata().map_err(FSError::HFS)?;
+
+        if !metadata.is_file() {
+            return Ok(guess_by_extension(path));
+        };
+
+        let modified = metadata.modified().map_err(FSError::HFS)?;
+
+        if let Some(cached) = self.entries.lock().unwrap().get(path) {
+            if cached.len == metadata.len() && cached.modified == modified {
+                return Ok(cached.mime.clone());
+            };
+        };
+
+        let mime = detect(path, &read_header(&pinned).await?);
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= MAX_CACHE_ENTRIES {
+            entries.clear();
+        };
+        entries.insert(path.to_path_buf(), CachedMime { len: metadata.len(), modified, mime: mime.clone() });
+
+        Ok(mime)
+    }
+
+    /// drops the cached entries of an item and everything under it
+    pub fn forget(&self, path: &Path) {
+        self.entries.lock().unwrap().retain(|p, _| !p.starts_with(path));
+    }
+}
+
+
+/// combines the magic bytes of a file with the guess made from its extension
+fn detect(path: &Path, header: &[u8]) -> Option<String> {
+    let by_extension = guess_by_extension(path);
+
+    if header.is_empty() {
+        return by_extension;
+    };
+
+    match infer::get(header) {
+        // text matchers are only a guess, so an extension has a higher priority
+        Some(t) if t.matcher_type() == infer::MatcherType::Text =>
+            by_extension.or_else(|| Some(t.mime_type().to_string())),
+        // lots of formats are zip containers, and the extension knows better which one exactly
+        Some(t) if t.mime_type() == "application/zip" =>
+            by_extension.filter(|m| !m.starts_with("text/")).or_else(|| Some(t.mime_type().to_string())),
+        Some(t) => Some(t.mime_type().to_string()),
+        None if looks_like_text(header) => by_extension,
+        None => by_extension
+            .filter(|m| !m.starts_with("text/"))
+            .or_else(|| Some(OCTET_STREAM_MIME_TYPE.to_string())),
+    }
+}
+
+
+/// whether a file of such type should never be rendered inline by a browser
+pub fn is_active_content(mime: &str) -> bool {
+    let essence = mime.split(';').next().unwrap_or_default().trim().to_ascii_lowercase();
+
+    ACTIVE_MIME_TYPES.contains(&essence.as_str())
+}
+
+
+fn guess_by_extension(path: &Path) -> Option<String> {
+    mime_guess::from_path(path).first().map(|mm| mm.to_string())
+}
+
+
+fn looks_like_text(header: &[u8]) -> bool {
+    if header.contains(&0) {
+        return false;
+    };
+
+    match std::str::from_utf8(header) {
+        Ok(_) => true,
+        // the header might have been cut in the middle of a multibyte character
+        Err(err) => err.error_len().is_none() && header.len() - err.valid_up_to() < 4,
+    }
+}
+
+
+async fn read_header(pinned: &PinnedItem) -> FSRes<Vec<u8>> {
+    let file = tokio::fs::File::from_std(pinned.open(std::fs::OpenOptions::new().read(true)).map_err(FSError::from_hfs)?);
+
+    let mut header = Vec::with_capacity(SNIFF_LENGTH);
+    file.take(SNIFF_LENGTH as u64).read_to_end(&mut header).await.map_err(FSError::HFS)?;
+
+    Ok(header)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use super::{detect, is_active_content, looks_like_text, MimeCache, OCTET_STREAM_MIME_TYPE};
+
+    const PNG_HEADER: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+    const ZIP_HEADER: &[u8] = b"PK\x03\x04\x14\0\0\0\x08\0";
+
+    fn detect_as(name: &str, header: &[u8]) -> Option<String> {
+        detect(Path::new(name), header)
+    }
+
+    #[test]
+    fn magic_bytes_win_over_the_extension() {
+        assert_eq!(detect_as("image.txt", PNG_HEADER).as_deref(), Some("image/png"));
+        assert_eq!(detect_as("image", PNG_HEADER).as_deref(), Some("image/png"));
+    }
+
+    #[test]
+    fn extension_is_used_for_text() {
+        assert_eq!(detect_as("notes.txt", b"hello").as_deref(), Some("text/plain"));
+        assert_eq!(detect_as("page.txt", b"<!DOCTYPE html><html></html>").as_deref(), Some("text/plain"));
+        assert_eq!(detect_as("notes", b"hello"), None);
+        // nothing to sniff
+        assert_eq!(detect_as("report.pdf", b"").as_deref(), Some("application/pdf"));
+    }
+
+    #[test]
+    fn binary_is_never_text() {
+        assert_eq!(detect_as("data.txt", b"\x00\x01\x02\x03").as_deref(), Some(OCTET_STREAM_MIME_TYPE));
+        assert_eq!(detect_as("data", b"\xff\xfe\xfd").as_deref(), Some(OCTET_STREAM_MIME_TYPE));
+        assert_eq!(detect_as("archive.txt", ZIP_HEADER).as_deref(), Some("application/zip"));
+    }
+
+    #[test]
+    fn zip_containers_are_told_apart_by_the_extension() {
+        assert_eq!(
+            detect_as("doc.docx", ZIP_HEADER).as_deref(),
+            Some("application/vnd.openxmlformats-officedocument.wordprocessingml.document")
+        );
+        assert_eq!(detect_as("archive", ZIP_HEADER).as_deref(), Some("application/zip"));
+    }
+
+    #[test]
+    fn text_cut_mid_character_is_still_text() {
+        assert!(looks_like_text("naïve".as_bytes()));
+        assert!(looks_like_text(&"abcé".as_bytes()[..4]));
+        assert!(!looks_like_text(b"abc\xff"));
+        assert!(!looks_like_text(b"abc\0def"));
+    }
+
+    #[test]
+    fn active_content_is_recognised() {
+        for mime in ["text/html", "text/html; charset=utf-8", "IMAGE/SVG+XML", "application/javascript"] {
+            assert!(is_active_content(mime), "{mime}");
+        };
+
+        for mime in ["text/plain", "image/png", "application/pdf", "text/htmlx"] {
+            assert!(!is_active_content(mime), "{mime}");
+        };
+    }
+
+    #[tokio::test]
+    async fn cache_follows_changes_to_the_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().canonicalize().unwrap().join("file");
+        let cache = MimeCache::default();
+
+        std::fs::write(&path, PNG_HEADER).unwrap();
+        assert_eq!(cache.get_mime(&path).await.unwrap().as_deref(), Some("image/png"));
+
+        std::fs::write(&path, b"\x00\x01").unwrap();
+        assert_eq!(cache.get_mime(&path).await.unwrap().as_deref(), Some(OCTET_STREAM_MIME_TYPE));
+    }
+}
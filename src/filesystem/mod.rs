@@ -1,30 +1,59 @@
-use std::fs::Metadata;
+use std::collections::{HashMap, HashSet};
+use std::fs::{FileType, Metadata, OpenOptions};
 use std::path::{Path, PathBuf};
-use std::str::FromStr;
 use std::time::SystemTime;
+use chrono::{NaiveDateTime, Utc};
+use diesel::SqliteConnection;
 use normalize_path::NormalizePath;
+use serde::Deserialize;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use crate::db;
+use pinned::{PinnedDir, PinnedItem};
 
 mod user_scope;
+mod mime;
+mod batch;
+mod integrity;
+mod attachments;
+mod pinned;
 
 
 pub use user_scope::UserScopedFS;
+pub use mime::is_active_content;
+pub use batch::{FSOperation, FSOperationOutcome};
+pub use integrity::{IntegrityReport, ScanMode};
+pub use attachments::StoredAttachment;
 
 
 #[derive(Debug)]
 pub struct Filesystem {
+    conn_pool: db::ConnPool,
     storage_path: PathBuf,
-    template_path: Option<PathBuf>,
+    templates: HashMap<String, Template>,
+    default_template: Option<String>,
     total_size: Option<u64>,
     userspace_size: Option<u64>,
+    mime_cache: mime::MimeCache,
 }
 
 
 #[derive(Debug)]
+pub struct Template {
+    pub path: PathBuf,
+    pub version: i32,
+}
+
+
+#[derive(Debug)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum FSError {
     HFS(std::io::Error),
     PathBreaksOut,
-    InvalidUTF8Path,
-    NotEnoughStorage
+    UnsafeItem,
+    NotEnoughStorage,
+    TargetExists,
+    RecursiveTarget,
+    NotAFile,
 }
 
 
@@ -33,8 +62,23 @@ impl std::fmt::Display for FSError {
         match self {
             Self::HFS(hfs_err) => write!(f, "a host fs error occured: {hfs_err}"),
             Self::PathBreaksOut => write!(f, "the path is invalid"),
-            Self::InvalidUTF8Path => write!(f, "the path is not valid a UTF-8 string"),
+            Self::UnsafeItem => write!(f, "the path leads to or through a symlink or a special file"),
             Self::NotEnoughStorage => write!(f, "you haven't got enough storage to store a file of such size"),
+            Self::TargetExists => write!(f, "an item at the target path already exists"),
+            Self::RecursiveTarget => write!(f, "an item can't be moved or copied into itself, nor replace a directory it is in"),
+            Self::NotAFile => write!(f, "the item is not a file"),
+        }
+    }
+}
+
+
+impl FSError {
+    /// a symlink which shows up only once the item is opened is the same as one found beforehand
+    fn from_hfs(err: std::io::Error) -> Self {
+        if pinned::is_symlink_error(&err) {
+            Self::UnsafeItem
+        } else {
+            Self::HFS(err)
         }
     }
 }
@@ -43,8 +87,26 @@ impl std::fmt::Display for FSError {
 pub type FSRes<T> = Result<T, FSError>;
 
 
+/// what to do when the target of a move or a copy already exists
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// replace the existing item, which is only removed once the replacement is in place
+    Overwrite,
+    /// pick a free name next to the existing item, like "file (1).txt"
+    Rename,
+    #[default]
+    Fail,
+}
+
+
 impl Filesystem {
-    pub fn new(storage_path: &Path, template_path: Option<&Path>, total_size: Option<u64>, userspace_size: Option<u64>) -> Self {
+    /// where the temporary items are kept, it is out of reach of any user
+    pub const STAGING_DIR_NAME: &'static str = ".tmp";
+    /// where the files attached to messages are kept, named by their keys
+    pub const ATTACHMENTS_DIR_NAME: &'static str = ".attachments";
+
+    pub fn new(conn_pool: db::ConnPool, storage_path: &Path, templates: HashMap<String, Template>, default_template: Option<String>, total_size: Option<u64>, userspace_size: Option<u64>) -> Self {
         log::debug!("initializing fs...");
         
         if !storage_path.exists() {
@@ -54,39 +116,50 @@ impl Filesystem {
         };
         
         Self {
-            userspace_size, total_size,
+            conn_pool, userspace_size, total_size, default_template,
             storage_path: storage_path.canonicalize().unwrap(),
-            template_path: template_path.map(|p| {
-                if !p.is_dir() {
-                    panic!("template path must be a path to an existing directory")
+            templates: templates.into_iter().map(|(name, template)| {
+                if !template.path.is_dir() {
+                    panic!("path of template '{name}' must be a path to an existing directory")
                 };
 
-                // todo remove such requirement
-                // todo check if p is under and relative to storage_path as well
-                
-                p.to_path_buf()
-            }),
+                (name, Template { path: template.path.canonicalize().unwrap(), ..template })
+            }).collect(),
+            mime_cache: mime::MimeCache::default(),
         }
     }
     
+    #[allow(dead_code)]
     pub fn storage_path(&self) -> &Path {
         &self.storage_path
     }
     
-    pub fn template_path(&self) -> Option<&Path> {
-        self.template_path.as_deref()
+    pub fn template(&self, name: &str) -> Option<&Template> {
+        self.templates.get(name)
+    }
+
+    pub fn default_template(&self) -> Option<&str> {
+        self.default_template.as_deref()
     }
     
     pub fn userspace_size(&self) -> Option<u64> {
         self.userspace_size
     }
     
+    #[allow(dead_code)]
     pub fn total_size(&self) -> Option<u64> {
         self.total_size
     }
 
     pub async fn create_dir(&self, path: &Path) -> FSRes<()> {
-        tokio::fs::create_dir(self.construct_path(path)?).await.map_err(FSError::HFS)?;
+        let path = self.construct_path(path)?;
+        let now = Utc::now().naive_utc();
+
+        let pinned = PinnedItem::new(&path).map_err(FSError::from_hfs)?;
+        tokio::fs::create_dir(&pinned.path).await.map_err(FSError::HFS)?;
+
+        self.record_items(vec![path], now).await;
+
         Ok(())
     }
     
@@ -94,14 +167,18 @@ impl Filesystem {
         let mut files = Vec::new();
         let mut directories = Vec::new();
         
-        let mut dir_iter = tokio::fs::read_dir(self.construct_path(path)?).await.map_err(FSError::HFS)?;
+        let path = self.construct_path(path)?;
+        let dir = PinnedDir::open(&path).map_err(FSError::from_hfs)?;
+        
+        let mut dir_iter = tokio::fs::read_dir(dir.path()).await.map_err(FSError::HFS)?;
         while let Some(item) = dir_iter.next_entry().await.map_err(FSError::HFS)? {
-            let item_path = item.path();
+            let file_type = item.file_type().await.map_err(FSError::HFS)?;
             
-            if item_path.is_dir() {
-                directories.push(item_path);
-            } else {
-                files.push(item_path);
+            // symlinks and special files are not accessible anyway, so they're not listed
+            if file_type.is_dir() {
+                directories.push(path.join(item.file_name()));
+            } else if file_type.is_file() {
+                files.push(path.join(item.file_name()));
             };
         };
         
@@ -115,32 +192,80 @@ impl Filesystem {
             }
         };
         
-        tokio::fs::write(self.construct_path(path)?, data).await.map_err(FSError::HFS)?;
+        let path = self.construct_path(path)?;
+        let pinned = PinnedItem::new(&path).map_err(FSError::from_hfs)?;
+        let is_new = !item_exists(&pinned.path);
+        let now = Utc::now().naive_utc();
+
+        let file = pinned.open(OpenOptions::new().write(true).create(true).truncate(true)).map_err(FSError::from_hfs)?;
+        tokio::fs::File::from_std(file).write_all(data).await.map_err(FSError::HFS)?;
+
+        if is_new {
+            self.record_items(vec![path], now).await;
+        };
+
         Ok(())
     }
 
     pub async fn remove_item(&self, path: &Path) -> FSRes<()> {
         let path = self.construct_path(path)?;
+        let pinned = PinnedItem::new(&path).map_err(FSError::from_hfs)?;
 
-        if path.is_file() {
-            tokio::fs::remove_file(path).await.map_err(FSError::HFS)?;
+        // neither of them follows a symlink, it would be the link itself which gets removed
+        if pinned.symlink_metadata().map_err(FSError::HFS)?.is_dir() {
+            tokio::fs::remove_dir_all(&pinned.path).await.map_err(FSError::HFS)?;
         } else {
-            tokio::fs::remove_dir_all(path).await.map_err(FSError::HFS)?;
+            tokio::fs::remove_file(&pinned.path).await.map_err(FSError::HFS)?;
         };
 
+        self.mime_cache.forget(&path);
+
+        let key = self.item_key(&path);
+        self.with_conn(move |conn| db::FSItem::delete_tree(conn, &key)).await;
+
         Ok(())
     }
 
-    pub async fn move_item(&self, source: &Path, target: &Path) -> FSRes<()> {
-        tokio::fs::rename(
-            self.construct_path(source)?,
-            self.construct_path(target)?
-        ).await.map_err(FSError::HFS)?;
-        Ok(())
+    /// removes the whole storage of a user, if there is any
+    pub async fn remove_user_dir(&self, user_id: i32) -> FSRes<()> {
+        match self.remove_item(user_id.to_string().as_ref()).await {
+            Err(FSError::HFS(err)) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            res => res,
+        }
+    }
+
+    /// returns the path the item has ended up at
+    pub async fn move_item(&self, source: &Path, target: &Path, conflict: ConflictPolicy) -> FSRes<PathBuf> {
+        let source = self.construct_path(source)?;
+        tokio::fs::symlink_metadata(&source).await.map_err(FSError::HFS)?;
+
+        let target = self.prepare_target(&source, self.construct_path(target)?, conflict).await?;
+
+        if target == source {
+            return Ok(target);
+        };
+
+        let replaced = self.set_aside(&target).await?;
+        let res = async {
+            let (pinned_source, pinned_target) = (PinnedItem::new(&source), PinnedItem::new(&target));
+            let (pinned_source, pinned_target) = (pinned_source.map_err(FSError::from_hfs)?, pinned_target.map_err(FSError::from_hfs)?);
+
+            tokio::fs::rename(&pinned_source.path, &pinned_target.path).await.map_err(FSError::HFS)
+        }.await;
+        self.settle_replaced(replaced, &target, res).await?;
+
+        self.mime_cache.forget(&source);
+
+        let (source_key, target_key) = (self.item_key(&source), self.item_key(&target));
+        self.with_conn(move |conn| db::FSItem::move_tree(conn, &source_key, &target_key)).await;
+
+        Ok(target)
     }
 
-    pub async fn copy_item(&self, source: &Path, target: &Path) -> FSRes<()> {
+    /// returns the path the copy has ended up at
+    pub async fn copy_item(&self, source: &Path, target: &Path, conflict: ConflictPolicy) -> FSRes<PathBuf> {
         let source = self.construct_path(source)?;
+        let is_file = tokio::fs::symlink_metadata(&source).await.map_err(FSError::HFS)?.is_file();
 
         if let Some(total_size) = self.total_size {
             if self.get_item_size(".".as_ref()).await? + self.get_item_size(&source).await? > total_size {
@@ -148,88 +273,196 @@ impl Filesystem {
             }
         };
 
-        let target = self.construct_path(target)?;
+        let target = self.prepare_target(&source, self.construct_path(target)?, conflict).await?;
 
-        if source.is_file() {
-            tokio::fs::copy(source, target).await.map_err(FSError::HFS)?;
-        } else {
-            let mut source = source;
+        if target == source {
+            return Err(FSError::RecursiveTarget);
+        };
+
+        let now = Utc::now().naive_utc();
+        let replaced = self.set_aside(&target).await?;
 
-            source.push("**");
-            source.push("*");
+        let res = {
+            let target = target.clone();
 
-            let base_path = self.storage_path.clone();  // xxx is there really not a better solution?
             tokio::task::spawn_blocking(move || {
-                for item in glob::glob(source.to_str().ok_or(FSError::InvalidUTF8Path)?).unwrap().filter_map(Result::ok) {
-                    let target = target.join(item.strip_prefix(&base_path).unwrap());
+                if is_file {
+                    pinned::copy_file(&source, &target)?;
+                    return Ok(vec![target]);
+                };
 
-                    if item.is_file() {
-                        let parent = target.parent().unwrap();
-                        if !parent.exists() {
-                            std::fs::create_dir_all(parent).unwrap();
-                        };
+                create_dir_pinned(&target)?;
 
-                        std::fs::copy(item, target).map_err(FSError::HFS)?;
+                let mut created = vec![target.clone()];
+
+                for (item, file_type) in walk_tree_typed(&source)? {
+                    // the subtree is kept relative to the root of the source
+                    let item_target = target.join(item.strip_prefix(&source).unwrap());
+
+                    if file_type.is_dir() {
+                        create_dir_pinned(&item_target)?;
                     } else {
-                        std::fs::create_dir_all(target).unwrap();
+                        pinned::copy_file(&item, &item_target)?;
                     };
+
+                    created.push(item_target);
                 };
 
-                Ok(())
-            }).await.unwrap()?;
+                Ok(created)
+            }).await.unwrap().map_err(FSError::from_hfs)
         };
 
-        Ok(())
+        let created = self.settle_replaced(replaced, &target, res).await?;
+
+        self.record_items(created, now).await;
+
+        Ok(target)
     }
 
     pub async fn read_file(&self, path: &Path) -> FSRes<Vec<u8>> {
-        tokio::fs::read(self.construct_path(path)?).await.map_err(FSError::HFS)
+        let file = pinned::open_file(&self.construct_path(path)?, OpenOptions::new().read(true)).map_err(FSError::from_hfs)?;
+
+        let mut data = Vec::new();
+        tokio::fs::File::from_std(file).read_to_end(&mut data).await.map_err(FSError::HFS)?;
+
+        Ok(data)
     }
 
     pub async fn get_item_size(&self, path: &Path) -> FSRes<u64> {
         let path = self.construct_path(path)?;
+        let metadata = PinnedItem::new(&path).and_then(|pinned| pinned.symlink_metadata()).map_err(FSError::from_hfs)?;
 
-        if path.is_file() {
-            Ok(tokio::fs::metadata(path).await.map_err(FSError::HFS)?.len())
+        if !metadata.is_dir() {
+            Ok(metadata.len())
         } else {
             tokio::task::spawn_blocking(move || {
-                let mut path = path;
-
-                path.push("**");
-                path.push("*");
-
-                Ok(glob::glob(path.to_str().ok_or(FSError::InvalidUTF8Path)?).unwrap()
-                    .filter_map(|p| p.ok())
-                    .fold(0, |total, p| total + p.metadata().unwrap().len()))
-            }).await.unwrap()
+                walk_tree(&path)?.into_iter()
+                    .try_fold(0, |total, p| Ok(total + PinnedItem::new(&p)?.symlink_metadata()?.len()))
+            }).await.unwrap().map_err(FSError::from_hfs)
         }
     }
 
     /// returns: (created, modified)
     pub async fn get_item_time_info(&self, path: &Path) -> FSRes<(SystemTime, SystemTime)> {  // xxx or should it be chrono::DateTime?
-        let metadata = self.get_item_metadata(&self.construct_path(path)?).await?;
+        let path = self.construct_path(path)?;
+        let metadata = self.get_item_metadata(&path).await?;
+        let modified = metadata.modified().map_err(FSError::HFS)?;
 
-        Ok((
-            metadata.created().map_err(FSError::HFS)?,
-            metadata.modified().map_err(FSError::HFS)?
-        ))
+        let key = self.item_key(&path);
+        let created = match self.with_conn(move |conn| db::FSItem::get(conn, &key)).await {
+            Some(item) => SystemTime::from(item.creation_time.and_utc()),
+            None => {
+                // lots of host filesystems do not keep the birth time, so mtime is the best we've got.
+                // it's recorded right away, so that it wouldn't change on the next modification
+                let created = metadata.created().unwrap_or(modified);
+
+                self.record_items(vec![path], chrono::DateTime::<Utc>::from(created).naive_utc()).await;
+
+                created
+            }
+        };
+
+        Ok((created, modified))
     }
 
     pub async fn get_mime(&self, path: &Path) -> FSRes<Option<String>> {
-        Ok(mime_guess::from_path(self.construct_path(path)?).first().map(|mm| mm.to_string()))
+        self.mime_cache.get_mime(&self.construct_path(path)?).await
     }
     
     pub async fn get_dir_tree(&self, path: &Path) -> FSRes<Vec<PathBuf>> {
-        let mut path = self.construct_path(path)?;
+        let path = self.construct_path(path)?;
 
-        path.push("**");
-        path.push("*");
+        tokio::task::spawn_blocking(move || walk_tree(&path)).await.unwrap().map_err(FSError::from_hfs)
+    }
 
-        tokio::task::spawn_blocking(move || {
-            Ok(glob::glob(path.to_str().ok_or(FSError::InvalidUTF8Path)?).unwrap()
-                .filter_map(|p| p.ok())
-                .collect())
-        }).await.unwrap()
+    /// resolves a conflict with an existing item at the target, and makes sure that the source wouldn't end up inside itself.
+    /// WARNING: EXPECTS ALREADY CONSTRUCTED PATHS
+    async fn prepare_target(&self, source: &Path, target: PathBuf, conflict: ConflictPolicy) -> FSRes<PathBuf> {
+        let target = if !item_exists(&target) {
+            target
+        } else {
+            match conflict {
+                ConflictPolicy::Fail => return Err(FSError::TargetExists),
+                ConflictPolicy::Rename => find_free_path(&target),
+                ConflictPolicy::Overwrite => target,
+            }
+        };
+
+        // it's up to the caller to decide what it means for the item to end up in the same place
+        if target == source {
+            return Ok(target);
+        };
+
+        if target.starts_with(source) || source.starts_with(&target) {
+            return Err(FSError::RecursiveTarget);
+        };
+
+        Ok(target)
+    }
+
+    /// moves an item which is about to be overwritten out of the way, so that it could be put back if the operation fails.
+    /// WARNING: EXPECTS AN ALREADY CONSTRUCTED PATH
+    async fn set_aside(&self, target: &Path) -> FSRes<Option<PathBuf>> {
+        if !item_exists(target) {
+            return Ok(None);
+        };
+
+        let aside = self.create_staging_dir().await?.join("replaced");
+
+        // the move itself never has anything to set aside, it's boxed only because of the recursion
+        Box::pin(self.move_item(target, &aside, ConflictPolicy::Fail)).await.map(Some)
+    }
+
+    /// removes the item which was set aside once the operation has succeeded, or puts it back if it has failed.
+    /// WARNING: EXPECTS ALREADY CONSTRUCTED PATHS
+    async fn settle_replaced<T>(&self, replaced: Option<PathBuf>, target: &Path, res: FSRes<T>) -> FSRes<T> {
+        let Some(replaced) = replaced else {
+            return res;
+        };
+
+        if res.is_err() {
+            // whatever the operation has left behind makes way for the original item
+            if item_exists(target) {
+                self.remove_item(target).await?;
+            };
+
+            Box::pin(self.move_item(&replaced, target, ConflictPolicy::Fail)).await?;
+        };
+
+        let staging_path = replaced.parent().expect("the item is set aside in a staging directory");
+        if let Err(err) = self.remove_item(staging_path).await {
+            log::warn!("failed to clean up staging directory {}: {err}", staging_path.display());
+        };
+
+        res
+    }
+
+    /// copies the items of a template, which the user hasn't been given yet, into the target directory.
+    /// whatever they have removed since is not given again.
+    /// returns the version of the deployed template
+    pub async fn deploy_template(&self, name: &str, user_id: i32, target: &Path) -> Option<FSRes<i32>> {
+        let template = self.templates.get(name)?;
+
+        Some(async {
+            let target = self.construct_path(target)?;
+            let source = template.path.clone();
+            let now = Utc::now().naive_utc();
+
+            let delivered = self.with_conn(move |conn| db::TemplateDelivery::get_all_paths_of_user(conn, user_id)).await
+                .into_iter()
+                .map(PathBuf::from)
+                .collect::<HashSet<_>>();
+
+            let (created, delivered) = tokio::task::spawn_blocking(move || copy_missing_tree(&source, &target, &delivered))
+                .await.unwrap().map_err(FSError::from_hfs)?;
+
+            self.record_items(created, now).await;
+
+            let delivered = delivered.iter().map(|p| p.to_string_lossy().to_string()).collect::<Vec<_>>();
+            self.with_conn(move |conn| db::TemplateDelivery::record(conn, user_id, &delivered)).await;
+
+            Ok(template.version)
+        }.await)
     }
 
     /// WARNING: EXPECTS AN ALREADY CONSTRUCTED PATH
@@ -244,11 +477,191 @@ impl Filesystem {
             return Err(FSError::PathBreaksOut)
         };
 
+        self.check_item_safety(&final_path)?;
+
         Ok(final_path)
     }
 
+    /// the normalization is only lexical, so a symlink anywhere along the path could still lead outside the storage.
+    /// WARNING: EXPECTS AN ALREADY CONSTRUCTED PATH
+    fn check_item_safety(&self, final_path: &Path) -> FSRes<()> {
+        let mut current = self.storage_path.clone();
+
+        for component in final_path.strip_prefix(&self.storage_path).unwrap().components() {
+            current.push(component);
+
+            match std::fs::symlink_metadata(&current) {
+                Ok(metadata) if !is_regular_item(metadata.file_type()) => return Err(FSError::UnsafeItem),
+                Ok(_) => {},
+                // the rest of the path doesn't exist (or can't be looked at), so the operation itself will deal with it
+                Err(_) => break,
+            };
+        };
+
+        Ok(())
+    }
+
+    /// WARNING: EXPECTS AN ALREADY CONSTRUCTED PATH
+    fn item_key(&self, final_path: &Path) -> String {
+        final_path.strip_prefix(&self.storage_path).unwrap().to_string_lossy().to_string()
+    }
+
+    /// WARNING: EXPECTS ALREADY CONSTRUCTED PATHS
+    async fn record_items(&self, paths: Vec<PathBuf>, creation_time: NaiveDateTime) {
+        let keys = paths.iter().map(|p| self.item_key(p)).collect::<Vec<_>>();
+
+        self.with_conn(move |conn| db::FSItem::record(conn, &keys, creation_time)).await;
+    }
+
+    async fn with_conn<T, F>(&self, f: F) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut SqliteConnection) -> T + Send + 'static
+    {
+        let conn_pool = self.conn_pool.clone();
+
+        tokio::task::spawn_blocking(move || f(&mut conn_pool.get().unwrap())).await.unwrap()
+    }
+
+    async fn create_staging_dir(&self) -> FSRes<PathBuf> {
+        let path = Path::new(Self::STAGING_DIR_NAME).join(uuid::Uuid::new_v4().to_string());
+
+        tokio::fs::create_dir_all(self.construct_path(&path)?).await.map_err(FSError::HFS)?;
+
+        Ok(path)
+    }
+
+    /// copies an item as is, without any storage checks and without it being recorded
+    async fn snapshot_item(&self, source: &Path, target: &Path) -> FSRes<()> {
+        let source = self.construct_path(source)?;
+        let target = self.construct_path(target)?;
+
+        tokio::task::spawn_blocking(move || {
+            if PinnedItem::new(&source)?.symlink_metadata()?.is_dir() {
+                copy_missing_tree(&source, &target, &HashSet::new()).map(|_| ())
+            } else {
+                pinned::copy_file(&source, &target).map(|_| ())
+            }
+        }).await.unwrap().map_err(FSError::from_hfs)
+    }
+
     // xxx should it be public?
     async fn get_item_metadata(&self, path: &Path) -> FSRes<Metadata> {
-        tokio::fs::metadata(self.construct_path(path)?).await.map_err(FSError::HFS)
+        PinnedItem::new(&self.construct_path(path)?)
+            .and_then(|pinned| pinned.symlink_metadata())
+            .map_err(FSError::from_hfs)
     }
 }
+
+
+fn item_exists(path: &Path) -> bool {
+    std::fs::symlink_metadata(path).is_ok()
+}
+
+
+/// "file.txt" becomes "file (1).txt", or "file (2).txt" if that one is taken too, and so on
+fn find_free_path(path: &Path) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().map(|ext| format!(".{}", ext.to_string_lossy())).unwrap_or_default();
+
+    (1..)
+        .map(|i| path.with_file_name(format!("{stem} ({i}){extension}")))
+        .find(|p| !item_exists(p))
+        .expect("there is always a free name")
+}
+
+
+fn is_regular_item(file_type: FileType) -> bool {
+    file_type.is_file() || file_type.is_dir()
+}
+
+
+/// lists everything under a directory in a top-down order.
+/// symlinks are not followed, and they're skipped just like the special files are
+fn walk_tree(path: &Path) -> std::io::Result<Vec<PathBuf>> {
+    Ok(walk_tree_typed(path)?.into_iter().map(|(item, _)| item).collect())
+}
+
+
+/// the same as `walk_tree`, along with what each of the items is
+fn walk_tree_typed(path: &Path) -> std::io::Result<Vec<(PathBuf, FileType)>> {
+    // the directory is read through its handle, so that it couldn't be swapped for a symlink once it's been checked
+    let dir = PinnedDir::open(path)?;
+
+    let mut entries = std::fs::read_dir(dir.path())?.collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+
+    let mut items = Vec::new();
+
+    for entry in entries {
+        let file_type = entry.file_type()?;
+
+        if !is_regular_item(file_type) {
+            continue;
+        };
+
+        let item = path.join(entry.file_name());
+        items.push((item.clone(), file_type));
+
+        if file_type.is_dir() {
+            items.append(&mut walk_tree_typed(&item)?);
+        };
+    };
+
+    Ok(items)
+}
+
+
+/// WARNING: EXPECTS AN ALREADY CONSTRUCTED PATH
+fn create_dir_pinned(path: &Path) -> std::io::Result<()> {
+    std::fs::create_dir(&PinnedItem::new(path)?.path)
+}
+
+
+/// copies the items of the source tree, which are missing in the target one and weren't delivered before.
+/// if a delivered directory is missing, everything under it is skipped as well.
+/// returns: (created items, newly delivered items relative to the source)
+fn copy_missing_tree(source: &Path, target: &Path, delivered: &HashSet<PathBuf>) -> std::io::Result<(Vec<PathBuf>, Vec<PathBuf>)> {
+    let mut created = Vec::new();
+    let mut newly_delivered = Vec::new();
+    let mut removed_dirs = Vec::<PathBuf>::new();
+
+    if !item_exists(target) {
+        create_dir_pinned(target)?;
+        created.push(target.to_path_buf());
+    };
+
+    for (item, file_type) in walk_tree_typed(source)? {
+        let relative = item.strip_prefix(source).unwrap();
+        let item_target = target.join(relative);
+
+        if removed_dirs.iter().any(|d| relative.starts_with(d)) {
+            continue;
+        };
+
+        if delivered.contains(relative) {
+            if file_type.is_dir() && !item_exists(&item_target) {
+                removed_dirs.push(relative.to_path_buf());
+            };
+
+            continue;
+        };
+
+        // an item the user already has counts as delivered too
+        newly_delivered.push(relative.to_path_buf());
+
+        if item_exists(&item_target) {
+            continue;
+        };
+
+        if file_type.is_dir() {
+            create_dir_pinned(&item_target)?;
+        } else {
+            pinned::copy_file(&item, &item_target)?;
+        };
+
+        created.push(item_target);
+    };
+
+    Ok((created, newly_delivered))
+}
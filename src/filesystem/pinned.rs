@@ -0,0 +1,160 @@
+// the paths are checked for symlinks beforehand, but one could be swapped in right after the check.
+// so the items are reached through an open handle of their parent directory instead, which doesn't follow
+// a symlink, and which is checked to be where it should be on the handle itself.
+// it relies on /proc/self/fd, so it's linux only
+use std::fs::{File, OpenOptions};
+use std::io::{Error, ErrorKind};
+use std::os::fd::AsRawFd;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+
+/// a directory which is held open, so that the items in it could be reached without resolving its path again
+pub(super) struct PinnedDir {
+    handle: File,
+}
+
+
+impl PinnedDir {
+    /// fails with ELOOP if the path leads through a symlink.
+    /// WARNING: EXPECTS AN ALREADY CONSTRUCTED PATH
+    pub fn open(path: &Path) -> std::io::Result<Self> {
+        let handle = OpenOptions::new()
+            .read(true)
+            .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+            .open(path)?;
+
+        // not O_DIRECTORY, as with it a symlink fails with ENOTDIR instead of ELOOP,
+        // and O_NONBLOCK so that a fifo in place of the directory doesn't hang
+        if !handle.metadata()?.is_dir() {
+            return Err(Error::from_raw_os_error(libc::ENOTDIR));
+        };
+
+        let pinned = Self { handle };
+
+        // a symlink further up would have been followed, and then the directory is somewhere else
+        if std::fs::read_link(pinned.path())? != path {
+            return Err(Error::from_raw_os_error(libc::ELOOP));
+        };
+
+        Ok(pinned)
+    }
+
+    /// leads to this very directory for as long as it's open
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from(format!("/proc/self/fd/{}", self.handle.as_raw_fd()))
+    }
+}
+
+
+/// an item reached through its pinned parent directory.
+/// the path is only valid as long as this is kept
+pub(super) struct PinnedItem {
+    _parent: PinnedDir,
+    pub path: PathBuf,
+}
+
+
+impl PinnedItem {
+    /// WARNING: EXPECTS AN ALREADY CONSTRUCTED PATH
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return Err(Error::new(ErrorKind::InvalidInput, "the item has no parent"));
+        };
+
+        let parent = PinnedDir::open(parent)?;
+
+        Ok(Self { path: parent.path().join(name), _parent: parent })
+    }
+
+    pub fn symlink_metadata(&self) -> std::io::Result<std::fs::Metadata> {
+        std::fs::symlink_metadata(&self.path)
+    }
+
+    /// the file itself can't be a symlink either
+    pub fn open(&self, options: &mut OpenOptions) -> std::io::Result<File> {
+        options.custom_flags(libc::O_NOFOLLOW).open(&self.path)
+    }
+}
+
+
+/// opens a file without following any symlinks on the way.
+/// WARNING: EXPECTS AN ALREADY CONSTRUCTED PATH
+pub(super) fn open_file(path: &Path, options: &mut OpenOptions) -> std::io::Result<File> {
+    PinnedItem::new(path)?.open(options)
+}
+
+
+/// the same as `std::fs::copy`, but without following any symlinks and without overwriting the target.
+/// returns the number of copied bytes.
+/// WARNING: EXPECTS ALREADY CONSTRUCTED PATHS
+pub(super) fn copy_file(source: &Path, target: &Path) -> std::io::Result<u64> {
+    let mut source = open_file(source, OpenOptions::new().read(true))?;
+    let mut target = open_file(target, OpenOptions::new().write(true).create_new(true))?;
+
+    std::io::copy(&mut source, &mut target)
+}
+
+
+pub(super) fn is_symlink_error(err: &Error) -> bool {
+    err.raw_os_error() == Some(libc::ELOOP)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::fs::OpenOptions;
+    use std::os::unix::fs::symlink;
+    use super::{copy_file, is_symlink_error, open_file, PinnedDir, PinnedItem};
+
+    #[test]
+    fn symlinks_are_not_followed() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+
+        std::fs::create_dir(base.join("real")).unwrap();
+        std::fs::write(base.join("real").join("file"), b"data").unwrap();
+        symlink(base.join("real"), base.join("dir_link")).unwrap();
+        symlink(base.join("real").join("file"), base.join("file_link")).unwrap();
+        // a symlink further up the path
+        std::fs::create_dir(base.join("real").join("nested")).unwrap();
+        let through_link = base.join("dir_link").join("nested");
+
+        for res in [
+            PinnedDir::open(&base.join("dir_link")).map(|_| ()),
+            PinnedDir::open(&through_link).map(|_| ()),
+            PinnedItem::new(&base.join("dir_link").join("file")).map(|_| ()),
+            open_file(&base.join("file_link"), OpenOptions::new().read(true)).map(|_| ()),
+            open_file(&base.join("file_link"), OpenOptions::new().write(true).truncate(true)).map(|_| ()),
+            copy_file(&base.join("file_link"), &base.join("copy")).map(|_| ()),
+            copy_file(&base.join("real").join("file"), &base.join("dir_link").join("copy")).map(|_| ()),
+        ] {
+            assert!(res.as_ref().is_err_and(is_symlink_error), "{res:?}");
+        };
+
+        assert_eq!(std::fs::read(base.join("real").join("file")).unwrap(), b"data");
+        assert!(!base.join("copy").exists());
+        assert!(!base.join("real").join("copy").exists());
+    }
+
+    #[test]
+    fn items_are_reached_through_the_pinned_parent() {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path().canonicalize().unwrap();
+
+        std::fs::create_dir(base.join("real")).unwrap();
+        std::fs::create_dir(base.join("elsewhere")).unwrap();
+        std::fs::write(base.join("elsewhere").join("file"), b"elsewhere").unwrap();
+
+        let item = PinnedItem::new(&base.join("real").join("file")).unwrap();
+
+        // the directory gets swapped for a symlink after it has been pinned
+        std::fs::rename(base.join("real"), base.join("moved")).unwrap();
+        symlink(base.join("elsewhere"), base.join("real")).unwrap();
+
+        item.open(OpenOptions::new().write(true).create(true).truncate(true)).unwrap();
+
+        assert!(base.join("moved").join("file").exists());
+        assert_eq!(std::fs::read(base.join("elsewhere").join("file")).unwrap(), b"elsewhere");
+    }
+}
@@ -2,7 +2,8 @@ use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::time::SystemTime;
 use normalize_path::NormalizePath;
-use super::{Filesystem, FSError, FSRes};
+use crate::db;
+use super::{batch, ConflictPolicy, Filesystem, FSError, FSOperation, FSOperationOutcome, FSRes, StoredAttachment};
 
 pub struct UserScopedFS<'a> {
     fs: &'a Filesystem,
@@ -12,15 +13,16 @@ pub struct UserScopedFS<'a> {
 
 
 impl<'a> UserScopedFS<'a> {
-    pub async fn new(fs: &'a Filesystem, user_id: i32) -> FSRes<Self> {
+    pub async fn new(fs: &'a Filesystem, user: &db::User) -> FSRes<Self> {
         let self_ = Self {
-            fs, user_id,
-            base_path: PathBuf::from_str(&user_id.to_string()).unwrap(),
+            fs,
+            user_id: user.id,
+            base_path: PathBuf::from_str(&user.id.to_string()).unwrap(),
         };
         
         // yes i know this is some very weird code
         if !fs.construct_path(&self_.construct_path(".".as_ref())?)?.exists() {
-            let td_res = self_.deploy_template().await;
+            let td_res = self_.deploy_template(user).await;
             
             if let Some(res) = td_res {
                 res?;
@@ -36,6 +38,7 @@ impl<'a> UserScopedFS<'a> {
         self.fs
     }
 
+    #[allow(dead_code)]
     pub fn user_id(&self) -> i32 {
         self.user_id
     }
@@ -56,7 +59,7 @@ impl<'a> UserScopedFS<'a> {
 
     pub async fn write_file(&self, path: &Path, data: &[u8]) -> FSRes<()> {
         if let Some(total_size) = self.fs.userspace_size() {
-            if self.get_item_size(".".as_ref()).await? + data.len() as u64 > total_size {
+            if self.get_used_size().await? + data.len() as u64 > total_size {
                 return Err(FSError::NotEnoughStorage);
             }
         };
@@ -68,24 +71,47 @@ impl<'a> UserScopedFS<'a> {
         self.fs.remove_item(&self.construct_path(path)?).await
     }
 
-    pub async fn move_item(&self, source: &Path, target: &Path) -> FSRes<()> {
-        self.fs.move_item(
+    /// returns the path the item has ended up at
+    pub async fn move_item(&self, source: &Path, target: &Path, conflict: ConflictPolicy) -> FSRes<PathBuf> {
+        let final_path = self.fs.move_item(
             &self.construct_path(source)?,
-            &self.construct_path(target)?
-        ).await
+            &self.construct_path(target)?,
+            conflict
+        ).await?;
+        
+        Ok(self.adapt_paths(vec![final_path]).await?.remove(0))
     }
 
-    pub async fn copy_item(&self, source: &Path, target: &Path) -> FSRes<()> {
+    /// returns the path the copy has ended up at
+    pub async fn copy_item(&self, source: &Path, target: &Path, conflict: ConflictPolicy) -> FSRes<PathBuf> {
         if let Some(total_size) = self.fs.userspace_size() {
-            if self.get_item_size(".".as_ref()).await? + self.get_item_size(source).await? > total_size {
+            if self.get_used_size().await? + self.get_item_size(source).await? > total_size {
                 return Err(FSError::NotEnoughStorage);
             }
         };
         
-        self.fs.copy_item(
+        let final_path = self.fs.copy_item(
             &self.construct_path(source)?,
-            &self.construct_path(target)?
-        ).await
+            &self.construct_path(target)?,
+            conflict
+        ).await?;
+        
+        Ok(self.adapt_paths(vec![final_path]).await?.remove(0))
+    }
+
+    /// validates all the paths and the storage beforehand, then executes the operations in order, checking the storage
+    /// once again before each of them. if any of them fails, the ones before it are reverted, and the ones after it are skipped
+    pub async fn run_batch(&self, operations: Vec<FSOperation>) -> FSRes<Vec<FSOperationOutcome>> {
+        let operations = operations.into_iter()
+            .map(|op| op.map_paths(|p| self.construct_path(p)))
+            .collect::<FSRes<Vec<_>>>()?;
+
+        let free_size = match self.fs.userspace_size() {
+            Some(total_size) => Some(total_size.saturating_sub(self.get_used_size().await?)),
+            None => None,
+        };
+
+        batch::run(self.fs, operations, free_size).await
     }
 
     pub async fn read_file(&self, path: &Path) -> FSRes<Vec<u8>> {
@@ -96,6 +122,47 @@ impl<'a> UserScopedFS<'a> {
         self.fs.get_item_size(&self.construct_path(path)?).await
     }
 
+    /// the user's directory along with the files the user has attached to their messages
+    pub async fn get_used_size(&self) -> FSRes<u64> {
+        let user_id = self.user_id;
+        let attachments_size = self.fs.with_conn(move |conn| db::MessageAttachment::get_total_size_sent_by(conn, user_id)).await;
+
+        Ok(self.get_item_size(".".as_ref()).await? + attachments_size)
+    }
+
+    /// snapshots the files into the attachment store, and if any of them fails, the already stored ones are removed
+    pub async fn store_attachments(&self, paths: &[PathBuf]) -> FSRes<Vec<StoredAttachment>> {
+        let paths = paths.iter()
+            .map(|p| self.construct_path(p))
+            .collect::<FSRes<Vec<_>>>()?;
+
+        if let Some(total_size) = self.fs.userspace_size() {
+            let mut required_size = 0;
+
+            for path in &paths {
+                required_size += self.fs.get_item_size(path).await?;
+            };
+
+            if self.get_used_size().await? + required_size > total_size {
+                return Err(FSError::NotEnoughStorage);
+            };
+        };
+
+        let mut attachments = Vec::with_capacity(paths.len());
+
+        for path in &paths {
+            match self.fs.store_attachment(path).await {
+                Ok(attachment) => attachments.push(attachment),
+                Err(err) => {
+                    self.fs.remove_attachments(&attachments.into_iter().map(|a| a.key).collect::<Vec<_>>()).await;
+                    return Err(err);
+                }
+            };
+        };
+
+        Ok(attachments)
+    }
+
     /// returns: (created, modified)
     pub async fn get_item_time_info(&self, path: &Path) -> FSRes<(SystemTime, SystemTime)> {
         self.fs.get_item_time_info(&self.construct_path(path)?).await
@@ -109,11 +176,20 @@ impl<'a> UserScopedFS<'a> {
         self.adapt_paths(self.fs.get_dir_tree(&self.construct_path(path)?).await?).await
     }
 
-    pub async fn deploy_template(&self) -> Option<FSRes<()>> {
-        Some(self.fs.copy_item(
-            self.fs.template_path.as_ref()?,
-            &self.base_path,
-        ).await)
+    /// copies the files of the user's template, which they haven't been given yet, and records the deployed version
+    pub async fn deploy_template(&self, user: &db::User) -> Option<FSRes<()>> {
+        let name = user.fs_template.clone()
+            .or_else(|| self.fs.default_template().map(str::to_string))?;
+        
+        let version = match self.fs.deploy_template(&name, self.user_id, &self.base_path).await? {
+            Ok(version) => version,
+            Err(err) => return Some(Err(err)),
+        };
+        
+        let user_id = self.user_id;
+        self.fs.with_conn(move |conn| db::User::set_fs_template(conn, user_id, &name, version)).await;
+        
+        Some(Ok(()))
     }
 
     /// WARNING: EXPECTS AN ALREADY CONSTRUCTED PATH
@@ -136,3 +212,280 @@ impl<'a> UserScopedFS<'a> {
         Ok(paths.into_iter().map(|p| p.strip_prefix(&true_base_path).unwrap().to_path_buf()).collect())
     }
 }
+
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::os::unix::fs::symlink;
+    use std::path::{Path, PathBuf};
+    use crate::db;
+    use super::super::{ConflictPolicy, Filesystem, FSError, FSOperation, FSOperationOutcome, FSRes};
+    use super::UserScopedFS;
+
+    const SECRET: &[u8] = b"secret";
+
+    struct Setup {
+        dir: tempfile::TempDir,
+        fs: Filesystem,
+        user: db::User,
+    }
+
+    impl Setup {
+        /// a user with a file and a directory of their own, and a file outside the storage they shouldn't reach.
+        /// there are symlinks to it inside the user's directory, both to the file and to its directory
+        async fn new() -> Self {
+            Self::with_userspace_size(None).await
+        }
+
+        async fn with_userspace_size(userspace_size: Option<u64>) -> Self {
+            let dir = tempfile::tempdir().unwrap();
+
+            let conn_pool = db::create_db_connection_pool(dir.path().join("db.sqlite3").to_str().unwrap(), 2);
+            db::migrate(&mut conn_pool.get().unwrap());
+            let user = db::User::create(&mut conn_pool.get().unwrap(), "user", "password", None, None).unwrap();
+
+            let fs = Filesystem::new(conn_pool, &dir.path().join("storage"), HashMap::new(), None, None, userspace_size);
+
+            let outside = dir.path().join("outside");
+            std::fs::create_dir(&outside).unwrap();
+            std::fs::write(outside.join("secret"), SECRET).unwrap();
+
+            let setup = Self { dir, fs, user };
+            let usfs = setup.usfs().await;
+            usfs.write_file("own".as_ref(), b"own").await.unwrap();
+            usfs.create_dir("dir".as_ref()).await.unwrap();
+
+            let user_dir = setup.user_dir();
+            symlink(outside.join("secret"), user_dir.join("file_link")).unwrap();
+            symlink(&outside, user_dir.join("dir_link")).unwrap();
+            symlink(&outside, user_dir.join("dir").join("nested_link")).unwrap();
+
+            setup
+        }
+
+        async fn usfs(&self) -> UserScopedFS<'_> {
+            UserScopedFS::new(&self.fs, &self.user).await.unwrap()
+        }
+
+        fn user_dir(&self) -> PathBuf {
+            self.fs.storage_path().join(self.user.id.to_string())
+        }
+
+        fn outside(&self) -> PathBuf {
+            self.dir.path().canonicalize().unwrap().join("outside")
+        }
+
+        /// nothing outside has been read into, changed, or added to
+        fn assert_outside_untouched(&self) {
+            assert_eq!(std::fs::read(self.outside().join("secret")).unwrap(), SECRET);
+
+            let mut names = std::fs::read_dir(self.outside()).unwrap()
+                .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+                .collect::<Vec<_>>();
+            names.sort();
+            assert_eq!(names, ["secret"]);
+        }
+    }
+
+    /// `..`, absolute paths, a symlink to a file outside, and paths through symlinked directories
+    fn escaping_paths(setup: &Setup) -> Vec<PathBuf> {
+        vec![
+            PathBuf::from(".."),
+            PathBuf::from("../outside/secret"),
+            PathBuf::from("dir/../../../outside/secret"),
+            setup.outside().join("secret"),
+            PathBuf::from("file_link"),
+            PathBuf::from("dir_link"),
+            PathBuf::from("dir_link/secret"),
+            PathBuf::from("dir/nested_link/secret"),
+        ]
+    }
+
+    #[track_caller]
+    fn assert_refused<T: std::fmt::Debug>(res: FSRes<T>, path: &Path) {
+        match res {
+            Err(FSError::PathBreaksOut | FSError::UnsafeItem) => {},
+            res => panic!("{} was not refused: {res:?}", path.display()),
+        };
+    }
+
+    #[tokio::test]
+    async fn read_refuses_escaping_paths() {
+        let setup = Setup::new().await;
+        let usfs = setup.usfs().await;
+
+        for path in escaping_paths(&setup) {
+            assert_refused(usfs.read_file(&path).await, &path);
+            assert_refused(usfs.get_mime(&path).await, &path);
+            assert_refused(usfs.get_item_time_info(&path).await, &path);
+        };
+
+        assert_eq!(usfs.read_file("own".as_ref()).await.unwrap(), b"own");
+    }
+
+    #[tokio::test]
+    async fn write_refuses_escaping_paths() {
+        let setup = Setup::new().await;
+        let usfs = setup.usfs().await;
+
+        for path in escaping_paths(&setup) {
+            assert_refused(usfs.write_file(&path, b"overwritten").await, &path);
+            assert_refused(usfs.write_file(&path.join("new"), b"new").await, &path);
+            assert_refused(usfs.create_dir(&path.join("new")).await, &path);
+        };
+
+        setup.assert_outside_untouched();
+    }
+
+    #[tokio::test]
+    async fn rename_refuses_escaping_paths() {
+        let setup = Setup::new().await;
+        let usfs = setup.usfs().await;
+
+        for path in escaping_paths(&setup) {
+            assert_refused(usfs.move_item(&path, "moved".as_ref(), ConflictPolicy::Fail).await, &path);
+            assert_refused(usfs.move_item("own".as_ref(), &path.join("moved"), ConflictPolicy::Fail).await, &path);
+            assert_refused(usfs.move_item("own".as_ref(), &path, ConflictPolicy::Overwrite).await, &path);
+        };
+
+        assert!(!setup.user_dir().join("moved").exists());
+        assert!(setup.user_dir().join("own").exists());
+        setup.assert_outside_untouched();
+    }
+
+    #[tokio::test]
+    async fn copy_refuses_escaping_paths() {
+        let setup = Setup::new().await;
+        let usfs = setup.usfs().await;
+
+        for path in escaping_paths(&setup) {
+            assert_refused(usfs.copy_item(&path, "copied".as_ref(), ConflictPolicy::Fail).await, &path);
+            assert_refused(usfs.copy_item("own".as_ref(), &path.join("copied"), ConflictPolicy::Fail).await, &path);
+            assert_refused(usfs.copy_item("own".as_ref(), &path, ConflictPolicy::Overwrite).await, &path);
+        };
+
+        assert!(!setup.user_dir().join("copied").exists());
+        setup.assert_outside_untouched();
+    }
+
+    #[tokio::test]
+    async fn copy_of_a_directory_skips_the_symlinks_in_it() {
+        let setup = Setup::new().await;
+        let usfs = setup.usfs().await;
+
+        usfs.copy_item("dir".as_ref(), "copied".as_ref(), ConflictPolicy::Fail).await.unwrap();
+
+        assert!(std::fs::read_dir(setup.user_dir().join("copied")).unwrap().next().is_none());
+    }
+
+    #[tokio::test]
+    async fn delete_refuses_escaping_paths() {
+        let setup = Setup::new().await;
+        let usfs = setup.usfs().await;
+
+        for path in escaping_paths(&setup) {
+            assert_refused(usfs.remove_item(&path).await, &path);
+        };
+
+        setup.assert_outside_untouched();
+    }
+
+    #[tokio::test]
+    async fn list_refuses_escaping_paths_and_hides_symlinks() {
+        let setup = Setup::new().await;
+        let usfs = setup.usfs().await;
+
+        for path in escaping_paths(&setup) {
+            assert_refused(usfs.list_dir(&path).await, &path);
+            assert_refused(usfs.get_dir_tree(&path).await, &path);
+            assert_refused(usfs.get_item_size(&path).await, &path);
+        };
+
+        let (files, directories) = usfs.list_dir(".".as_ref()).await.unwrap();
+        assert_eq!(files, [PathBuf::from("own")]);
+        assert_eq!(directories, [PathBuf::from("dir")]);
+
+        assert_eq!(usfs.get_dir_tree(".".as_ref()).await.unwrap(), [PathBuf::from("dir"), PathBuf::from("own")]);
+    }
+
+    #[tokio::test]
+    async fn batch_checks_storage() {
+        // "own" takes 3 bytes, so there is room for two more copies of it
+        let setup = Setup::with_userspace_size(Some(9)).await;
+        let usfs = setup.usfs().await;
+        // the size of a directory itself depends on the host fs
+        std::fs::remove_dir_all(setup.user_dir().join("dir")).unwrap();
+
+        let copy = |source: &str, target: &str, conflict| FSOperation::Copy { source: source.into(), target: target.into(), conflict };
+        let move_ = |source: &str, target: &str, conflict| FSOperation::Move { source: source.into(), target: target.into(), conflict };
+
+        let res = usfs.run_batch(vec![copy("own", "a", ConflictPolicy::Fail), copy("own", "b", ConflictPolicy::Fail), copy("own", "c", ConflictPolicy::Fail)]).await;
+        assert!(matches!(res, Err(FSError::NotEnoughStorage)), "{res:?}");
+        assert!(!setup.user_dir().join("a").exists());
+
+        // the later operations work with the items made by the earlier ones, so they are checked once again.
+        // an overwritten target is snapshotted, and neither it nor a removed item is let go of until the end
+        for operations in [
+            vec![copy("own", "a", ConflictPolicy::Fail), copy("a", "b", ConflictPolicy::Fail), copy("b", "c", ConflictPolicy::Fail)],
+            vec![copy("own", "a", ConflictPolicy::Fail), copy("own", "b", ConflictPolicy::Fail), move_("own", "a", ConflictPolicy::Overwrite)],
+            vec![copy("own", "a", ConflictPolicy::Fail), FSOperation::Remove { path: "own".into() }, copy("a", "b", ConflictPolicy::Fail), copy("a", "c", ConflictPolicy::Fail)],
+        ] {
+            let outcomes = usfs.run_batch(operations).await.unwrap();
+
+            assert!(matches!(outcomes.last(), Some(FSOperationOutcome::Failed(FSError::NotEnoughStorage))), "{outcomes:?}");
+            assert_eq!(usfs.get_used_size().await.unwrap(), 3);
+        };
+
+        let outcomes = usfs.run_batch(vec![copy("own", "a", ConflictPolicy::Fail), copy("a", "b", ConflictPolicy::Fail)]).await.unwrap();
+        assert!(matches!(outcomes[..], [FSOperationOutcome::Done, FSOperationOutcome::Done]), "{outcomes:?}");
+
+        // the snapshot of an existing target is counted beforehand as well
+        std::fs::remove_file(setup.user_dir().join("b")).unwrap();
+        let res = usfs.run_batch(vec![copy("own", "a", ConflictPolicy::Overwrite)]).await;
+        assert!(matches!(res, Err(FSError::NotEnoughStorage)), "{res:?}");
+
+        let outcomes = usfs.run_batch(vec![move_("own", "a", ConflictPolicy::Overwrite)]).await.unwrap();
+        assert!(matches!(outcomes[..], [FSOperationOutcome::Done]), "{outcomes:?}");
+    }
+
+    #[tokio::test]
+    async fn batch_refuses_escaping_paths() {
+        let setup = Setup::new().await;
+        let usfs = setup.usfs().await;
+
+        for path in escaping_paths(&setup) {
+            let operations = [
+                FSOperation::Copy { source: path.clone(), target: "copied".into(), conflict: ConflictPolicy::Fail },
+                FSOperation::Copy { source: "own".into(), target: path.join("copied"), conflict: ConflictPolicy::Fail },
+                FSOperation::Move { source: path.clone(), target: "moved".into(), conflict: ConflictPolicy::Fail },
+                FSOperation::Move { source: "own".into(), target: path.clone(), conflict: ConflictPolicy::Overwrite },
+                FSOperation::Remove { path: path.join("secret") },
+            ];
+
+            for operation in operations {
+                // the lexical escapes are caught up front, the symlinks once the operation is executed
+                match usfs.run_batch(vec![operation.clone()]).await {
+                    Ok(outcomes) => assert!(
+                        matches!(outcomes[..], [FSOperationOutcome::Failed(FSError::PathBreaksOut | FSError::UnsafeItem)]),
+                        "{operation:?} was not refused: {outcomes:?}"
+                    ),
+                    res => assert_refused(res, &path),
+                };
+            };
+        };
+
+        assert!(setup.user_dir().join("own").exists());
+        setup.assert_outside_untouched();
+    }
+
+    #[tokio::test]
+    async fn attachments_refuse_escaping_paths() {
+        let setup = Setup::new().await;
+        let usfs = setup.usfs().await;
+
+        for path in escaping_paths(&setup) {
+            assert_refused(usfs.store_attachments(std::slice::from_ref(&path)).await, &path);
+        };
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use axum::http::{header, StatusCode};
+use axum::response::{IntoResponse, Response};
+use crate::config::{MessagesConfig, RateLimitConfig};
+
+
+/// the states of the keys, which are not limited anymore, are dropped once there are this many of them
+const PRUNE_THRESHOLD: usize = 1024;
+
+
+/// allows `count` actions per `period` for each key, with bursts of up to `count` actions
+#[derive(Debug)]
+pub struct RateLimiter<K> {
+    /// the time between two actions at a steady rate
+    interval: Duration,
+    period: Duration,
+    /// when each key is going to be allowed a full burst again
+    states: Mutex<HashMap<K, Instant>>,
+}
+
+
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new(count: u32, period: Duration) -> Self {
+        Self {
+            interval: period / count.max(1),
+            period,
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// counts the action in, if it is allowed. otherwise returns how long to wait until it would be
+    pub fn check(&self, key: K) -> Result<(), Duration> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: K, now: Instant) -> Result<(), Duration> {
+        let mut states = self.states.lock().unwrap();
+
+        if states.len() >= PRUNE_THRESHOLD {
+            states.retain(|_, free_at| *free_at > now);
+        };
+
+        let free_at = states.get(&key).copied().unwrap_or(now).max(now);
+        let burst_allowance = self.period - self.interval;
+
+        if free_at - now > burst_allowance {
+            return Err(free_at - now - burst_allowance);
+        };
+
+        states.insert(key, free_at + self.interval);
+
+        Ok(())
+    }
+}
+
+
+impl From<&RateLimitConfig> for RateLimiter<i32> {
+    fn from(config: &RateLimitConfig) -> Self {
+        Self::new(config.count, Duration::from_secs(config.period))
+    }
+}
+
+
+impl From<&RateLimitConfig> for RateLimiter<IpAddr> {
+    fn from(config: &RateLimitConfig) -> Self {
+        Self::new(config.count, Duration::from_secs(config.period))
+    }
+}
+
+
+#[derive(Debug)]
+pub enum SendLimitError {
+    /// holds how long to wait
+    RateLimited(Duration),
+    /// holds the max length
+    BodyIsTooLong(usize),
+}
+
+
+impl IntoResponse for SendLimitError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::RateLimited(wait) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                // rounded up, so that the retry would not come too early
+                [(header::RETRY_AFTER, (wait.as_secs() + u64::from(wait.subsec_nanos() > 0)).to_string())],
+                "too many messages were sent, try again later"
+            ).into_response(),
+            Self::BodyIsTooLong(max_length) =>
+                (StatusCode::PAYLOAD_TOO_LARGE, format!("the message body can not be longer than {max_length} characters")).into_response(),
+        }
+    }
+}
+
+
+#[derive(Debug)]
+pub struct MessageLimits {
+    max_body_length: Option<usize>,
+    per_user: Option<RateLimiter<i32>>,
+    per_ip: Option<RateLimiter<IpAddr>>,
+}
+
+
+impl MessageLimits {
+    pub fn new(config: &MessagesConfig) -> Self {
+        Self {
+            max_body_length: config.max_body_length,
+            per_user: config.user_send_rate.as_ref().map(RateLimiter::from),
+            per_ip: config.ip_send_rate.as_ref().map(RateLimiter::from),
+        }
+    }
+
+    /// applies to the edits as well, which are not rate limited though
+    pub fn check_body(&self, body: &str) -> Result<(), SendLimitError> {
+        match self.max_body_length {
+            Some(max_length) if body.chars().count() > max_length => Err(SendLimitError::BodyIsTooLong(max_length)),
+            _ => Ok(()),
+        }
+    }
+
+    /// counts the message in, if it is allowed to be sent
+    pub fn check_send(&self, sender_id: i32, ip: IpAddr, body: &str) -> Result<(), SendLimitError> {
+        self.check_body(body)?;
+
+        // the user goes first, so that someone going over their own limit would not use up the limit of everyone sharing their ip
+        if let Some(ref limiter) = self.per_user {
+            limiter.check(sender_id).map_err(SendLimitError::RateLimited)?;
+        };
+
+        if let Some(ref limiter) = self.per_ip {
+            limiter.check(ip).map_err(SendLimitError::RateLimited)?;
+        };
+
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::{Duration, Instant};
+    use axum::http::header;
+    use axum::response::IntoResponse;
+    use crate::config::{MessagesConfig, RateLimitConfig};
+    use super::{MessageLimits, RateLimiter, SendLimitError};
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn bursts_are_allowed_up_to_the_count() {
+        let limiter = RateLimiter::new(3, 30 * SECOND);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at(1, start), Ok(()));
+        };
+
+        // the next one is freed up a third of the period after the burst started
+        assert_eq!(limiter.check_at(1, start), Err(10 * SECOND));
+        assert_eq!(limiter.check_at(1, start + 4 * SECOND), Err(6 * SECOND));
+
+        // the other keys are on their own
+        assert_eq!(limiter.check_at(2, start), Ok(()));
+    }
+
+    #[test]
+    fn actions_are_refilled_at_a_steady_rate() {
+        let limiter = RateLimiter::new(3, 30 * SECOND);
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            limiter.check_at(1, start).unwrap();
+        };
+
+        assert_eq!(limiter.check_at(1, start + 10 * SECOND), Ok(()));
+        assert_eq!(limiter.check_at(1, start + 10 * SECOND), Err(10 * SECOND));
+        assert_eq!(limiter.check_at(1, start + 20 * SECOND), Ok(()));
+
+        // a whole period of quiet brings the full burst back, but not more than that
+        let later = start + 60 * SECOND;
+        for _ in 0..3 {
+            assert_eq!(limiter.check_at(1, later), Ok(()));
+        };
+        assert!(limiter.check_at(1, later).is_err());
+    }
+
+    #[test]
+    fn retry_after_is_rounded_up() {
+        for (wait, retry_after) in [
+            (Duration::ZERO, "0"),
+            (Duration::from_nanos(1), "1"),
+            (Duration::from_millis(1500), "2"),
+            (2 * SECOND, "2"),
+        ] {
+            let response = SendLimitError::RateLimited(wait).into_response();
+
+            assert_eq!(response.headers()[header::RETRY_AFTER], retry_after, "{wait:?}");
+        };
+    }
+
+    fn limits(max_body_length: Option<usize>, user_count: u32, ip_count: u32) -> MessageLimits {
+        MessageLimits::new(&MessagesConfig {
+            max_body_length,
+            user_send_rate: Some(RateLimitConfig { count: user_count, period: 60 }),
+            ip_send_rate: Some(RateLimitConfig { count: ip_count, period: 60 }),
+            ..MessagesConfig::default()
+        })
+    }
+
+    #[test]
+    fn body_length_is_counted_in_characters() {
+        let limits = limits(Some(3), 10, 10);
+
+        assert!(limits.check_body("äöü").is_ok());
+        assert!(matches!(limits.check_body("äöüß"), Err(SendLimitError::BodyIsTooLong(3))));
+    }
+
+    #[test]
+    fn user_over_their_limit_does_not_use_up_the_ip_limit() {
+        let limits = limits(None, 1, 2);
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+        assert!(limits.check_send(1, ip, "").is_ok());
+        for _ in 0..5 {
+            assert!(matches!(limits.check_send(1, ip, ""), Err(SendLimitError::RateLimited(_))));
+        };
+
+        assert!(limits.check_send(2, ip, "").is_ok());
+        assert!(matches!(limits.check_send(3, ip, ""), Err(SendLimitError::RateLimited(_))));
+    }
+}
@@ -4,14 +4,26 @@ mod config;
 mod middleware;
 mod filesystem;
 mod env;
+mod limits;
+mod content;
+mod events;
+mod tasks;
+mod token_usage;
+mod totp;
 
 
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::Duration;
 use axum::extract::Request;
 use axum::ServiceExt;
 use tower::Layer;
 use config::Config;
+use events::EventHub;
 use filesystem::Filesystem;
+use limits::MessageLimits;
+use tasks::TokenSweepMetrics;
+use token_usage::TokenUsage;
 use crate::env::load_dotenv;
 
 
@@ -20,6 +32,10 @@ struct AppState {
     pub conn_pool: db::ConnPool,
     pub config: Arc<Config>,
     pub filesystem: Arc<Filesystem>,
+    pub message_limits: Arc<MessageLimits>,
+    pub events: Arc<EventHub>,
+    pub token_usage: Arc<TokenUsage>,
+    pub token_sweeps: Arc<TokenSweepMetrics>,
 }
 
 
@@ -41,16 +57,43 @@ async fn main() {
     db::migrate(&mut conn_pool.get().unwrap());
     
     let filesystem = Filesystem::new(
+        conn_pool.clone(),
         &config.filesystem.storage_path, 
-        config.filesystem.template_path.as_deref(),
+        config.filesystem.templates.iter()
+            .map(|(name, t)| (name.clone(), filesystem::Template { path: t.path.clone(), version: t.version }))
+            .collect(),
+        config.filesystem.default_template.clone(),
         config.filesystem.total_size,
         config.filesystem.user_space_size
     );
     
+    if let Some(mode) = config.filesystem.startup_scan {
+        log::info!("scanning the storage...");
+        
+        match filesystem.scan_integrity(mode).await {
+            Ok(report) if report.is_clean() => log::info!("storage is fine"),
+            Ok(report) => log::warn!("storage integrity scan: {report}"),
+            Err(err) => log::error!("storage integrity scan failed: {err}"),
+        };
+    };
+    
     // todo remove this to string and then later from string conversion, while still supporting V4 and V6
     let addr = format!("{}:{}", config.server.address, config.server.port);
     
-    let state = AppState { conn_pool, filesystem: Arc::new(filesystem), config: Arc::new(config) };
+    let message_limits = MessageLimits::new(&config.messages);
+    
+    let filesystem = Arc::new(filesystem);
+    
+    tasks::spawn_message_scheduler(conn_pool.clone(), filesystem.clone(), Duration::from_secs(config.messages.scheduler_interval.max(1)));
+    
+    let token_usage = Arc::new(TokenUsage::new());
+    
+    let token_sweeps = Arc::new(TokenSweepMetrics::new());
+    
+    tasks::spawn_token_usage_flusher(conn_pool.clone(), token_usage.clone(), token_usage::FLUSH_INTERVAL);
+    tasks::spawn_token_sweeper(conn_pool.clone(), token_usage.clone(), token_sweeps.clone(), Duration::from_secs(config.auth.sweep_interval.max(1)));
+    
+    let state = AppState { conn_pool, filesystem, message_limits: Arc::new(message_limits), events: Arc::new(EventHub::new()), token_usage, token_sweeps, config: Arc::new(config) };
 
     let router = axum::Router::new()
         .nest("/v2", routers::v2::get_router())
@@ -72,5 +115,6 @@ async fn main() {
     log::info!("starting server!!!");
     
     let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
-    axum::serve(listener, ServiceExt::<Request>::into_make_service(app)).await.unwrap();
+    // the connection info is needed for the per-ip limits
+    axum::serve(listener, ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(app)).await.unwrap();
 }
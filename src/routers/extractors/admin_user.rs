@@ -0,0 +1,57 @@
+use std::fmt::Formatter;
+use axum::extract::FromRequestParts;
+use axum::http::request::Parts;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use crate::{AppState, db};
+use crate::routers::extractors::session_user::SessionUserRejection;
+use crate::routers::extractors::SessionUser;
+
+pub struct AdminUser(pub db::User);
+
+
+#[derive(Debug)]
+pub enum AdminUserRejection {
+    UserRejection(SessionUserRejection),
+    NotAnAdmin,
+}
+
+
+impl std::fmt::Display for AdminUserRejection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UserRejection(rej) => <_ as std::fmt::Display>::fmt(rej, f),
+            Self::NotAnAdmin => write!(f, "you must be an admin to do this"),
+        }
+    }
+}
+
+
+impl IntoResponse for AdminUserRejection {
+    fn into_response(self) -> Response {
+        match self {
+            Self::UserRejection(rej) => rej.into_response(),
+            rej @ Self::NotAnAdmin => (StatusCode::FORBIDDEN, rej.to_string()).into_response(),
+        }
+    }
+}
+
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for AdminUser {
+    type Rejection = AdminUserRejection;
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        app_state: &AppState
+    ) -> Result<Self, Self::Rejection> {
+        let SessionUser(user) = SessionUser::from_request_parts(parts, app_state).await
+            .map_err(AdminUserRejection::UserRejection)?;
+        
+        if !user.is_admin {
+            return Err(AdminUserRejection::NotAnAdmin);
+        };
+        
+        Ok(Self(user))
+    }
+}
@@ -1,5 +1,7 @@
 mod session_token;
 mod session_user;
+mod admin_user;
 
-pub use session_token::{SessionToken, SessionTokenRejection};
-pub use session_user::{SessionUser, SessionUserRejection};
+pub use session_token::SessionToken;
+pub use session_user::SessionUser;
+pub use admin_user::AdminUser;
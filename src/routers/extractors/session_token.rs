@@ -13,6 +13,7 @@ use axum_extra::{
     typed_header::TypedHeaderRejection,
     TypedHeader
 };
+use chrono::Utc;
 use crate::{AppState, db};
 
 pub struct SessionToken(pub db::Token);
@@ -51,20 +52,35 @@ impl FromRequestParts<AppState> for SessionToken {
 
     async fn from_request_parts(
         parts: &mut Parts,
-        AppState { conn_pool, .. }: &AppState
+        AppState { conn_pool, token_usage, .. }: &AppState
     ) -> Result<Self, Self::Rejection> {
         let token_value =
             TypedHeader::<Authorization<Bearer>>::from_request_parts(parts, &()).await
                 .map_err(SessionTokenRejection::HeaderRejection)?;
 
         let conn_pool = conn_pool.clone();
+        let token_usage = token_usage.clone();
         let token = tokio::task::spawn_blocking(move || {
             let mut conn = conn_pool.get().unwrap();
             
-            match db::Token::get(&mut conn, token_value.token()) {
-                Some(t) if !t.is_valid() => { t.delete(&mut conn); None }
-                token => token
-            }
+            let mut token = db::Token::get(&mut conn, token_value.token())?;
+            token_usage.update(&mut token);
+            
+            if token.is_expired() {
+                token.delete(&mut conn);
+                return None;
+            };
+            
+            // it can still be refreshed
+            if token.is_access_expired() {
+                return None;
+            };
+            
+            let now = Utc::now().naive_local();
+            token_usage.record(token.id, now);
+            token.last_used_time = Some(now);
+            
+            Some(token)
         }).await.unwrap();
 
         Ok(Self(token.ok_or(SessionTokenRejection::InvalidToken)?))
@@ -2,16 +2,16 @@ use std::io::ErrorKind;
 use std::path::PathBuf;
 use std::str::FromStr;
 use axum::extract::{Query, State};
-use axum::http::StatusCode;
+use axum::http::{header, StatusCode};
 use axum::Json;
 use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
 use bytes::Bytes;
 use serde::Deserialize;
 use crate::{AppState, db};
-use crate::filesystem::{Filesystem, FSError, UserScopedFS};
+use crate::filesystem::{ConflictPolicy, Filesystem, FSError, FSOperation, UserScopedFS, is_active_content};
 use crate::routers::extractors::SessionUser;
-use super::schema::{DataResponse, FSDirListing, FSQuota, FSTree};
+use super::schema::{DataResponse, FSBatchResult, FSDirListing, FSQuota, FSTree, DEFAULT_MIME_TYPE};
 use super::utils::{B64ToStrError, from_b64};
 
 pub fn get_router() -> axum::Router<AppState> {
@@ -25,6 +25,7 @@ pub fn get_router() -> axum::Router<AppState> {
         .route("/rm", get(remove_item))
         .route("/rename", get(move_item))
         .route("/tree", get(get_usfs_tree))
+        .route("/batch", post(run_batch))
 }
 
 
@@ -38,7 +39,9 @@ impl IntoResponse for FSInteractionError {
     fn into_response(self) -> Response {
         match self {
             Self::FS(err @ FSError::NotEnoughStorage) => (StatusCode::PAYLOAD_TOO_LARGE, err.to_string()),
-            Self::FS(err @ (FSError::InvalidUTF8Path | FSError::PathBreaksOut)) => (StatusCode::BAD_REQUEST, err.to_string()),
+            Self::FS(err @ (FSError::PathBreaksOut | FSError::RecursiveTarget | FSError::NotAFile)) => (StatusCode::BAD_REQUEST, err.to_string()),
+            Self::FS(err @ FSError::TargetExists) => (StatusCode::CONFLICT, err.to_string()),
+            Self::FS(err @ FSError::UnsafeItem) => (StatusCode::FORBIDDEN, err.to_string()),
             Self::FS(FSError::HFS(hfs_err)) =>
                 match hfs_err.kind() {
                     ErrorKind::NotFound => (StatusCode::NOT_FOUND, "item at such path does not exist".to_string()),
@@ -98,11 +101,25 @@ async fn get_file(
     State(AppState { filesystem, .. }): State<AppState>,
     SessionUser(user): SessionUser,
     Query(Path { path_enc }): Query<Path>,
-) -> Result<Vec<u8>, FSInteractionError> {
+) -> Result<Response, FSInteractionError> {
     let path = dec_path(&path_enc)?;
     let usfs = mk_usfs(&filesystem, &user).await?;
 
-    usfs.read_file(&path).await.map_err(FSInteractionError::FS)
+    let data = usfs.read_file(&path).await.map_err(FSInteractionError::FS)?;
+    let mime = usfs.get_mime(&path).await.map_err(FSInteractionError::FS)?.unwrap_or(DEFAULT_MIME_TYPE.to_string());
+
+    // files are served from the api's origin, so anything which can run scripts must not be rendered inline
+    let disposition = if is_active_content(&mime) { "attachment" } else { "inline" };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, mime),
+            (header::CONTENT_DISPOSITION, disposition.to_string()),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            (header::CONTENT_SECURITY_POLICY, "sandbox".to_string()),
+        ],
+        data
+    ).into_response())
 }
 
 
@@ -128,17 +145,29 @@ struct Target {
 }
 
 
+/// v1 has always replaced the existing targets, unlike the filesystem itself, which refuses to by default
+const DEFAULT_CONFLICT_POLICY: ConflictPolicy = ConflictPolicy::Overwrite;
+
+
+#[derive(Deserialize)]
+struct Conflict {
+    conflict: Option<ConflictPolicy>,
+}
+
+
+/// an existing target is overwritten, unless another conflict policy is given
 async fn copy_item(
     State(AppState { filesystem, .. }): State<AppState>,
     SessionUser(user): SessionUser,
     Query(Path { path_enc: source_enc }): Query<Path>,
     Query(Target { target_enc }): Query<Target>,
+    Query(Conflict { conflict }): Query<Conflict>,
 ) -> Result<(), FSInteractionError> {
     let source = dec_path(&source_enc)?;
     let target = dec_path(&target_enc)?;
     let usfs = mk_usfs(&filesystem, &user).await?;
 
-    usfs.copy_item(&source, &target).await.map_err(FSInteractionError::FS)?;
+    usfs.copy_item(&source, &target, conflict.unwrap_or(DEFAULT_CONFLICT_POLICY)).await.map_err(FSInteractionError::FS)?;
 
     Ok(())
 }
@@ -166,16 +195,18 @@ struct ItemMove {
 }
 
 
+/// an existing target is overwritten, unless another conflict policy is given
 async fn move_item(
     State(AppState { filesystem, .. }): State<AppState>,
     SessionUser(user): SessionUser,
     Query(ItemMove { source_enc, target_enc }): Query<ItemMove>,
+    Query(Conflict { conflict }): Query<Conflict>,
 ) -> Result<(), FSInteractionError> {
     let source = dec_path(&source_enc)?;
     let target = dec_path(&target_enc)?;
     let usfs = mk_usfs(&filesystem, &user).await?;
     
-    usfs.move_item(&source, &target).await.map_err(FSInteractionError::FS)?;
+    usfs.move_item(&source, &target, conflict.unwrap_or(DEFAULT_CONFLICT_POLICY)).await.map_err(FSInteractionError::FS)?;
     
     Ok(())
 }
@@ -191,11 +222,69 @@ async fn get_usfs_tree(
 }
 
 
+// the operations are named and shaped after their respective endpoints
+#[derive(Deserialize)]
+#[serde(tag = "op")]
+enum BatchOperation {
+    #[serde(rename = "cp")]
+    Copy {
+        #[serde(rename = "path")]
+        source_enc: String,
+        #[serde(rename = "target")]
+        target_enc: String,
+        conflict: Option<ConflictPolicy>,
+    },
+    #[serde(rename = "rename")]
+    Move {
+        #[serde(rename = "oldpath")]
+        source_enc: String,
+        #[serde(rename = "newpath")]
+        target_enc: String,
+        conflict: Option<ConflictPolicy>,
+    },
+    #[serde(rename = "rm")]
+    Remove {
+        #[serde(rename = "path")]
+        path_enc: String,
+    },
+}
+
+
+impl TryFrom<BatchOperation> for FSOperation {
+    type Error = FSInteractionError;
+
+    fn try_from(op: BatchOperation) -> Result<Self, Self::Error> {
+        Ok(match op {
+            BatchOperation::Copy { source_enc, target_enc, conflict } =>
+                Self::Copy { source: dec_path(&source_enc)?, target: dec_path(&target_enc)?, conflict: conflict.unwrap_or(DEFAULT_CONFLICT_POLICY) },
+            BatchOperation::Move { source_enc, target_enc, conflict } =>
+                Self::Move { source: dec_path(&source_enc)?, target: dec_path(&target_enc)?, conflict: conflict.unwrap_or(DEFAULT_CONFLICT_POLICY) },
+            BatchOperation::Remove { path_enc } =>
+                Self::Remove { path: dec_path(&path_enc)? },
+        })
+    }
+}
+
+
+async fn run_batch(
+    State(AppState { filesystem, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Json(operations): Json<Vec<BatchOperation>>,
+) -> Result<Json<DataResponse<FSBatchResult>>, FSInteractionError> {
+    let operations = operations.into_iter().map(FSOperation::try_from).collect::<Result<Vec<_>, _>>()?;
+    let usfs = mk_usfs(&filesystem, &user).await?;
+
+    let outcomes = usfs.run_batch(operations).await.map_err(FSInteractionError::FS)?;
+
+    Ok(Json(DataResponse::new(FSBatchResult::from(outcomes))))
+}
+
+
 fn dec_path(s: &str) -> Result<PathBuf, FSInteractionError> {
     Ok(PathBuf::from_str(&from_b64(s).map_err(FSInteractionError::B64Decoding)?).unwrap())
 }
 
 
 async fn mk_usfs<'a>(fs: &'a Filesystem, user: &db::User) -> Result<UserScopedFS<'a>, FSInteractionError> {
-    UserScopedFS::new(&fs, user.id).await.map_err(FSInteractionError::FS)
+    UserScopedFS::new(fs, user).await.map_err(FSInteractionError::FS)
 }
@@ -1,25 +1,37 @@
 use std::collections::{HashMap, HashSet};
-use std::sync::{Arc, Mutex};
+use std::net::SocketAddr;
 use std::num::ParseIntError;
-use axum::extract::{Query, State, };
+use axum::extract::{ConnectInfo, Query, State};
 use axum::http::StatusCode;
 use axum::Json;
 use axum::response::{IntoResponse, Response};
 use axum::routing::{get, post};
+use chrono::DateTime;
 use serde::Deserialize;
 use crate::{AppState, db};
+use crate::content::{Body, ContentError, ContentType};
+use crate::events::Event;
+use crate::limits::SendLimitError;
 use crate::routers::extractors::SessionUser;
 use crate::routers::v1::utils::{B64ToStrError, from_b64};
-use crate::routers::v1::schema::{DataResponse, Message, MessagePreview, MessageThreadPart, SentMessage};
+use crate::routers::v1::schema::{DataResponse, Message, MessagePreview, MessageReaction, MessageRevision, MessageSearchResult, MessageThreadPart, SentMessage};
 
 pub fn get_router() -> axum::Router<AppState> {
     axum::Router::new()
         .route("/list", get(list_messages))
+        .route("/search", get(search_messages))
         .route("/send", post(send_message))
         .route("/reply", post(send_reply))
         .route("/get", get(get_message))
         .route("/delete", get(delete_message))
         .route("/thread", get(get_thread))
+        .route("/edit", post(edit_message))
+        .route("/revisions", get(get_revisions))
+        .route("/mark", get(mark_message))
+        .route("/mark_many", post(mark_messages))
+        .route("/react", get(add_reaction))
+        .route("/unreact", get(remove_reaction))
+        .route("/unread", get(count_unread_messages))
 }
 
 
@@ -37,28 +49,125 @@ struct MsgPreviewCfg {
 }
 
 
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum MsgFolder {
+    Inbox,
+    Sent,
+}
+
+
+impl From<MsgFolder> for db::MessageFolder {
+    fn from(folder: MsgFolder) -> Self {
+        match folder {
+            MsgFolder::Inbox => Self::Inbox,
+            MsgFolder::Sent => Self::Sent,
+        }
+    }
+}
+
+
+#[derive(Deserialize)]
+struct MsgListFilter {
+    folder: Option<MsgFolder>,
+    /// base64 encoded username
+    correspondent: Option<String>,
+    /// a timestamp in milliseconds, inclusive
+    since: Option<i64>,
+    /// a timestamp in milliseconds, exclusive
+    until: Option<i64>,
+    unread: Option<bool>,
+}
+
+
+enum ListMessagesError {
+    B64Decoding(B64ToStrError),
+    CorrespondentNotFound,
+    InvalidTimestamp,
+}
+
+
+impl IntoResponse for ListMessagesError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::B64Decoding(dec_err) => dec_err.into_response(),
+            Self::CorrespondentNotFound => (StatusCode::NOT_FOUND, "the correspondent was not found").into_response(),
+            Self::InvalidTimestamp => (StatusCode::BAD_REQUEST, "the timestamp is out of range").into_response(),
+        }
+    }
+}
+
+
 async fn list_messages(
     State(AppState { conn_pool, .. }): State<AppState>,
     SessionUser(user): SessionUser,
     Query(LLPagination { count, offset, descending }): Query<LLPagination>,
     Query(MsgPreviewCfg { preview_length }): Query<MsgPreviewCfg>,
-) -> Json<DataResponse<Vec<MessagePreview>>> {
+    Query(MsgListFilter { folder, correspondent, since, until, unread }): Query<MsgListFilter>,
+) -> Result<Json<DataResponse<Vec<MessagePreview>>>, ListMessagesError> {
+    let to_naive = |ms: i64| DateTime::from_timestamp_millis(ms).map(|t| t.naive_utc()).ok_or(ListMessagesError::InvalidTimestamp);
+
+    let mut filter = db::MessageFilter {
+        folder: folder.map(db::MessageFolder::from),
+        since: since.map(to_naive).transpose()?,
+        until: until.map(to_naive).transpose()?,
+        is_unread_only: unread.unwrap_or(false),
+        ..Default::default()
+    };
+
     let message_previews = tokio::task::spawn_blocking(move || {
         let conn = &mut conn_pool.get().unwrap();
 
+        if let Some(correspondent_enc) = correspondent {
+            let correspondent_username = from_b64(&correspondent_enc).map_err(ListMessagesError::B64Decoding)?;
+            let correspondent = db::User::get_by_username(conn, &correspondent_username).ok_or(ListMessagesError::CorrespondentNotFound)?;
+            filter.correspondent_id = Some(correspondent.id);
+        };
+
         // todo maybe combine those 2 operations into a single, so that we would avoid doing so many db operations in a loop
-        let messages = db::Message::get_all_not_deleted_accessible_to_user(conn, &user, 
+        let messages = db::Message::get_all_not_deleted_accessible_to_user(conn, &user, &filter,
                                                             descending.unwrap_or(true), 
                                                             count.unwrap_or(-1), 
                                                             offset.unwrap_or(0));
         
-        messages.into_iter().map(
-            |msg| MessagePreview::new(conn, &msg, preview_length.unwrap_or(80) as usize)
+        Ok(messages.into_iter().map(
+            |msg| MessagePreview::new(conn, &msg, &user, preview_length.unwrap_or(80) as usize)
                 .expect("messages are not deleted, as we have filtered out the deleted once before")
-        ).collect::<Vec<_>>()
-    }).await.unwrap();
+        ).collect::<Vec<_>>())
+    }).await.unwrap()?;
     
-    Json(DataResponse::new(message_previews))
+    Ok(Json(DataResponse::new(message_previews)))
+}
+
+
+#[derive(Deserialize)]
+struct MsgSearch {
+    query: String,
+}
+
+
+async fn search_messages(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Query(LLPagination { count, offset, descending }): Query<LLPagination>,
+    Query(MsgPreviewCfg { preview_length }): Query<MsgPreviewCfg>,
+    Query(MsgSearch { query: query_enc }): Query<MsgSearch>,
+) -> Result<Json<DataResponse<Vec<MessageSearchResult>>>, B64ToStrError> {
+    let query = from_b64(&query_enc)?;
+
+    let results = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+
+        let hits = db::Message::search(conn, &user, &query, descending.unwrap_or(true), count.unwrap_or(-1), offset.unwrap_or(0));
+
+        hits.into_iter().map(|hit| MessageSearchResult {
+            message: MessagePreview::new(conn, &hit.message, &user, preview_length.unwrap_or(80) as usize)
+                .expect("the deleted messages are not searched"),
+            snippet: hit.snippet,
+        }).collect::<Vec<_>>()
+    }).await.unwrap();
+
+    Ok(Json(DataResponse::new(results)))
 }
 
 
@@ -66,6 +175,11 @@ enum SendMessageError {
     B64Decoding(B64ToStrError),
     TargetUserNotFound,
     ReplyMessageNotFound,
+    TargetDoesNotAccept,
+    InvalidTimestamp,
+    ExpiresBeforeDelivery,
+    Limited(SendLimitError),
+    InvalidContent(ContentError),
 }
 
 
@@ -74,12 +188,79 @@ impl IntoResponse for SendMessageError {
         match self {
             Self::B64Decoding(dec_err) => dec_err.into_response(),
             Self::TargetUserNotFound => (StatusCode::NOT_FOUND, "the target user was not found").into_response(),
-            Self::ReplyMessageNotFound => (StatusCode::NOT_FOUND, "the reply message was not found").into_response()
+            Self::ReplyMessageNotFound => (StatusCode::NOT_FOUND, "the reply message was not found").into_response(),
+            Self::TargetDoesNotAccept => (StatusCode::FORBIDDEN, "the target user does not accept messages from you").into_response(),
+            Self::InvalidTimestamp => (StatusCode::BAD_REQUEST, "the timestamp is out of range").into_response(),
+            Self::ExpiresBeforeDelivery => (StatusCode::BAD_REQUEST, "the message would expire before it's delivered").into_response(),
+            Self::Limited(limit_err) => limit_err.into_response(),
+            Self::InvalidContent(content_err) => content_err.into_response(),
         }
     }
 }
 
 
+#[derive(Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+enum MsgContentType {
+    Plain,
+    Markdown,
+    Structured,
+}
+
+
+impl From<MsgContentType> for ContentType {
+    fn from(content_type: MsgContentType) -> Self {
+        match content_type {
+            MsgContentType::Plain => Self::Plain,
+            MsgContentType::Markdown => Self::Markdown,
+            MsgContentType::Structured => Self::Structured,
+        }
+    }
+}
+
+
+#[derive(Deserialize)]
+struct MsgFormat {
+    #[serde(rename = "contentType")]
+    content_type: Option<MsgContentType>,
+}
+
+
+impl SendMessageError {
+    fn from_send_error(err: db::MessageSendError) -> Self {
+        match err {
+            db::MessageSendError::RecipientDoesNotAccept(_) => Self::TargetDoesNotAccept,
+            db::MessageSendError::ExpiresBeforeDelivery => Self::ExpiresBeforeDelivery,
+        }
+    }
+}
+
+
+#[derive(Deserialize)]
+struct MsgSchedule {
+    /// a timestamp in milliseconds, the message is delivered right away if missing
+    #[serde(rename = "deliverAt")]
+    deliver_at: Option<i64>,
+    /// a timestamp in milliseconds, after which the message is purged
+    #[serde(rename = "expireAt")]
+    expire_at: Option<i64>,
+}
+
+
+impl TryFrom<MsgSchedule> for db::MessageSchedule {
+    type Error = SendMessageError;
+
+    fn try_from(schedule: MsgSchedule) -> Result<Self, Self::Error> {
+        let to_naive = |ms: i64| DateTime::from_timestamp_millis(ms).map(|t| t.naive_utc()).ok_or(SendMessageError::InvalidTimestamp);
+
+        Ok(Self {
+            deliver_time: schedule.deliver_at.map(to_naive).transpose()?,
+            expire_time: schedule.expire_at.map(to_naive).transpose()?,
+        })
+    }
+}
+
+
 #[derive(Deserialize)]
 struct MsgSend {
     target: String,
@@ -87,18 +268,25 @@ struct MsgSend {
 
 
 async fn send_message(
-    State(AppState { conn_pool, .. }): State<AppState>,
+    State(AppState { conn_pool, message_limits, .. }): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     SessionUser(user): SessionUser,
     Query(MsgSend { target: target_username_enc }): Query<MsgSend>,
+    Query(MsgFormat { content_type }): Query<MsgFormat>,
+    Query(schedule): Query<MsgSchedule>,
     contents: String,
 ) -> Result<Json<DataResponse<SentMessage>>, SendMessageError> {
+    let schedule = db::MessageSchedule::try_from(schedule)?;
+    message_limits.check_send(user.id, addr.ip(), &contents).map_err(SendMessageError::Limited)?;
+    let contents = Body::new(content_type.map_or(ContentType::Plain, ContentType::from), &contents).map_err(SendMessageError::InvalidContent)?;
+
     let msg = tokio::task::spawn_blocking(move || {
         let conn = &mut conn_pool.get().unwrap();
         
         let target_username = from_b64(&target_username_enc).map_err(SendMessageError::B64Decoding)?;
         let target = db::User::get_by_username(conn, &target_username).ok_or(SendMessageError::TargetUserNotFound)?;
 
-        let msg = db::Message::send(conn, &user, &target, None, &contents);
+        let msg = db::Message::send(conn, &user, &[target], None, &contents, &[], schedule).map_err(SendMessageError::from_send_error)?;
 
         Ok(SentMessage::new(conn, &msg))
     }).await.unwrap()?;
@@ -116,20 +304,30 @@ struct MsgReply {
 
 // this is just insane amounts of duplicate code
 async fn send_reply(
-    State(AppState { conn_pool, .. }): State<AppState>,
+    State(AppState { conn_pool, message_limits, .. }): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     SessionUser(user): SessionUser,
     Query(MsgReply { target: target_username_enc, id: reply_msg_id }): Query<MsgReply>,
+    Query(MsgFormat { content_type }): Query<MsgFormat>,
+    Query(schedule): Query<MsgSchedule>,
     contents: String,
 ) -> Result<Json<DataResponse<SentMessage>>, SendMessageError> {
+    let schedule = db::MessageSchedule::try_from(schedule)?;
+    message_limits.check_send(user.id, addr.ip(), &contents).map_err(SendMessageError::Limited)?;
+    let contents = Body::new(content_type.map_or(ContentType::Plain, ContentType::from), &contents).map_err(SendMessageError::InvalidContent)?;
+
     let msg = tokio::task::spawn_blocking(move || {
         let conn = &mut conn_pool.get().unwrap();
 
         let target_username = from_b64(&target_username_enc).map_err(SendMessageError::B64Decoding)?;
         let target = db::User::get_by_username(conn, &target_username).ok_or(SendMessageError::TargetUserNotFound)?;
 
-        let reply = db::Message::get(conn, reply_msg_id).ok_or(SendMessageError::ReplyMessageNotFound)?;
+        // replying adds the sender to the conversation, so the message has to be one they can see
+        let reply = db::Message::get(conn, reply_msg_id)
+            .filter(|reply| reply.is_accessible_to(conn, &user))
+            .ok_or(SendMessageError::ReplyMessageNotFound)?;
         
-        let msg = db::Message::send(conn, &user, &target, Some(&reply), &contents);
+        let msg = db::Message::send(conn, &user, &[target], Some(&reply), &contents, &[], schedule).map_err(SendMessageError::from_send_error)?;
 
         Ok(SentMessage::new(conn, &msg))
     }).await.unwrap()?;
@@ -150,6 +348,9 @@ enum GetMessageError {
     MessageNotFoundError,
     MessageNotAccessibleError,
     MessageIsDeleted,
+    InvalidReaction,
+    Limited(SendLimitError),
+    InvalidContent(ContentError),
 }
 
 
@@ -161,6 +362,9 @@ impl IntoResponse for GetMessageError {
             Self::MessageNotAccessibleError => (StatusCode::FORBIDDEN, "you do not have such access to this message").into_response(),
             Self::InvalidID(parse_err) => (StatusCode::BAD_REQUEST, format!("the ID is invalid: {parse_err}")).into_response(),
             Self::MessageIsDeleted => (StatusCode::GONE, "the message is deleted").into_response(),
+            Self::InvalidReaction => (StatusCode::BAD_REQUEST, "the reaction should be an emoji or a short code like ':thumbs_up:'").into_response(),
+            Self::Limited(limit_err) => limit_err.into_response(),
+            Self::InvalidContent(content_err) => content_err.into_response(),
         }
     }
 }
@@ -179,15 +383,14 @@ async fn get_message(
 
         let mut msg = db::Message::get(conn, msg_id).ok_or(GetMessageError::MessageNotFoundError)?;
 
-        if !msg.is_accessible_to(&user) {
+        if !msg.is_accessible_to(conn, &user) {
             return Err(GetMessageError::MessageNotAccessibleError);
         };
         
-        if user.id == msg.receiver_id {
-            let _ = msg.mark_as_read(conn);
-        }
+        // the sender is not one of the recipients (usually), so then it's fine for this to fail
+        let _ = msg.set_read_state(conn, &user, true);
 
-        Ok(Message::new(conn, &msg))
+        Ok(Message::new(conn, &msg, &user))
     }).await.unwrap()?;
     
     Ok(Json(DataResponse::new(message)))
@@ -195,129 +398,317 @@ async fn get_message(
 
 
 async fn delete_message(
-    State(AppState { conn_pool, .. }): State<AppState>,
+    State(AppState { conn_pool, filesystem, .. }): State<AppState>,
     SessionUser(user): SessionUser,
     Query(MsgGet { id: msg_id_enc }): Query<MsgGet>,
 ) -> Result<(), GetMessageError> {
     let msg_id = from_b64(&msg_id_enc).map_err(GetMessageError::B64DecodeError)?.parse().map_err(GetMessageError::InvalidID)?;
 
-    tokio::task::spawn_blocking(move || {
+    let file_keys = tokio::task::spawn_blocking(move || {
         let conn = &mut conn_pool.get().unwrap();
 
         let mut msg = db::Message::get(conn, msg_id).ok_or(GetMessageError::MessageNotFoundError)?;
 
-        if !user.id == msg.sender_id {
+        if !msg.is_accessible_to(conn, &user) {
             return Err(GetMessageError::MessageNotAccessibleError);
         };
 
-        msg.delete(conn);
-
-        Ok(())
+        msg.delete_for(conn, &user).map_err(|_| GetMessageError::MessageIsDeleted)
     }).await.unwrap()?;
 
+    filesystem.remove_attachments(&file_keys).await;
+
     Ok(())
 }
 
 
-async fn get_thread(
+async fn edit_message(
+    State(AppState { conn_pool, message_limits, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Query(MsgGet { id: msg_id_enc }): Query<MsgGet>,
+    Query(MsgFormat { content_type }): Query<MsgFormat>,
+    contents: String,
+) -> Result<Json<DataResponse<Message>>, GetMessageError> {
+    message_limits.check_body(&contents).map_err(GetMessageError::Limited)?;
+    let msg_id = from_b64(&msg_id_enc).map_err(GetMessageError::B64DecodeError)?.parse().map_err(GetMessageError::InvalidID)?;
+
+    let message = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+
+        let mut msg = db::Message::get(conn, msg_id).ok_or(GetMessageError::MessageNotFoundError)?;
+
+        if msg.sender_id != user.id {
+            return Err(GetMessageError::MessageNotAccessibleError);
+        };
+
+        if msg.is_deleted_for(conn, &user) {
+            return Err(GetMessageError::MessageIsDeleted);
+        };
+
+        // the message keeps its content type, unless another one is given
+        let contents = Body::new(content_type.map_or_else(|| msg.get_content_type(), ContentType::from), &contents)
+            .map_err(GetMessageError::InvalidContent)?;
+
+        msg.edit(conn, &contents).map_err(|_| GetMessageError::MessageIsDeleted)?;
+
+        Ok(Message::new(conn, &msg, &user))
+    }).await.unwrap()?;
+
+    Ok(Json(DataResponse::new(message)))
+}
+
+
+async fn get_revisions(
     State(AppState { conn_pool, .. }): State<AppState>,
     SessionUser(user): SessionUser,
     Query(MsgGet { id: msg_id_enc }): Query<MsgGet>,
-) -> Result<Json<DataResponse<MessageThreadPart>>, GetMessageError> {
+) -> Result<Json<DataResponse<Vec<MessageRevision>>>, GetMessageError> {
     let msg_id = from_b64(&msg_id_enc).map_err(GetMessageError::B64DecodeError)?.parse().map_err(GetMessageError::InvalidID)?;
 
-    // i am sorry for this code, but hey it should be fast! ...i hope
-    let msg_thread = tokio::task::spawn_blocking(move || {
+    let revisions = tokio::task::spawn_blocking(move || {
         let conn = &mut conn_pool.get().unwrap();
 
         let msg = db::Message::get(conn, msg_id).ok_or(GetMessageError::MessageNotFoundError)?;
 
-        if !msg.is_accessible_to(&user) {
+        if !msg.is_accessible_to(conn, &user) {
             return Err(GetMessageError::MessageNotAccessibleError);
         };
 
-        let (thread_parts_ids, root_msg_id, messages) = {
-            let mut thread_parts: HashMap<i32, Vec<i32>> = HashMap::new();  // an {id: [msg_which_reply_to_id]} map
-            let mut messages: HashMap<i32, db::Message> = HashMap::new();  // used for caching
-            let mut ids_to_resolve_queue = vec![msg.id];
+        if msg.is_deleted_for(conn, &user) {
+            return Err(GetMessageError::MessageIsDeleted);
+        };
 
-            messages.insert(msg.id, msg);
-            let mut resolved_ids = HashSet::new();
-            let mut root_msg_id = None;
+        Ok(msg.get_revisions(conn).into_iter().map(MessageRevision::from).collect::<Vec<_>>())
+    }).await.unwrap()?;
 
-            while let Some(msg_id) = ids_to_resolve_queue.pop() {
-                // xxx is this check even needed?
-                if resolved_ids.contains(&msg_id) {
-                    continue;
-                };
+    Ok(Json(DataResponse::new(revisions)))
+}
+
+
+#[derive(Deserialize)]
+struct MsgReadState {
+    read: Option<bool>,
+}
 
-                let msg = messages.entry(msg_id).or_insert_with(|| db::Message::get(conn, msg_id).expect("the queue stores only existing IDs"));
 
-                if let Some(replying_msg) = msg.get_replying_msg(conn) {
-                    if !resolved_ids.contains(&replying_msg.id) {
-                        let thread_part = thread_parts.entry(replying_msg.id).or_default();
+async fn mark_message(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Query(MsgGet { id: msg_id_enc }): Query<MsgGet>,
+    Query(MsgReadState { read }): Query<MsgReadState>,
+) -> Result<(), GetMessageError> {
+    let msg_id = from_b64(&msg_id_enc).map_err(GetMessageError::B64DecodeError)?.parse().map_err(GetMessageError::InvalidID)?;
 
-                        thread_part.push(msg_id);
+    tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
 
-                        ids_to_resolve_queue.push(replying_msg.id);
+        let mut msg = db::Message::get(conn, msg_id).ok_or(GetMessageError::MessageNotFoundError)?;
 
-                        // todo somehow cache this
-                        // messages.insert(replying_msg.id, replying_msg);
-                    };
-                } else {
-                    if root_msg_id.is_none() {
-                        root_msg_id = Some(msg_id);
-                    } else {
-                        unreachable!("there cannot be multiple message roots");
-                    };
-                };
+        if !msg.is_accessible_to(conn, &user) {
+            return Err(GetMessageError::MessageNotAccessibleError);
+        };
 
-                for reply_msg in msg.get_all_not_deleted_replies(conn) {
-                    if !reply_msg.is_accessible_to(&user) || resolved_ids.contains(&reply_msg.id) {
-                        continue;
-                    };
+        if msg.is_deleted_for(conn, &user) {
+            return Err(GetMessageError::MessageIsDeleted);
+        };
 
-                    let thread_part = thread_parts.entry(msg_id).or_default();
+        // only the recipients have a read state
+        msg.set_read_state(conn, &user, read.unwrap_or(true)).map_err(|_| GetMessageError::MessageNotAccessibleError)
+    }).await.unwrap()
+}
 
-                    thread_part.push(reply_msg.id);
 
-                    ids_to_resolve_queue.push(reply_msg.id);
+#[derive(Deserialize)]
+struct MsgReaction {
+    reaction: String,
+}
 
-                    messages.insert(reply_msg.id, reply_msg);
-                };
 
-                resolved_ids.insert(msg_id);
-            };
+async fn add_reaction(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+    Query(MsgGet { id: msg_id_enc }): Query<MsgGet>,
+    Query(MsgReaction { reaction: reaction_enc }): Query<MsgReaction>,
+) -> Result<Json<DataResponse<Vec<MessageReaction>>>, GetMessageError> {
+    let reaction = from_b64(&reaction_enc).map_err(GetMessageError::B64DecodeError)?;
+
+    if !db::MessageReaction::is_valid(&reaction) {
+        return Err(GetMessageError::InvalidReaction);
+    };
+
+    react(state, user, &msg_id_enc, reaction, true).await
+}
+
+
+async fn remove_reaction(
+    State(state): State<AppState>,
+    SessionUser(user): SessionUser,
+    Query(MsgGet { id: msg_id_enc }): Query<MsgGet>,
+    Query(MsgReaction { reaction: reaction_enc }): Query<MsgReaction>,
+) -> Result<Json<DataResponse<Vec<MessageReaction>>>, GetMessageError> {
+    let reaction = from_b64(&reaction_enc).map_err(GetMessageError::B64DecodeError)?;
+
+    react(state, user, &msg_id_enc, reaction, false).await
+}
 
-            (thread_parts, root_msg_id.unwrap(), messages)
+
+/// the sender of the message gets notified, returns all the reactions to the message afterwards
+async fn react(
+    AppState { conn_pool, events, .. }: AppState,
+    user: db::User,
+    msg_id_enc: &str,
+    reaction: String,
+    is_added: bool,
+) -> Result<Json<DataResponse<Vec<MessageReaction>>>, GetMessageError> {
+    let msg_id = from_b64(msg_id_enc).map_err(GetMessageError::B64DecodeError)?.parse().map_err(GetMessageError::InvalidID)?;
+    let (username, event_reaction) = (user.get_username(), reaction.clone());
+
+    let (notified_id, reactions) = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+
+        let msg = db::Message::get(conn, msg_id).ok_or(GetMessageError::MessageNotFoundError)?;
+
+        if !msg.is_accessible_to(conn, &user) {
+            return Err(GetMessageError::MessageNotAccessibleError);
         };
 
-        let mut thread_parts = {
-            let mut thread_parts: HashMap<i32, Arc<Mutex<MessageThreadPart>>> = HashMap::new();
-            let mut get_thread_part = |id: i32| {
-                thread_parts.entry(id).or_insert_with(|| Arc::new(Mutex::new(
-                    MessageThreadPart::new_partial(
-                        conn,
-                        messages.get(&id)
-                            .expect("all messages should have gotten cached"),
-                        80
-                    )
-                ))).clone()
-            };
+        if msg.is_deleted_for(conn, &user) {
+            return Err(GetMessageError::MessageIsDeleted);
+        };
 
-            for (msg_id, replies_ids) in thread_parts_ids.iter() {
-                let thread_part = get_thread_part(*msg_id);
+        let is_changed = if is_added {
+            db::MessageReaction::add(conn, &msg, &user, &reaction)
+        } else {
+            db::MessageReaction::remove(conn, &msg, &user, &reaction)
+        };
+
+        let sender = msg.get_sender(conn);
+        let notified_id = Some(sender.id).filter(|_| is_changed && sender.id != user.id && !msg.is_deleted_for(conn, &sender));
+
+        Ok((notified_id, MessageReaction::all_of(db::MessageReaction::get_all_of_messages(conn, &[msg.id]), &user)))
+    }).await.unwrap()?;
+
+    if let Some(sender_id) = notified_id {
+        events.publish(sender_id, Event::Reaction { message_id: msg_id, username, reaction: event_reaction, added: is_added });
+    };
+
+    Ok(Json(DataResponse::new(reactions)))
+}
+
+
+/// the messages which can't be marked are skipped, returns how many were marked
+async fn mark_messages(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Query(MsgReadState { read }): Query<MsgReadState>,
+    Json(ids): Json<Vec<i32>>,
+) -> Json<DataResponse<usize>> {
+    let marked = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+
+        db::Message::set_read_state_of_many(conn, &user, &ids, read.unwrap_or(true))
+    }).await.unwrap();
+
+    Json(DataResponse::new(marked))
+}
+
+
+async fn count_unread_messages(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+) -> Json<DataResponse<i64>> {
+    let count = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+
+        db::Message::count_unread_of(conn, &user)
+    }).await.unwrap();
+
+    Json(DataResponse::new(count))
+}
 
-                thread_part.lock().unwrap().replies.append(&mut replies_ids.iter().map(|id| get_thread_part(*id)).collect());
-            };
 
-            thread_parts
+async fn get_thread(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Query(MsgGet { id: msg_id_enc }): Query<MsgGet>,
+) -> Result<Json<DataResponse<MessageThreadPart>>, GetMessageError> {
+    let msg_id = from_b64(&msg_id_enc).map_err(GetMessageError::B64DecodeError)?.parse().map_err(GetMessageError::InvalidID)?;
+
+    let msg_thread = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+
+        let msg = db::Message::get(conn, msg_id).ok_or(GetMessageError::MessageNotFoundError)?;
+
+        if !msg.is_accessible_to(conn, &user) {
+            return Err(GetMessageError::MessageNotAccessibleError);
         };
 
-        Ok(Arc::<_>::try_unwrap(thread_parts.remove(&root_msg_id).unwrap())
-            .expect("it's the root message, so it should not have any references to it")
-            .into_inner().unwrap())
+        // a thread is exactly the conversation the message is in, so it's fetched all at once
+        let conversation = db::Conversation::get(conn, msg.conversation_id.expect("every message belongs to a conversation"))
+            .expect("conversations are not deleted while they have messages");
+        let mut usernames = conversation.get_participants(conn).into_iter()
+            .map(|u| (u.id, u.get_username()))
+            .collect::<HashMap<_, _>>();
+        let conversation_messages = db::Message::get_all_in_conversation(conn, conversation.id);
+        // the receivers of the scheduled messages join the conversation only once those are delivered
+        let missing_ids = conversation_messages.iter()
+            .flat_map(|m| [m.sender_id, m.receiver_id])
+            .filter(|id| !usernames.contains_key(id))
+            .collect::<HashSet<_>>().into_iter()
+            .collect::<Vec<_>>();
+        usernames.extend(db::User::get_all_by_ids(conn, &missing_ids).into_iter().map(|u| (u.id, u.get_username())));
+        // the messages the user still has a copy of
+        let kept_ids = db::MessageRecipient::get_all_in_conversation(conn, conversation.id).into_iter()
+            .filter(|r| r.recipient_id == user.id && !r.is_deleted)
+            .map(|r| r.message_id)
+            .chain(conversation_messages.iter().filter(|m| m.sender_id == user.id && !m.is_deleted_by_sender).map(|m| m.id))
+            .collect::<HashSet<_>>();
+        let messages = conversation_messages.iter()
+            .map(|m| (m.id, m))
+            .collect::<HashMap<_, _>>();
+
+        // the messages above the requested one are always shown, while the rest only if they are accessible
+        let mut ancestors = HashSet::from([msg.id]);
+        let mut root_msg_id = msg.id;
+        // a reply to a message which no longer existed got a conversation of its own, so it is the root then
+        while let Some(replying_id) = messages[&root_msg_id].replying_id.filter(|id| messages.contains_key(id)) {
+            ancestors.insert(replying_id);
+            root_msg_id = replying_id;
+        };
+
+        let mut replies_ids: HashMap<i32, Vec<i32>> = HashMap::new();  // an {id: [msg_which_reply_to_id]} map
+        for reply in &conversation_messages {
+            if let Some(replying_id) = reply.replying_id {
+                if ancestors.contains(&reply.id) || kept_ids.contains(&reply.id) {
+                    replies_ids.entry(replying_id).or_default().push(reply.id);
+                };
+            };
+        };
+
+        Ok(build_thread_part(root_msg_id, &messages, &replies_ids, &kept_ids, &usernames))
     }).await.unwrap()?;
 
     Ok(Json(DataResponse::new(msg_thread)))
 }
+
+
+fn build_thread_part(
+    msg_id: i32,
+    messages: &HashMap<i32, &db::Message>,
+    replies_ids: &HashMap<i32, Vec<i32>>,
+    kept_ids: &HashSet<i32>,
+    usernames: &HashMap<i32, String>,
+) -> MessageThreadPart {
+    let mut thread_part = MessageThreadPart::new_partial(messages[&msg_id], usernames, 80);
+
+    // the ancestors are shown even if the user has deleted them, but without their contents
+    if !kept_ids.contains(&msg_id) {
+        thread_part.partial_body = "[deleted]".to_string();
+    };
+
+    thread_part.replies = replies_ids.get(&msg_id).into_iter().flatten()
+        .map(|id| build_thread_part(*id, messages, replies_ids, kept_ids, usernames))
+        .collect();
+
+    thread_part
+}
@@ -9,6 +9,7 @@ pub struct DataResponse<T> {
 }
 
 
+#[allow(dead_code)]
 impl<T> DataResponse<T> {
     pub fn new(data: T) -> Self {
         Self { valid: true, status_code: 200, data }
@@ -38,6 +39,7 @@ pub struct FlatDataResponse<T> {
 }
 
 
+#[allow(dead_code)]
 impl<T> FlatDataResponse<T> {
     pub fn new(data: T) -> Self {
         Self { valid: true, status_code: 200, data }
@@ -1,13 +1,12 @@
 // todo normalise paths in scoped_path (requires implementing respective method in fs)
 use std::path::{Path, PathBuf};
-use std::str::FromStr;
 use std::time::SystemTime;
 use serde::{Deserialize, Serialize};
 use crate::db;
-use crate::filesystem::{FSRes, UserScopedFS};
+use crate::filesystem::{FSOperationOutcome, FSRes, UserScopedFS};
 
 
-const DEFAULT_MIME_TYPE: &str = "text/plain; charset=utf-8"; 
+pub const DEFAULT_MIME_TYPE: &str = "text/plain; charset=utf-8";
 
 
 #[derive(Serialize, Deserialize)]
@@ -22,11 +21,11 @@ pub struct FSQuota {
 impl FSQuota {
     pub async fn new(usfs: &UserScopedFS<'_>, user: &db::User) -> FSRes<Self> {
         let max = usfs.fs().userspace_size().unwrap_or(u64::MAX);
-        let used = usfs.get_item_size(".".as_ref()).await?;
+        let used = usfs.get_used_size().await?;
 
         Ok(Self {
             username: user.get_username(),
-            free: max - used,
+            free: max.saturating_sub(used),
             max, used
         })
     }
@@ -136,6 +135,46 @@ impl FSTree {
 } 
 
 
+#[serde_with::skip_serializing_none]
+#[derive(Serialize, Deserialize)]
+pub struct FSBatchOperationResult {
+    pub status: String,
+    pub error: Option<String>,
+}
+
+
+impl From<FSOperationOutcome> for FSBatchOperationResult {
+    fn from(outcome: FSOperationOutcome) -> Self {
+        let (status, error) = match outcome {
+            FSOperationOutcome::Done => ("done", None),
+            FSOperationOutcome::Failed(err) => ("failed", Some(err.to_string())),
+            FSOperationOutcome::RolledBack => ("rolledBack", None),
+            FSOperationOutcome::RollbackFailed(err) => ("rollbackFailed", Some(err.to_string())),
+            FSOperationOutcome::Skipped => ("skipped", None),
+        };
+
+        Self { status: status.to_string(), error }
+    }
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct FSBatchResult {
+    pub success: bool,
+    pub results: Vec<FSBatchOperationResult>,
+}
+
+
+impl From<Vec<FSOperationOutcome>> for FSBatchResult {
+    fn from(outcomes: Vec<FSOperationOutcome>) -> Self {
+        Self {
+            success: outcomes.iter().all(|o| matches!(o, FSOperationOutcome::Done)),
+            results: outcomes.into_iter().map(FSBatchOperationResult::from).collect(),
+        }
+    }
+}
+
+
 fn get_item_name(path: &Path) -> String {
     path.file_name().map(|r#fn| r#fn.to_string_lossy().to_string()).unwrap_or(".".to_string())
 }
@@ -1,7 +1,8 @@
-use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
 use chrono::NaiveDateTime;
 use diesel::SqliteConnection;
 use serde::{Deserialize, Serialize};
+use crate::content::ContentType;
 use crate::db;
 
 #[derive(Deserialize, Serialize)]
@@ -14,7 +15,9 @@ pub struct MessagePreview {
     pub timestamp: u64,
     #[serde(rename = "partialBody")]
     pub partial_body: String,
-    pub read: bool
+    pub read: bool,
+    #[serde(rename = "editedAt")]
+    pub edited_at: Option<u64>,
 }
 
 
@@ -25,20 +28,34 @@ pub enum ConversionError {
 
 
 impl MessagePreview {
-    pub fn new(conn: &mut SqliteConnection, message: &db::Message, preview_length: usize) -> Result<Self, ConversionError> {
+    pub fn new(conn: &mut SqliteConnection, message: &db::Message, viewer: &db::User, preview_length: usize) -> Result<Self, ConversionError> {
+        if message.is_deleted_for(conn, viewer) {
+            return Err(ConversionError::ItemIsDeleted);
+        };
+        
         Ok(Self {
             id: message.id,
             sender: message.get_sender(conn).get_username(),
             receiver: message.get_receiver(conn).get_username(),
             replying_to: message.replying_id,
             timestamp: message.sent_time.signed_duration_since(NaiveDateTime::UNIX_EPOCH).num_milliseconds() as u64,
-            partial_body: message.get_body_preview(preview_length).map_err(|_| ConversionError::ItemIsDeleted)?.to_string(),
-            read: message.is_read.ok_or(ConversionError::ItemIsDeleted)?,
+            partial_body: message.get_body_preview(preview_length).map_err(|_| ConversionError::ItemIsDeleted)?,
+            // a scheduled message has no recipients yet, and it's not read by anyone either
+            read: read_state(conn, message, viewer).unwrap_or(false),
+            edited_at: message.edited_time.map(|t| t.signed_duration_since(NaiveDateTime::UNIX_EPOCH).num_milliseconds() as u64),
         })
     }
 }
 
 
+#[derive(Deserialize, Serialize)]
+pub struct MessageSearchResult {
+    pub message: MessagePreview,
+    /// html, with the matches wrapped in `<mark>`
+    pub snippet: String,
+}
+
+
 #[serde_with::skip_serializing_none]
 #[derive(Deserialize, Serialize)]
 pub struct SentMessage {
@@ -67,25 +84,81 @@ pub struct Message {
     pub sender: String,
     pub receiver: String,
     pub body: String,
+    /// plain, markdown or structured
+    #[serde(rename = "contentType")]
+    pub content_type: String,
     pub replies: Vec<i32>,
+    pub reactions: Vec<MessageReaction>,
     #[serde(rename = "replyingTo")]
     pub replying_to: Option<i32>,
     pub timestamp: u64,
-    pub read: bool
+    pub read: bool,
+    #[serde(rename = "editedAt")]
+    pub edited_at: Option<u64>,
+    /// whether the message is yet to be delivered, at its timestamp
+    pub pending: bool,
+    #[serde(rename = "expiresAt")]
+    pub expires_at: Option<u64>,
 }
 
 
 impl Message {
-    pub fn new(conn: &mut SqliteConnection, message: &db::Message) -> Self {
+    pub fn new(conn: &mut SqliteConnection, message: &db::Message, viewer: &db::User) -> Self {
+        let body = message.body.clone().filter(|_| !message.is_deleted_for(conn, viewer));
+        
         Self {
             id: message.id,
             sender: message.get_sender(conn).get_username(),
             receiver: message.get_receiver(conn).get_username(),
-            body: message.body.clone().unwrap_or("[deleted]".to_string()),
+            // the placeholder is plain text, whatever the message was
+            content_type: if body.is_some() { message.content_type.clone() } else { ContentType::Plain.as_str().to_string() },
+            body: body.unwrap_or("[deleted]".to_string()),
             replies: message.get_all_not_deleted_replies(conn).into_iter().map(|msg| msg.id).collect(),
+            reactions: MessageReaction::all_of(db::MessageReaction::get_all_of_messages(conn, &[message.id]), viewer),
             replying_to: message.replying_id,
             timestamp: message.sent_time.signed_duration_since(NaiveDateTime::UNIX_EPOCH).num_milliseconds() as u64,
-            read: message.is_read.unwrap_or(false),
+            read: read_state(conn, message, viewer).unwrap_or(false),
+            edited_at: message.edited_time.map(|t| t.signed_duration_since(NaiveDateTime::UNIX_EPOCH).num_milliseconds() as u64),
+            pending: message.is_pending,
+            expires_at: message.expire_time.map(|t| t.signed_duration_since(NaiveDateTime::UNIX_EPOCH).num_milliseconds() as u64),
+        }
+    }
+}
+
+
+#[derive(Deserialize, Serialize)]
+pub struct MessageReaction {
+    pub reaction: String,
+    pub count: usize,
+    /// whether the viewer is one of those who reacted so
+    pub reacted: bool,
+}
+
+
+impl MessageReaction {
+    pub fn all_of(reactions: Vec<db::MessageReaction>, viewer: &db::User) -> Vec<Self> {
+        db::MessageReaction::summarize(reactions).into_iter()
+            .map(|s| Self { reacted: s.users_ids.contains(&viewer.id), count: s.users_ids.len(), reaction: s.reaction })
+            .collect()
+    }
+}
+
+
+#[derive(Deserialize, Serialize)]
+pub struct MessageRevision {
+    pub body: String,
+    #[serde(rename = "contentType")]
+    pub content_type: String,
+    pub timestamp: u64,
+}
+
+
+impl From<db::MessageRevision> for MessageRevision {
+    fn from(revision: db::MessageRevision) -> Self {
+        Self {
+            body: revision.body,
+            content_type: revision.content_type,
+            timestamp: revision.creation_time.signed_duration_since(NaiveDateTime::UNIX_EPOCH).num_milliseconds() as u64,
         }
     }
 }
@@ -98,7 +171,7 @@ pub struct MessageThreadPart {
     pub receiver: String,
     #[serde(rename = "partialBody")]
     pub partial_body: String,
-    pub replies: Vec<Arc<Mutex<MessageThreadPart>>>,
+    pub replies: Vec<MessageThreadPart>,
     #[serde(rename = "replyingTo")]
     pub replying_to: Option<i32>,
     pub timestamp: u64,
@@ -106,15 +179,22 @@ pub struct MessageThreadPart {
 
 
 impl MessageThreadPart {
-    pub fn new_partial(conn: &mut SqliteConnection, message: &db::Message, preview_length: usize) -> Self {
+    /// the usernames are expected to contain the sender and the receiver of the message
+    pub fn new_partial(message: &db::Message, usernames: &HashMap<i32, String>, preview_length: usize) -> Self {
         Self {
             id: message.id,
-            sender: message.get_sender(conn).get_username(),
-            receiver: message.get_receiver(conn).get_username(),
-            partial_body: message.get_body_preview(preview_length).unwrap_or("[deleted]").to_string(),
+            sender: usernames[&message.sender_id].clone(),
+            receiver: usernames[&message.receiver_id].clone(),
+            partial_body: message.get_body_preview(preview_length).unwrap_or_else(|_| "[deleted]".to_string()),
             replies: Vec::new(),
             replying_to: message.replying_id,
             timestamp: message.sent_time.signed_duration_since(NaiveDateTime::UNIX_EPOCH).num_milliseconds() as u64,
         }
     }
 }
+
+
+/// a recipient sees whether they have read the message, while the sender sees whether the receiver has
+fn read_state(conn: &mut SqliteConnection, message: &db::Message, viewer: &db::User) -> Option<bool> {
+    message.is_read_by(conn, if viewer.id == message.sender_id { message.receiver_id } else { viewer.id })
+}
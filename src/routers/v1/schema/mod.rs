@@ -8,6 +8,6 @@ mod filesystem;
 pub use user::PartialUser;
 pub use data_response::{DataResponse, FlatDataResponse};
 pub use meta_info::MetaInfo;
-pub use session::Session;
-pub use message::{MessagePreview, SentMessage, Message, MessageThreadPart};
-pub use filesystem::{FSQuota, FSTree, FSDirListing};
+pub use session::{Session, SecondFactorChallenge};
+pub use message::{MessagePreview, MessageSearchResult, SentMessage, Message, MessageReaction, MessageRevision, MessageThreadPart};
+pub use filesystem::{FSQuota, FSTree, FSDirListing, FSBatchResult, DEFAULT_MIME_TYPE};
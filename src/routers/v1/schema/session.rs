@@ -9,11 +9,18 @@ pub struct Session {
 }
 
 
+/// given instead of a session, when the user has the second factor enabled
+#[derive(Serialize, Deserialize)]
+pub struct SecondFactorChallenge {
+    pub challenge: String,
+}
+
+
 impl Session {
-    pub fn new(conn: &mut SqliteConnection, token: db::Token) -> Self {
+    pub fn new(conn: &mut SqliteConnection, issued: db::IssuedToken) -> Self {
         Self {
-            username: token.get_owner(conn).username.expect("token should be valid, so the user shouldn't be deleted"),
-            token: token.value
+            username: issued.token.get_owner(conn).username.expect("token should be valid, so the user shouldn't be deleted"),
+            token: issued.access_token
         }
     }
 }
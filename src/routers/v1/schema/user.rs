@@ -8,6 +8,7 @@ pub struct PartialUser {
 }
 
 
+#[allow(dead_code)]
 pub enum ConversionError {
     ItemIsDeleted, ItemIsCorrupted(bool)
 }
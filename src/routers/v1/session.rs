@@ -1,40 +1,132 @@
+use std::net::SocketAddr;
 use std::time::Duration;
-use axum::extract::State;
+use axum::extract::{ConnectInfo, Query, State};
 use axum::http::StatusCode;
 use axum::Json;
+use axum::response::{IntoResponse, Response};
 use axum::routing::get;
-use axum_extra::headers::Authorization;
+use axum_extra::headers::{Authorization, UserAgent};
 use axum_extra::headers::authorization::Basic;
 use axum_extra::TypedHeader;
+use serde::Deserialize;
 use crate::{AppState, db};
+use crate::config::AuthConfig;
 use crate::routers::extractors::SessionToken;
-use super::schema::{DataResponse, Session};
+use crate::routers::v1::utils::{B64ToStrError, from_b64};
+use super::schema::{DataResponse, SecondFactorChallenge, Session};
 
 pub fn get_router() -> axum::Router<AppState> {
     axum::Router::new()
         .route("/auth", get(create_session))
+        .route("/auth/2fa", get(complete_session))
         .route("/logoff", get(delete_session))
 }
 
 
+/// the v1 clients can't refresh the tokens
+fn get_lifetimes(config: &AuthConfig) -> db::TokenLifetimes {
+    db::TokenLifetimes {
+        session: config.session_lifetime.map(Duration::from_secs),
+        access: None,
+        idle: config.idle_timeout.map(Duration::from_secs),
+    }
+}
+
+
+fn get_client(addr: SocketAddr, user_agent: Option<TypedHeader<UserAgent>>) -> db::TokenClient {
+    // there is no way to name the session in the v1
+    db::TokenClient {
+        label: None,
+        ip: Some(addr.ip().to_string()),
+        user_agent: user_agent.map(|TypedHeader(ua)| ua.to_string()),
+    }
+}
+
+
 async fn create_session(
     State(AppState { conn_pool, config, .. }): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    user_agent: Option<TypedHeader<UserAgent>>,
     TypedHeader(basic_creds): TypedHeader<Authorization<Basic>>
-) -> Result<Json<DataResponse<Session>>, StatusCode> {
-    let session = tokio::task::spawn_blocking(move || {
+) -> Result<Response, StatusCode> {
+    let client = get_client(addr, user_agent);
+    
+    let auth = tokio::task::spawn_blocking(move || {
         let conn = &mut conn_pool.get().unwrap();
         
-        let token = db::Token::auth(conn, 
-                                    basic_creds.username(), 
-                                    basic_creds.password(), 
-                                    config.auth.session_lifetime.map(Duration::from_secs));
+        let auth = db::Token::auth(conn, 
+                                   basic_creds.username(), 
+                                   basic_creds.password(), 
+                                   get_lifetimes(&config.auth),
+                                   client);
         
-        token.map(|t| Session::new(conn, t))
+        auth.map(|a| match a {
+            db::TokenAuth::Issued(t) => Ok(Session::new(conn, t)),
+            db::TokenAuth::SecondFactorRequired(challenge) => Err(challenge),
+        })
     }).await.unwrap();
 
-    match session {
+    match auth {
         None => Err(StatusCode::UNAUTHORIZED),
-        Some(s) => Ok(Json(DataResponse::new(s)))
+        Some(Ok(s)) => Ok(Json(DataResponse::new(s)).into_response()),
+        // the login is to be continued with /auth/2fa
+        Some(Err(challenge)) => Ok((
+            StatusCode::ACCEPTED, 
+            Json(DataResponse { data: SecondFactorChallenge { challenge }, valid: true, status_code: StatusCode::ACCEPTED.as_u16() as i32 })
+        ).into_response()),
+    }
+}
+
+
+#[derive(Deserialize)]
+struct SecondFactorLogin {
+    challenge: String,
+    code: String,
+}
+
+
+enum SecondFactorLoginError {
+    B64Decoding(B64ToStrError),
+    ChallengeNotFound,
+    InvalidCode,
+    Locked,
+}
+
+
+impl IntoResponse for SecondFactorLoginError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::B64Decoding(dec_err) => dec_err.into_response(),
+            Self::ChallengeNotFound => (StatusCode::UNAUTHORIZED, "the login has expired, start it again").into_response(),
+            Self::InvalidCode => (StatusCode::UNAUTHORIZED, "the code is invalid").into_response(),
+            Self::Locked => (StatusCode::TOO_MANY_REQUESTS, "too many invalid codes were given, try again later").into_response(),
+        }
+    }
+}
+
+
+async fn complete_session(
+    State(AppState { conn_pool, config, .. }): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    user_agent: Option<TypedHeader<UserAgent>>,
+    Query(SecondFactorLogin { challenge: challenge_enc, code: code_enc }): Query<SecondFactorLogin>
+) -> Result<Json<DataResponse<Session>>, SecondFactorLoginError> {
+    let challenge = from_b64(&challenge_enc).map_err(SecondFactorLoginError::B64Decoding)?;
+    let code = from_b64(&code_enc).map_err(SecondFactorLoginError::B64Decoding)?;
+    let client = get_client(addr, user_agent);
+    
+    let session = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        db::Token::auth_second_factor(conn, &challenge, &code, get_lifetimes(&config.auth), client)
+            .map(|t| Session::new(conn, t))
+    }).await.unwrap();
+    
+    match session {
+        Err(db::LoginChallengeError::NotFound) => Err(SecondFactorLoginError::ChallengeNotFound),
+        Err(db::LoginChallengeError::InvalidCode) => Err(SecondFactorLoginError::InvalidCode),
+        Err(db::LoginChallengeError::Locked) => Err(SecondFactorLoginError::Locked),
+        Ok(s) => Ok(Json(DataResponse::new(s))),
     }
 }
 
@@ -1,6 +1,3 @@
-use std::io::ErrorKind;
-use std::path::PathBuf;
-use std::str::FromStr;
 use axum::extract::{Query, State};
 use axum::http::StatusCode;
 use axum::Json;
@@ -11,7 +8,6 @@ use axum_extra::headers::authorization::Basic;
 use axum_extra::TypedHeader;
 use serde::Deserialize;
 use crate::{AppState, db};
-use crate::filesystem::{FSError, UserScopedFS};
 use crate::routers::extractors::SessionUser;
 use crate::routers::v1::utils::{B64ToStrError, from_b64};
 use super::schema::DataResponse;
@@ -24,6 +20,11 @@ pub fn get_router() -> axum::Router<AppState> {
         .route("/changepswd", get(change_self_password))
         .route("/delete", get(delete_self))
         .route("/create", get(create_new_user))
+        .route("/privacy", get(get_self_privacy))
+        .route("/privacy/update", get(update_self_privacy))
+        .route("/block", get(block_user))
+        .route("/unblock", get(unblock_user))
+        .route("/blocked", get(get_blocked_users))
 }
 
 
@@ -117,13 +118,15 @@ impl IntoResponse for UserCreationError {
 
 
 async fn create_new_user(
-    State(AppState { conn_pool, .. }): State<AppState>,
+    State(AppState { conn_pool, filesystem, .. }): State<AppState>,
     TypedHeader(auth): TypedHeader<Authorization<Basic>>,
 ) -> Result<Json<DataResponse<()>>, UserCreationError> {
+    let template = filesystem.default_template().map(str::to_string);
+    
     tokio::task::spawn_blocking(move || {
         let conn = &mut conn_pool.get().unwrap();
         
-        db::User::create(conn, auth.username(), auth.password(), None)
+        db::User::create(conn, auth.username(), auth.password(), None, template.as_deref())
     }).await.unwrap().map_err(UserCreationError::DbError)?;
     
     Ok(Json(DataResponse::new(())))
@@ -135,16 +138,139 @@ async fn delete_self(
     State(AppState { conn_pool, filesystem, .. }): State<AppState>,
     SessionUser(mut user): SessionUser,
 ) {
-    let usfs = UserScopedFS::new(&filesystem, user.id).await.unwrap();  // i hope this doesnt ever fail
+    let user_id = user.id;
+
+    let file_keys = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+
+        user.delete(conn)
+    }).await.unwrap();
+
+    // whatever is left behind is going to be found by the integrity scan
+    filesystem.remove_attachments(&file_keys).await;
+    if let Err(err) = filesystem.remove_user_dir(user_id).await {
+        log::warn!("failed to remove the storage of deleted user {user_id}: {err}");
+    };
+}
+
+
+async fn get_self_privacy(
+    SessionUser(user): SessionUser
+) -> Json<DataResponse<String>> {
+    Json(DataResponse::new(user.get_message_privacy().as_str().to_string()))
+}
+
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum MessagePrivacy {
+    Everyone,
+    Contacts,
+    Nobody,
+}
+
+
+#[derive(Deserialize)]
+struct NewPrivacy {
+    messages: MessagePrivacy,
+}
+
+
+async fn update_self_privacy(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(mut user): SessionUser,
+    Query(NewPrivacy { messages }): Query<NewPrivacy>,
+) {
+    let privacy = match messages {
+        MessagePrivacy::Everyone => db::MessagePrivacy::Everyone,
+        MessagePrivacy::Contacts => db::MessagePrivacy::Contacts,
+        MessagePrivacy::Nobody => db::MessagePrivacy::Nobody,
+    };
 
     tokio::task::spawn_blocking(move || {
         let conn = &mut conn_pool.get().unwrap();
 
-        user.delete(conn);
+        user.set_message_privacy(conn, privacy)
+            .expect("token is valid, so user should be as well too");
+    }).await.unwrap();
+}
+
+
+async fn get_blocked_users(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+) -> Json<DataResponse<Vec<String>>> {
+    let blocked = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+
+        db::UserBlock::get_all_blocked_by(conn, &user)
     }).await.unwrap();
 
-    match usfs.remove_item(".".as_ref()).await {
-        Err(FSError::HFS(err)) if err.kind() == ErrorKind::NotFound => {},
-        whatever => whatever.unwrap()   // i really hope this doesnt fail under most other circumstances as well
+    Json(DataResponse::new(blocked.iter().map(db::User::get_username).collect()))
+}
+
+
+enum BlockError {
+    B64Decoding(B64ToStrError),
+    UserNotFound,
+    CannotBlockSelf,
+}
+
+
+impl IntoResponse for BlockError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::B64Decoding(dec_err) => dec_err.into_response(),
+            Self::UserNotFound => (StatusCode::NOT_FOUND, "the user was not found").into_response(),
+            Self::CannotBlockSelf => (StatusCode::BAD_REQUEST, "you can not block yourself").into_response(),
+        }
     }
 }
+
+
+#[derive(Deserialize)]
+struct TargetUser {
+    username: String,
+}
+
+
+async fn block_user(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Query(TargetUser { username: username_enc }): Query<TargetUser>,
+) -> Result<(), BlockError> {
+    let username = from_b64(&username_enc).map_err(BlockError::B64Decoding)?;
+
+    tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+
+        let blocked = db::User::get_by_username(conn, &username).ok_or(BlockError::UserNotFound)?;
+
+        if blocked.id == user.id {
+            return Err(BlockError::CannotBlockSelf);
+        };
+
+        db::UserBlock::block(conn, &user, &blocked);
+
+        Ok(())
+    }).await.unwrap()
+}
+
+
+async fn unblock_user(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Query(TargetUser { username: username_enc }): Query<TargetUser>,
+) -> Result<(), BlockError> {
+    let username = from_b64(&username_enc).map_err(BlockError::B64Decoding)?;
+
+    tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+
+        let blocked = db::User::get_by_username(conn, &username).ok_or(BlockError::UserNotFound)?;
+
+        db::UserBlock::unblock(conn, &user, &blocked);
+
+        Ok(())
+    }).await.unwrap()
+}
@@ -0,0 +1,121 @@
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use crate::{AppState, db, tasks};
+use serde::Deserialize;
+use crate::filesystem::{FSError, ScanMode};
+use crate::routers::extractors::AdminUser;
+use super::schema::{BlockStats, IntegrityReport, TemplateRollout, TokenStats};
+
+pub fn get_router() -> axum::Router<AppState> {
+    axum::Router::new()
+        .route("/templates/:name/rollout", post(roll_out_template))
+        .route("/fs/scan", post(scan_fs))
+        .route("/blocks/stats", get(get_block_stats))
+        .route("/tokens/stats", get(get_token_stats))
+}
+
+
+enum AdminActionError {
+    TemplateNotFound,
+    FSError(FSError),
+}
+
+
+impl IntoResponse for AdminActionError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::TemplateNotFound => (StatusCode::NOT_FOUND, "such template does not exist").into_response(),
+            Self::FSError(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        }
+    }
+}
+
+
+/// the rollout itself goes on in the background, as there can be plenty of users and files
+async fn roll_out_template(
+    State(AppState { conn_pool, filesystem, .. }): State<AppState>,
+    AdminUser(admin): AdminUser,
+    Path(name): Path<String>,
+) -> Result<(StatusCode, Json<TemplateRollout>), AdminActionError> {
+    let version = filesystem.template(&name).ok_or(AdminActionError::TemplateNotFound)?.version;
+    
+    log::info!("user {} is rolling out version {version} of template '{name}'", admin.id);
+    let is_default = filesystem.default_template() == Some(name.as_str());
+    
+    let users = {
+        let name = name.clone();
+        
+        tokio::task::spawn_blocking(move || {
+            let conn = &mut conn_pool.get().unwrap();
+            
+            db::User::get_all_with_outdated_fs_template(conn, &name, version, is_default)
+        }).await.unwrap()
+    };
+    
+    let rollout = TemplateRollout { template: name.clone(), version, users: users.len() as u32 };
+    
+    tasks::spawn_template_rollout(filesystem, name, version, users);
+    
+    Ok((StatusCode::ACCEPTED, Json(rollout)))
+}
+
+
+#[derive(Deserialize)]
+struct ScanParams {
+    #[serde(default)]
+    fix: bool,
+}
+
+
+async fn scan_fs(
+    State(AppState { filesystem, .. }): State<AppState>,
+    AdminUser(admin): AdminUser,
+    Query(ScanParams { fix }): Query<ScanParams>,
+) -> Result<Json<IntegrityReport>, AdminActionError> {
+    log::info!("user {} is scanning the storage{}", admin.id, if fix { " with fixing" } else { "" });
+    
+    let report = filesystem.scan_integrity(if fix { ScanMode::Fix } else { ScanMode::Report }).await
+        .map_err(AdminActionError::FSError)?;
+    
+    log::info!("storage integrity scan: {report}");
+    
+    Ok(Json(report.into()))
+}
+
+
+#[derive(Deserialize)]
+struct StatsParams {
+    top: Option<i64>,
+}
+
+
+async fn get_block_stats(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    AdminUser(_): AdminUser,
+    Query(StatsParams { top }): Query<StatsParams>,
+) -> Json<BlockStats> {
+    let stats = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        db::UserBlock::get_stats(conn, top.unwrap_or(10))
+    }).await.unwrap();
+    
+    Json(stats.into())
+}
+
+
+async fn get_token_stats(
+    State(AppState { conn_pool, token_sweeps, .. }): State<AppState>,
+    AdminUser(_): AdminUser,
+) -> Json<TokenStats> {
+    let total = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        db::Token::count(conn)
+    }).await.unwrap();
+    
+    Json(TokenStats::new(total, token_sweeps.get()))
+}
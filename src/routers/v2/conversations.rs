@@ -0,0 +1,89 @@
+use std::collections::HashSet;
+use axum::extract::{Path, Query, State};
+use axum::http::StatusCode;
+use axum::Json;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use serde::Deserialize;
+use crate::{AppState, db};
+use crate::routers::extractors::SessionUser;
+use super::schema::{Conversation, ConversationPreview};
+
+pub fn get_router() -> axum::Router<AppState> {
+    axum::Router::new()
+        .route("/", get(list_conversations))
+        .route("/:id", get(get_conversation))
+}
+
+
+#[derive(Deserialize)]
+struct Pagination {
+    count: Option<i64>,
+    offset: Option<u64>,
+}
+
+
+async fn list_conversations(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Query(Pagination { count, offset }): Query<Pagination>,
+) -> Json<Vec<ConversationPreview>> {
+    let overviews = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        db::Conversation::get_overviews_of_user(conn, &user, count.unwrap_or(-1), offset.unwrap_or(0))
+    }).await.unwrap();
+    
+    Json(overviews.into_iter().map(ConversationPreview::from).collect())
+}
+
+
+enum GetConversationError {
+    NotFound,
+    NotAParticipant,
+}
+
+
+impl IntoResponse for GetConversationError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::NotFound => (StatusCode::NOT_FOUND, "the conversation was not found").into_response(),
+            Self::NotAParticipant => (StatusCode::FORBIDDEN, "you are not a participant of this conversation").into_response(),
+        }
+    }
+}
+
+
+async fn get_conversation(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(id): Path<i32>,
+) -> Result<Json<Conversation>, GetConversationError> {
+    let conversation = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let conversation = db::Conversation::get(conn, id).ok_or(GetConversationError::NotFound)?;
+        
+        if !conversation.is_participant(conn, &user) {
+            return Err(GetConversationError::NotAParticipant);
+        };
+        
+        let participants = conversation.get_participants(conn);
+        let recipients = db::MessageRecipient::get_all_in_conversation(conn, conversation.id);
+        let attachments = db::MessageAttachment::get_all_in_conversation(conn, conversation.id);
+        let reactions = db::MessageReaction::get_all_in_conversation(conn, conversation.id);
+        
+        // only the messages the user still has a copy of
+        let kept_ids = recipients.iter()
+            .filter(|r| r.recipient_id == user.id && !r.is_deleted)
+            .map(|r| r.message_id)
+            .collect::<HashSet<_>>();
+        let messages = db::Message::get_all_in_conversation(conn, conversation.id).into_iter()
+            .filter(|m| (m.sender_id == user.id && !m.is_deleted_by_sender) || kept_ids.contains(&m.id))
+            .collect();
+        
+        Ok(Conversation::new(conversation, participants, messages, recipients, attachments, reactions))
+    }).await.unwrap()?;
+    
+    Ok(Json(conversation))
+}
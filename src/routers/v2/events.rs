@@ -0,0 +1,61 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use std::time::Duration;
+use axum::extract::State;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use axum::routing::get;
+use futures::{Stream, StreamExt};
+use crate::{AppState, db};
+use crate::routers::extractors::SessionToken;
+
+/// how often an open stream makes sure its session is still valid, along with the keep-alives
+const SESSION_CHECK_PERIOD: Duration = Duration::from_secs(15);
+
+
+pub fn get_router() -> axum::Router<AppState> {
+    axum::Router::new()
+        .route("/", get(listen))
+}
+
+
+/// a server-sent events stream of what happens to the user's messages, from the moment it's opened.
+/// it ends once the session is revoked or expires, a refreshed session keeps it going
+async fn listen(
+    State(AppState { conn_pool, events, token_usage, .. }): State<AppState>,
+    SessionToken(token): SessionToken,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let (user_id, token_id) = (token.owner_id, token.id);
+    let user = {
+        let conn_pool = conn_pool.clone();
+
+        Arc::new(tokio::task::spawn_blocking(move || token.get_owner(&mut conn_pool.get().unwrap())).await.unwrap())
+    };
+
+    // only the requests count as using the session, an open stream does not keep it from idling out
+    let session_end = async move {
+        let mut interval = tokio::time::interval(SESSION_CHECK_PERIOD);
+
+        loop {
+            interval.tick().await;
+
+            let (conn_pool, token_usage, user) = (conn_pool.clone(), token_usage.clone(), Arc::clone(&user));
+            let is_valid = tokio::task::spawn_blocking(move || {
+                let conn = &mut conn_pool.get().unwrap();
+
+                db::Token::get_of_owner(conn, &user, token_id)
+                    .map(|mut t| { token_usage.update(&mut t); t })
+                    .is_some_and(|t| !t.is_expired() && !t.is_access_expired())
+            }).await.unwrap();
+
+            if !is_valid {
+                break;
+            };
+        };
+    };
+
+    let stream = events.subscribe(user_id)
+        .map(|event| Ok(Event::default().json_data(event).expect("the events should be serializable")))
+        .take_until(Box::pin(session_end));
+
+    Sse::new(stream).keep_alive(KeepAlive::new().interval(SESSION_CHECK_PERIOD))
+}
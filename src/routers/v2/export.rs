@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use crate::db;
+
+
+/// how many messages are loaded at once
+const PAGE_SIZE: i64 = 100;
+
+/// the host part of the addresses and the message ids in the mbox
+const MBOX_HOST: &str = "arcapi";
+
+
+#[derive(Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Json,
+    /// an mboxrd file, with a message per conversation message
+    Mbox,
+}
+
+
+impl ExportFormat {
+    pub fn mime(&self) -> &'static str {
+        match self {
+            Self::Json => "application/json",
+            Self::Mbox => "application/mbox",
+        }
+    }
+
+    pub fn file_name(&self) -> &'static str {
+        match self {
+            Self::Json => "messages.json",
+            Self::Mbox => "messages.mbox",
+        }
+    }
+}
+
+
+#[derive(Serialize)]
+struct ExportedMessage {
+    id: i32,
+    conversation_id: i32,
+    /// the first message of the reply chain, which is the message itself if it doesn't reply to anything
+    thread_id: i32,
+    replying_to: Option<i32>,
+    sender: String,
+    recipients: Vec<String>,
+    content_type: String,
+    body: String,
+    /// the names of the attached files, the files themselves are not exported
+    attachments: Vec<String>,
+    sent_time: NaiveDateTime,
+    edited_time: Option<NaiveDateTime>,
+}
+
+
+/// writes out the messages accessible to the user a page at a time, the oldest first
+pub struct MessageExporter {
+    conn_pool: db::ConnPool,
+    user: db::User,
+    format: ExportFormat,
+    /// the (sent time, id) of the last exported message
+    last_exported: Option<(NaiveDateTime, i32)>,
+    is_started: bool,
+    usernames: HashMap<i32, String>,
+    /// the thread of each message seen so far
+    thread_ids: HashMap<i32, i32>,
+}
+
+
+impl MessageExporter {
+    pub fn new(conn_pool: db::ConnPool, user: db::User, format: ExportFormat) -> Self {
+        Self { conn_pool, user, format, last_exported: None, is_started: false, usernames: HashMap::new(), thread_ids: HashMap::new() }
+    }
+
+    /// returns the next part of the export, and whether it was the last one.
+    /// WARNING: BLOCKS, AS IT USES THE DB
+    pub fn next_chunk(&mut self) -> (String, bool) {
+        let conn = &mut self.conn_pool.get().unwrap();
+
+        let mut chunk = String::new();
+
+        if !self.is_started {
+            self.is_started = true;
+
+            if let ExportFormat::Json = self.format {
+                chunk.push_str(&format!(
+                    r#"{{"username":{},"export_time":{},"messages":["#,
+                    serde_json::to_string(&self.user.get_username()).unwrap(),
+                    serde_json::to_string(&Utc::now().naive_utc()).unwrap(),
+                ));
+            };
+        };
+
+        let filter = db::MessageFilter { after: self.last_exported, ..Default::default() };
+        let page = db::Message::get_all_not_deleted_accessible_to_user(conn, &self.user, &filter, false, PAGE_SIZE, 0);
+        let is_last = (page.len() as i64) < PAGE_SIZE;
+
+        let mut attachments = HashMap::<i32, Vec<String>>::new();
+        for attachment in db::MessageAttachment::get_all_of_messages(conn, &page.iter().map(|m| m.id).collect::<Vec<_>>()) {
+            attachments.entry(attachment.message_id).or_default().push(attachment.name);
+        };
+
+        for message in page {
+            // the replied messages are always older, unless they are not accessible anymore
+            let thread_id = match message.replying_id {
+                Some(replying_id) => self.thread_ids.get(&replying_id).copied().unwrap_or(replying_id),
+                None => message.id,
+            };
+            self.thread_ids.insert(message.id, thread_id);
+
+            let recipients = message.get_recipients(conn).into_iter()
+                .map(|r| self.get_username(conn, r.recipient_id))
+                .collect();
+
+            let exported = ExportedMessage {
+                id: message.id,
+                conversation_id: message.conversation_id.expect("every message belongs to a conversation"),
+                thread_id,
+                replying_to: message.replying_id,
+                sender: self.get_username(conn, message.sender_id),
+                recipients,
+                content_type: message.content_type,
+                body: message.body.expect("the deleted messages are not exported"),
+                attachments: attachments.remove(&message.id).unwrap_or_default(),
+                sent_time: message.sent_time,
+                edited_time: message.edited_time,
+            };
+
+            match self.format {
+                ExportFormat::Json => {
+                    if self.last_exported.is_some() {
+                        chunk.push(',');
+                    };
+
+                    chunk.push_str(&serde_json::to_string(&exported).unwrap());
+                },
+                ExportFormat::Mbox => chunk.push_str(&to_mbox_entry(&exported)),
+            };
+
+            self.last_exported = Some((exported.sent_time, exported.id));
+        };
+
+        if is_last {
+            if let ExportFormat::Json = self.format {
+                chunk.push_str("]}");
+            };
+        };
+
+        (chunk, is_last)
+    }
+
+    fn get_username(&mut self, conn: &mut diesel::SqliteConnection, user_id: i32) -> String {
+        self.usernames.entry(user_id)
+            .or_insert_with(|| db::User::get(conn, user_id).expect("the participants should exist").get_username())
+            .clone()
+    }
+}
+
+
+fn to_mbox_entry(message: &ExportedMessage) -> String {
+    let sent_time = message.sent_time.and_utc();
+    let mime = match message.content_type.as_str() {
+        "markdown" => "text/markdown",
+        "structured" => "application/json",
+        _ => "text/plain",
+    };
+
+    let mut entry = format!("From {}@{MBOX_HOST} {}\n", message.sender, sent_time.format("%a %b %e %H:%M:%S %Y"));
+    entry.push_str(&format!("From: {}@{MBOX_HOST}\n", message.sender));
+    entry.push_str(&format!("To: {}\n", message.recipients.iter().map(|r| format!("{r}@{MBOX_HOST}")).collect::<Vec<_>>().join(", ")));
+    entry.push_str(&format!("Date: {}\n", sent_time.to_rfc2822()));
+    entry.push_str(&format!("Message-ID: <{}@{MBOX_HOST}>\n", message.id));
+
+    if let Some(replying_to) = message.replying_to {
+        entry.push_str(&format!("In-Reply-To: <{replying_to}@{MBOX_HOST}>\n"));
+        entry.push_str(&format!("References: <{}@{MBOX_HOST}>\n", message.thread_id));
+    };
+
+    if !message.attachments.is_empty() {
+        entry.push_str(&format!("X-Attachments: {}\n", message.attachments.join(", ").replace(['\r', '\n'], " ")));
+    };
+
+    entry.push_str(&format!("Content-Type: {mime}; charset=utf-8\n\n"));
+
+    // mboxrd quoting, so that the body lines wouldn't be taken for the start of another message
+    for line in message.body.lines() {
+        if line.trim_start_matches('>').starts_with("From ") {
+            entry.push('>');
+        };
+
+        entry.push_str(line);
+        entry.push('\n');
+    };
+
+    entry.push('\n');
+
+    entry
+}
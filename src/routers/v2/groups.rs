@@ -0,0 +1,191 @@
+use axum::extract::{Path, State};
+use axum::http::StatusCode;
+use axum::Json;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, get, post};
+use diesel::SqliteConnection;
+use crate::{AppState, db};
+use crate::routers::extractors::SessionUser;
+use super::schema::{Group, NewGroup};
+
+pub fn get_router() -> axum::Router<AppState> {
+    axum::Router::new()
+        .route("/", get(list_groups).post(create_group))
+        .route("/:name", get(get_group).delete(delete_group))
+        .route("/:name/members", post(add_members))
+        .route("/:name/members/:username", delete(remove_member))
+}
+
+
+enum GroupError {
+    InvalidName,
+    SuchNameIsAlreadyUsed,
+    GroupNotFound,
+    GroupNotAccessible,
+    NotAnOwner,
+    UserNotFound(String),
+}
+
+
+impl IntoResponse for GroupError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::InvalidName => (StatusCode::BAD_REQUEST, "the group name cannot be empty").into_response(),
+            Self::SuchNameIsAlreadyUsed => (StatusCode::CONFLICT, "such group name is already used").into_response(),
+            Self::GroupNotFound => (StatusCode::NOT_FOUND, "the group was not found").into_response(),
+            Self::GroupNotAccessible => (StatusCode::FORBIDDEN, "you are not a member of this group").into_response(),
+            Self::NotAnOwner => (StatusCode::FORBIDDEN, "only the owner of the group can do this").into_response(),
+            Self::UserNotFound(username) => (StatusCode::NOT_FOUND, format!("user '{username}' was not found")).into_response(),
+        }
+    }
+}
+
+
+async fn list_groups(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+) -> Json<Vec<Group>> {
+    let groups = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        db::UserGroup::get_all_of_user(conn, &user).into_iter()
+            .map(|g| to_group_view(conn, g))
+            .collect()
+    }).await.unwrap();
+    
+    Json(groups)
+}
+
+
+async fn create_group(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Json(NewGroup { name, members: members_usernames }): Json<NewGroup>,
+) -> Result<Json<Group>, GroupError> {
+    if name.trim().is_empty() {
+        return Err(GroupError::InvalidName);
+    };
+    
+    let group = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let members = get_users(conn, members_usernames)?;
+        
+        let group = db::UserGroup::create(conn, &user, &name).map_err(|err| match err {
+            db::UserGroupCreationError::SuchNameIsAlreadyUsed => GroupError::SuchNameIsAlreadyUsed,
+        })?;
+        
+        // the owner is a member as well, so that they would get the messages sent to the group
+        group.add_members(conn, &[user]);
+        group.add_members(conn, &members);
+        
+        Ok(to_group_view(conn, group))
+    }).await.unwrap()?;
+    
+    Ok(Json(group))
+}
+
+
+async fn get_group(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(name): Path<String>,
+) -> Result<Json<Group>, GroupError> {
+    let group = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let group = db::UserGroup::get_by_name(conn, &name).ok_or(GroupError::GroupNotFound)?;
+        
+        if !group.is_accessible_to(conn, &user) {
+            return Err(GroupError::GroupNotAccessible);
+        };
+        
+        Ok(to_group_view(conn, group))
+    }).await.unwrap()?;
+    
+    Ok(Json(group))
+}
+
+
+async fn delete_group(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(name): Path<String>,
+) -> Result<(), GroupError> {
+    tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let group = db::UserGroup::get_by_name(conn, &name).ok_or(GroupError::GroupNotFound)?;
+        
+        if !group.is_owned_by(&user) {
+            return Err(GroupError::NotAnOwner);
+        };
+        
+        group.delete(conn);
+        
+        Ok(())
+    }).await.unwrap()
+}
+
+
+async fn add_members(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(name): Path<String>,
+    Json(members_usernames): Json<Vec<String>>,
+) -> Result<Json<Group>, GroupError> {
+    let group = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let group = db::UserGroup::get_by_name(conn, &name).ok_or(GroupError::GroupNotFound)?;
+        
+        if !group.is_owned_by(&user) {
+            return Err(GroupError::NotAnOwner);
+        };
+        
+        let members = get_users(conn, members_usernames)?;
+        group.add_members(conn, &members);
+        
+        Ok(to_group_view(conn, group))
+    }).await.unwrap()?;
+    
+    Ok(Json(group))
+}
+
+
+/// either the owner removes someone, or a member leaves on their own
+async fn remove_member(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path((name, username)): Path<(String, String)>,
+) -> Result<(), GroupError> {
+    tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let group = db::UserGroup::get_by_name(conn, &name).ok_or(GroupError::GroupNotFound)?;
+        let member = db::User::get_by_username(conn, &username).ok_or(GroupError::UserNotFound(username))?;
+        
+        if !group.is_owned_by(&user) && member.id != user.id {
+            return Err(GroupError::NotAnOwner);
+        };
+        
+        group.remove_member(conn, &member);
+        
+        Ok(())
+    }).await.unwrap()
+}
+
+
+fn get_users(conn: &mut SqliteConnection, usernames: Vec<String>) -> Result<Vec<db::User>, GroupError> {
+    usernames.into_iter()
+        .map(|username| db::User::get_by_username(conn, &username).ok_or(GroupError::UserNotFound(username)))
+        .collect()
+}
+
+
+fn to_group_view(conn: &mut SqliteConnection, group: db::UserGroup) -> Group {
+    let owner = db::User::get(conn, group.owner_id).expect("users are never removed from the db");
+    let members = group.get_members(conn);
+    
+    Group::new(group, &owner, &members)
+}
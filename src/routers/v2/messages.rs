@@ -0,0 +1,701 @@
+use std::collections::{HashMap, HashSet};
+use std::convert::Infallible;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use axum::body::Body as ResponseBody;
+use axum::extract::{ConnectInfo, Path, Query, State};
+use axum::http::{header, StatusCode};
+use axum::Json;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post, put};
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use diesel::SqliteConnection;
+use crate::{AppState, db};
+use crate::content::{Body, ContentError};
+use crate::events::{Event, EventHub};
+use crate::filesystem::{Filesystem, FSError, UserScopedFS, is_active_content};
+use crate::limits::SendLimitError;
+use crate::routers::extractors::SessionUser;
+use super::export::{ExportFormat, MessageExporter};
+use super::schema::{Message, Reaction, NewMessage, NewReply, EditedMessage, MessageRevision, ReadState, BulkReadState, MarkedCount, UnreadCount, MessageSearchResult, usernames_of};
+
+pub fn get_router() -> axum::Router<AppState> {
+    axum::Router::new()
+        .route("/", get(list_messages).post(send_message))
+        .route("/search", get(search_messages))
+        .route("/unread", get(count_unread_messages))
+        .route("/export", get(export_messages))
+        .route("/read", post(mark_messages))
+        .route("/:id", get(get_message).put(edit_message).delete(delete_message))
+        .route("/:id/revisions", get(get_revisions))
+        .route("/:id/read", put(mark_message))
+        .route("/:id/reactions/:reaction", put(add_reaction).delete(remove_reaction))
+        .route("/:id/reply", post(reply_to_message))
+        .route("/:id/attachments/:attachment_id", get(get_attachment))
+}
+
+
+enum MessageError {
+    RecipientNotFound(String),
+    GroupNotFound(String),
+    GroupNotAccessible(String),
+    NoRecipients,
+    MessageNotFound,
+    MessageNotAccessible,
+    MessageIsDeleted,
+    NotTheSender,
+    NotARecipient,
+    AttachmentNotFound,
+    InvalidReaction,
+    ExpiresBeforeDelivery,
+    CorrespondentNotFound(String),
+    RecipientDoesNotAccept(String),
+    Limited(SendLimitError),
+    InvalidContent(ContentError),
+    FS(FSError),
+}
+
+
+impl IntoResponse for MessageError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::RecipientNotFound(username) => (StatusCode::NOT_FOUND, format!("user '{username}' was not found")).into_response(),
+            Self::GroupNotFound(name) => (StatusCode::NOT_FOUND, format!("group '{name}' was not found")).into_response(),
+            Self::GroupNotAccessible(name) => (StatusCode::FORBIDDEN, format!("you are not a member of group '{name}'")).into_response(),
+            Self::NoRecipients => (StatusCode::BAD_REQUEST, "there is no one to send the message to").into_response(),
+            Self::MessageNotFound => (StatusCode::NOT_FOUND, "the message was not found").into_response(),
+            Self::MessageNotAccessible => (StatusCode::FORBIDDEN, "you do not have such access to this message").into_response(),
+            Self::MessageIsDeleted => (StatusCode::GONE, "the message is deleted").into_response(),
+            Self::NotTheSender => (StatusCode::FORBIDDEN, "only the sender can do this").into_response(),
+            Self::NotARecipient => (StatusCode::FORBIDDEN, "only the recipients can do this").into_response(),
+            Self::AttachmentNotFound => (StatusCode::NOT_FOUND, "the attachment was not found").into_response(),
+            Self::InvalidReaction => (StatusCode::BAD_REQUEST, "the reaction should be an emoji or a short code like ':thumbs_up:'").into_response(),
+            Self::ExpiresBeforeDelivery => (StatusCode::BAD_REQUEST, "the message would expire before it's delivered").into_response(),
+            Self::RecipientDoesNotAccept(username) => (StatusCode::FORBIDDEN, format!("user '{username}' does not accept messages from you")).into_response(),
+            Self::CorrespondentNotFound(username) => (StatusCode::NOT_FOUND, format!("user '{username}' was not found")).into_response(),
+            Self::Limited(limit_err) => limit_err.into_response(),
+            Self::InvalidContent(content_err) => content_err.into_response(),
+            Self::FS(err @ FSError::NotEnoughStorage) => (StatusCode::PAYLOAD_TOO_LARGE, err.to_string()).into_response(),
+            Self::FS(err @ (FSError::PathBreaksOut | FSError::NotAFile)) => (StatusCode::BAD_REQUEST, err.to_string()).into_response(),
+            Self::FS(err @ FSError::UnsafeItem) => (StatusCode::FORBIDDEN, err.to_string()).into_response(),
+            Self::FS(FSError::HFS(err)) if err.kind() == ErrorKind::NotFound =>
+                (StatusCode::NOT_FOUND, "item at such path does not exist").into_response(),
+            Self::FS(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+        }
+    }
+}
+
+
+#[derive(Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Folder {
+    Inbox,
+    Sent,
+}
+
+
+#[derive(Deserialize)]
+struct ListQuery {
+    folder: Option<Folder>,
+    /// a username
+    correspondent: Option<String>,
+    since: Option<NaiveDateTime>,
+    until: Option<NaiveDateTime>,
+    #[serde(default)]
+    unread: bool,
+    count: Option<i64>,
+    offset: Option<u64>,
+    descending: Option<bool>,
+}
+
+
+async fn list_messages(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Query(query): Query<ListQuery>,
+) -> Result<Json<Vec<Message>>, MessageError> {
+    let messages = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let correspondent_id = match query.correspondent {
+            Some(username) => Some(db::User::get_by_username(conn, &username).ok_or(MessageError::CorrespondentNotFound(username))?.id),
+            None => None,
+        };
+        
+        let filter = db::MessageFilter {
+            folder: query.folder.map(|f| match f {
+                Folder::Inbox => db::MessageFolder::Inbox,
+                Folder::Sent => db::MessageFolder::Sent,
+            }),
+            correspondent_id,
+            since: query.since,
+            until: query.until,
+            is_unread_only: query.unread,
+            ..Default::default()
+        };
+        
+        let messages = db::Message::get_all_not_deleted_accessible_to_user(conn, &user, &filter,
+                                                                            query.descending.unwrap_or(true),
+                                                                            query.count.unwrap_or(-1),
+                                                                            query.offset.unwrap_or(0));
+        
+        Ok(to_message_views(conn, messages))
+    }).await.unwrap()?;
+    
+    Ok(Json(messages))
+}
+
+
+#[derive(Deserialize)]
+struct SearchQuery {
+    q: String,
+    count: Option<i64>,
+    offset: Option<u64>,
+    descending: Option<bool>,
+}
+
+
+async fn search_messages(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Query(SearchQuery { q, count, offset, descending }): Query<SearchQuery>,
+) -> Json<Vec<MessageSearchResult>> {
+    let results = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let hits = db::Message::search(conn, &user, &q, descending.unwrap_or(true), count.unwrap_or(-1), offset.unwrap_or(0));
+        
+        let (messages, snippets) = hits.into_iter()
+            .map(|hit| (hit.message, hit.snippet))
+            .unzip::<_, _, Vec<_>, Vec<_>>();
+        
+        to_message_views(conn, messages).into_iter()
+            .zip(snippets)
+            .map(|(message, snippet)| MessageSearchResult { message, snippet })
+            .collect::<Vec<_>>()
+    }).await.unwrap();
+    
+    Json(results)
+}
+
+
+async fn count_unread_messages(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+) -> Json<UnreadCount> {
+    let count = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        db::Message::count_unread_of(conn, &user)
+    }).await.unwrap();
+    
+    Json(UnreadCount { count })
+}
+
+
+/// the messages which can't be marked are skipped
+async fn mark_messages(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Json(BulkReadState { ids, read }): Json<BulkReadState>,
+) -> Json<MarkedCount> {
+    let marked = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        db::Message::set_read_state_of_many(conn, &user, &ids, read)
+    }).await.unwrap();
+    
+    Json(MarkedCount { marked })
+}
+
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    #[serde(default)]
+    format: ExportFormat,
+}
+
+
+/// everything the user has access to, the oldest first. it's streamed, as there can be a lot of it
+async fn export_messages(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Query(ExportQuery { format }): Query<ExportQuery>,
+) -> Response {
+    let exporter = MessageExporter::new(conn_pool, user, format);
+    
+    // None once the last chunk was sent
+    let chunks = futures::stream::unfold(Some(exporter), |exporter| async move {
+        let mut exporter = exporter?;
+        
+        let (chunk, is_last, exporter) = tokio::task::spawn_blocking(move || {
+            let (chunk, is_last) = exporter.next_chunk();
+            (chunk, is_last, exporter)
+        }).await.unwrap();
+        
+        Some((Ok::<_, Infallible>(chunk), if is_last { None } else { Some(exporter) }))
+    });
+    
+    (
+        [
+            (header::CONTENT_TYPE, format.mime().to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}\"", format.file_name())),
+        ],
+        ResponseBody::from_stream(chunks)
+    ).into_response()
+}
+
+
+async fn send_message(
+    State(AppState { conn_pool, filesystem, message_limits, .. }): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    SessionUser(user): SessionUser,
+    Json(NewMessage { recipients: recipients_usernames, groups, body, content_type, attachments, deliver_time, expire_time }): Json<NewMessage>,
+) -> Result<Json<Message>, MessageError> {
+    message_limits.check_send(user.id, addr.ip(), &body).map_err(MessageError::Limited)?;
+    let body = Body::new(content_type.into(), &body).map_err(MessageError::InvalidContent)?;
+    
+    let (user, recipients) = {
+        let conn_pool = conn_pool.clone();
+        
+        tokio::task::spawn_blocking(move || {
+            let conn = &mut conn_pool.get().unwrap();
+            
+            let mut recipients = Vec::new();
+            
+            for username in recipients_usernames {
+                let recipient = db::User::get_by_username(conn, &username).ok_or_else(|| MessageError::RecipientNotFound(username.clone()))?;
+                
+                // checked before the attachments get stored
+                if !recipient.accepts_messages_from(conn, &user) {
+                    return Err(MessageError::RecipientDoesNotAccept(username));
+                };
+                
+                recipients.push(recipient);
+            };
+            
+            for name in groups {
+                let group = db::UserGroup::get_by_name(conn, &name).ok_or_else(|| MessageError::GroupNotFound(name.clone()))?;
+                
+                if !group.is_accessible_to(conn, &user) {
+                    return Err(MessageError::GroupNotAccessible(name));
+                };
+                
+                // no need to send it to yourself just because you are in the group as well.
+                // the members who do not accept the message are left out, so that one of them wouldn't stop it for everyone
+                let members = group.get_members(conn).into_iter()
+                    .filter(|m| m.id != user.id && m.accepts_messages_from(conn, &user))
+                    .collect::<Vec<_>>();
+                recipients.extend(members);
+            };
+            
+            let mut seen_ids = HashSet::new();
+            recipients.retain(|u| seen_ids.insert(u.id));
+            
+            if recipients.is_empty() {
+                return Err(MessageError::NoRecipients);
+            };
+            
+            Ok((user, recipients))
+        }).await.unwrap()?
+    };
+    
+    let attachments = store_attachments(&filesystem, &user, attachments).await?;
+    
+    let schedule = db::MessageSchedule { deliver_time, expire_time };
+    
+    Ok(Json(send(&conn_pool, &filesystem, user, recipients, None, body, attachments, schedule).await?))
+}
+
+
+async fn get_message(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(id): Path<i32>,
+) -> Result<Json<Message>, MessageError> {
+    let message = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let mut msg = db::Message::get(conn, id).ok_or(MessageError::MessageNotFound)?;
+        
+        if !msg.is_accessible_to(conn, &user) {
+            return Err(MessageError::MessageNotAccessible);
+        };
+        
+        if msg.is_deleted_for(conn, &user) {
+            return Err(MessageError::MessageIsDeleted);
+        };
+        
+        // the sender is not one of the recipients (usually), so then it's fine for this to fail
+        let _ = msg.set_read_state(conn, &user, true);
+        
+        Ok(to_message_view(conn, msg))
+    }).await.unwrap()?;
+    
+    Ok(Json(message))
+}
+
+
+/// the previous body is kept as a revision
+async fn edit_message(
+    State(AppState { conn_pool, message_limits, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(id): Path<i32>,
+    Json(EditedMessage { body, content_type }): Json<EditedMessage>,
+) -> Result<Json<Message>, MessageError> {
+    message_limits.check_body(&body).map_err(MessageError::Limited)?;
+    
+    let message = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let mut msg = db::Message::get(conn, id).ok_or(MessageError::MessageNotFound)?;
+        
+        if msg.sender_id != user.id {
+            return Err(if msg.is_accessible_to(conn, &user) { MessageError::NotTheSender } else { MessageError::MessageNotAccessible });
+        };
+        
+        if msg.is_deleted_for(conn, &user) {
+            return Err(MessageError::MessageIsDeleted);
+        };
+        
+        let body = Body::new(content_type.map_or_else(|| msg.get_content_type(), Into::into), &body)
+            .map_err(MessageError::InvalidContent)?;
+        
+        msg.edit(conn, &body).map_err(|_| MessageError::MessageIsDeleted)?;
+        
+        Ok(to_message_view(conn, msg))
+    }).await.unwrap()?;
+    
+    Ok(Json(message))
+}
+
+
+async fn mark_message(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(id): Path<i32>,
+    Json(ReadState { read }): Json<ReadState>,
+) -> Result<(), MessageError> {
+    tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let mut msg = db::Message::get(conn, id).ok_or(MessageError::MessageNotFound)?;
+        
+        if !msg.is_accessible_to(conn, &user) {
+            return Err(MessageError::MessageNotAccessible);
+        };
+        
+        if msg.is_deleted_for(conn, &user) {
+            return Err(MessageError::MessageIsDeleted);
+        };
+        
+        // only the recipients have a read state
+        msg.set_read_state(conn, &user, read).map_err(|_| MessageError::NotARecipient)
+    }).await.unwrap()
+}
+
+
+/// the sender of the message gets notified
+async fn add_reaction(
+    State(AppState { conn_pool, events, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path((id, reaction)): Path<(i32, String)>,
+) -> Result<Json<Vec<Reaction>>, MessageError> {
+    if !db::MessageReaction::is_valid(&reaction) {
+        return Err(MessageError::InvalidReaction);
+    };
+    
+    Ok(Json(react(conn_pool, &events, user, id, reaction, true).await?))
+}
+
+
+async fn remove_reaction(
+    State(AppState { conn_pool, events, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path((id, reaction)): Path<(i32, String)>,
+) -> Result<Json<Vec<Reaction>>, MessageError> {
+    Ok(Json(react(conn_pool, &events, user, id, reaction, false).await?))
+}
+
+
+/// only the user's own copy is deleted, the others keep theirs
+async fn delete_message(
+    State(AppState { conn_pool, filesystem, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(id): Path<i32>,
+) -> Result<(), MessageError> {
+    let file_keys = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let mut msg = db::Message::get(conn, id).ok_or(MessageError::MessageNotFound)?;
+        
+        if !msg.is_accessible_to(conn, &user) {
+            return Err(MessageError::MessageNotAccessible);
+        };
+        
+        msg.delete_for(conn, &user).map_err(|_| MessageError::MessageIsDeleted)
+    }).await.unwrap()?;
+    
+    filesystem.remove_attachments(&file_keys).await;
+    
+    Ok(())
+}
+
+
+/// the oldest revision first
+async fn get_revisions(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(id): Path<i32>,
+) -> Result<Json<Vec<MessageRevision>>, MessageError> {
+    let revisions = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let msg = db::Message::get(conn, id).ok_or(MessageError::MessageNotFound)?;
+        
+        if !msg.is_accessible_to(conn, &user) {
+            return Err(MessageError::MessageNotAccessible);
+        };
+        
+        if msg.is_deleted_for(conn, &user) {
+            return Err(MessageError::MessageIsDeleted);
+        };
+        
+        Ok(msg.get_revisions(conn).into_iter().map(MessageRevision::from).collect::<Vec<_>>())
+    }).await.unwrap()?;
+    
+    Ok(Json(revisions))
+}
+
+
+/// the reply goes to everyone in the conversation
+async fn reply_to_message(
+    State(AppState { conn_pool, filesystem, message_limits, .. }): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    SessionUser(user): SessionUser,
+    Path(id): Path<i32>,
+    Json(NewReply { body, content_type, attachments, deliver_time, expire_time }): Json<NewReply>,
+) -> Result<Json<Message>, MessageError> {
+    message_limits.check_send(user.id, addr.ip(), &body).map_err(MessageError::Limited)?;
+    let body = Body::new(content_type.into(), &body).map_err(MessageError::InvalidContent)?;
+    
+    let (user, replied, recipients) = {
+        let conn_pool = conn_pool.clone();
+        
+        tokio::task::spawn_blocking(move || {
+            let conn = &mut conn_pool.get().unwrap();
+            
+            let replied = db::Message::get(conn, id).ok_or(MessageError::MessageNotFound)?;
+            
+            if !replied.is_accessible_to(conn, &user) {
+                return Err(MessageError::MessageNotAccessible);
+            };
+            
+            let conversation = db::Conversation::get(conn, replied.conversation_id.expect("every message belongs to a conversation"))
+                .expect("conversations are not deleted while they have messages");
+            
+            let mut recipients = conversation.get_participants(conn).into_iter()
+                .filter(|u| !u.is_deleted && u.id != user.id && u.accepts_messages_from(conn, &user))
+                .collect::<Vec<_>>();
+            
+            // the sender of the replied message becomes the primary recipient, the same as it would be in v1
+            recipients.sort_by_key(|u| u.id != replied.sender_id);
+            
+            if recipients.is_empty() {
+                return Err(MessageError::NoRecipients);
+            };
+            
+            Ok((user, replied, recipients))
+        }).await.unwrap()?
+    };
+    
+    let attachments = store_attachments(&filesystem, &user, attachments).await?;
+    
+    let schedule = db::MessageSchedule { deliver_time, expire_time };
+    
+    Ok(Json(send(&conn_pool, &filesystem, user, recipients, Some(replied), body, attachments, schedule).await?))
+}
+
+
+async fn get_attachment(
+    State(AppState { conn_pool, filesystem, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path((id, attachment_id)): Path<(i32, i32)>,
+) -> Result<Response, MessageError> {
+    let attachment = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let msg = db::Message::get(conn, id).ok_or(MessageError::MessageNotFound)?;
+        
+        if !msg.is_accessible_to(conn, &user) {
+            return Err(MessageError::MessageNotAccessible);
+        };
+        
+        if msg.is_deleted_for(conn, &user) {
+            return Err(MessageError::MessageIsDeleted);
+        };
+        
+        db::MessageAttachment::get(conn, attachment_id)
+            .filter(|a| a.message_id == msg.id)
+            .ok_or(MessageError::AttachmentNotFound)
+    }).await.unwrap()?;
+    
+    let data = filesystem.read_attachment(&attachment.file_key).await.map_err(MessageError::FS)?;
+    let mime = attachment.mime.unwrap_or("application/octet-stream".to_string());
+    
+    // the same as with the files from the filesystem, anything which can run scripts must not be rendered inline
+    let disposition = if is_active_content(&mime) { "attachment" } else { "inline" };
+    let filename = attachment.name.replace(['"', '\\', '\r', '\n'], "_");
+    
+    Ok((
+        [
+            (header::CONTENT_TYPE, mime),
+            (header::CONTENT_DISPOSITION, format!("{disposition}; filename=\"{filename}\"")),
+            (header::X_CONTENT_TYPE_OPTIONS, "nosniff".to_string()),
+            (header::CONTENT_SECURITY_POLICY, "sandbox".to_string()),
+        ],
+        data
+    ).into_response())
+}
+
+
+async fn store_attachments(filesystem: &Filesystem, user: &db::User, paths: Vec<String>) -> Result<Vec<db::NewMessageAttachment>, MessageError> {
+    if paths.is_empty() {
+        return Ok(Vec::new());
+    };
+    
+    let usfs = UserScopedFS::new(filesystem, user).await.map_err(MessageError::FS)?;
+    
+    let paths = paths.into_iter().map(PathBuf::from).collect::<Vec<_>>();
+    
+    Ok(usfs.store_attachments(&paths).await.map_err(MessageError::FS)?
+        .into_iter()
+        .map(|a| db::NewMessageAttachment { file_key: a.key, name: a.name, size: a.size as i64, mime: a.mime })
+        .collect())
+}
+
+
+/// the stored attachments are removed if the message can't be sent
+#[allow(clippy::too_many_arguments)]
+async fn send(
+    conn_pool: &db::ConnPool,
+    filesystem: &Filesystem,
+    sender: db::User,
+    recipients: Vec<db::User>,
+    replied: Option<db::Message>,
+    body: Body,
+    attachments: Vec<db::NewMessageAttachment>,
+    schedule: db::MessageSchedule,
+) -> Result<Message, MessageError> {
+    let file_keys = attachments.iter().map(|a| a.file_key.clone()).collect::<Vec<_>>();
+    let conn_pool = conn_pool.clone();
+    
+    let res = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let msg = db::Message::send(conn, &sender, &recipients, replied.as_ref(), &body, &attachments, schedule)?;
+        
+        Ok(to_message_view(conn, msg))
+    }).await.unwrap();
+    
+    match res {
+        Ok(message) => Ok(message),
+        Err(err) => {
+            filesystem.remove_attachments(&file_keys).await;
+            
+            Err(match err {
+                db::MessageSendError::RecipientDoesNotAccept(username) => MessageError::RecipientDoesNotAccept(username),
+                db::MessageSendError::ExpiresBeforeDelivery => MessageError::ExpiresBeforeDelivery,
+            })
+        },
+    }
+}
+
+
+/// returns all the reactions to the message afterwards
+async fn react(conn_pool: db::ConnPool, events: &EventHub, user: db::User, id: i32, reaction: String, is_added: bool) -> Result<Vec<Reaction>, MessageError> {
+    let (username, event_reaction) = (user.get_username(), reaction.clone());
+    
+    let (notified_id, reactions) = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let msg = db::Message::get(conn, id).ok_or(MessageError::MessageNotFound)?;
+        
+        if !msg.is_accessible_to(conn, &user) {
+            return Err(MessageError::MessageNotAccessible);
+        };
+        
+        if msg.is_deleted_for(conn, &user) {
+            return Err(MessageError::MessageIsDeleted);
+        };
+        
+        let is_changed = if is_added {
+            db::MessageReaction::add(conn, &msg, &user, &reaction)
+        } else {
+            db::MessageReaction::remove(conn, &msg, &user, &reaction)
+        };
+        
+        // there's no need to tell the sender about their own reactions, or about the messages they have deleted
+        let sender = msg.get_sender(conn);
+        let notified_id = Some(sender.id).filter(|_| is_changed && sender.id != user.id && !msg.is_deleted_for(conn, &sender));
+        
+        let conversation = db::Conversation::get(conn, msg.conversation_id.expect("every message belongs to a conversation"))
+            .expect("conversations are not deleted while they have messages");
+        let usernames = usernames_of(&conversation.get_participants(conn));
+        
+        Ok((notified_id, Reaction::all_of(db::MessageReaction::get_all_of_messages(conn, &[msg.id]), &usernames)))
+    }).await.unwrap()?;
+    
+    if let Some(sender_id) = notified_id {
+        events.publish(sender_id, Event::Reaction { message_id: id, username, reaction: event_reaction, added: is_added });
+    };
+    
+    Ok(reactions)
+}
+
+
+fn to_message_view(conn: &mut SqliteConnection, msg: db::Message) -> Message {
+    to_message_views(conn, vec![msg]).pop().expect("there is a view for every message")
+}
+
+
+/// loads what the messages need for all of them at once, instead of message by message
+fn to_message_views(conn: &mut SqliteConnection, messages: Vec<db::Message>) -> Vec<Message> {
+    let ids = messages.iter().map(|msg| msg.id).collect::<Vec<_>>();
+    
+    let mut recipients = group_by_message(db::Message::get_recipients_of_all(conn, &messages), |r| r.message_id);
+    let mut attachments = group_by_message(db::MessageAttachment::get_all_of_messages(conn, &ids), |a| a.message_id);
+    let mut reactions = group_by_message(db::MessageReaction::get_all_of_messages(conn, &ids), |r| r.message_id);
+    
+    // the scheduled recipients are not participants yet, so the users are looked up directly
+    let mut users_ids = messages.iter().map(|msg| msg.sender_id).collect::<Vec<_>>();
+    users_ids.extend(recipients.values().flatten().map(|r| r.recipient_id));
+    users_ids.extend(reactions.values().flatten().map(|r| r.user_id));
+    users_ids.sort_unstable();
+    users_ids.dedup();
+    let usernames = usernames_of(&db::User::get_all_by_ids(conn, &users_ids));
+    
+    messages.into_iter()
+        .map(|msg| {
+            let id = msg.id;
+            Message::new(
+                msg,
+                recipients.remove(&id).unwrap_or_default(),
+                attachments.remove(&id).unwrap_or_default(),
+                reactions.remove(&id).unwrap_or_default(),
+                &usernames,
+            )
+        })
+        .collect()
+}
+
+
+fn group_by_message<T>(items: Vec<T>, message_id: impl Fn(&T) -> i32) -> HashMap<i32, Vec<T>> {
+    let mut grouped = HashMap::<i32, Vec<T>>::new();
+    for item in items {
+        grouped.entry(message_id(&item)).or_default().push(item);
+    };
+    grouped
+}
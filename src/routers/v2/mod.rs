@@ -2,6 +2,12 @@ mod meta;
 mod schema;
 mod token;
 mod users;
+mod admin;
+mod conversations;
+mod messages;
+mod groups;
+mod events;
+mod export;
 
 use crate::AppState;
 
@@ -9,5 +15,10 @@ pub fn get_router() -> axum::Router<AppState> {
     axum::Router::new()
         .nest("/token", token::get_router())
         .nest("/users", users::get_router())
+        .nest("/admin", admin::get_router())
+        .nest("/conversations", conversations::get_router())
+        .nest("/messages", messages::get_router())
+        .nest("/groups", groups::get_router())
+        .nest("/events", events::get_router())
         .nest("/", meta::get_router())
 }
@@ -0,0 +1,91 @@
+use std::path::PathBuf;
+use serde::{Deserialize, Serialize};
+use chrono::NaiveDateTime;
+use crate::{db, filesystem, tasks};
+
+#[derive(Serialize, Deserialize)]
+pub struct TemplateRollout {
+    pub template: String,
+    pub version: i32,
+    /// how many users are going to get it
+    pub users: u32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct IntegrityReport {
+    pub orphaned_dirs: Vec<String>,
+    pub users_without_dirs: Vec<i32>,
+    pub stale_staging_items: Vec<String>,
+    pub orphaned_attachments: Vec<String>,
+    pub dangling_records: Vec<String>,
+    pub unrecorded_items: Vec<String>,
+    pub fixed: bool,
+}
+
+impl From<filesystem::IntegrityReport> for IntegrityReport {
+    fn from(report: filesystem::IntegrityReport) -> Self {
+        let to_strings = |paths: Vec<PathBuf>| paths.iter().map(|p| p.to_string_lossy().to_string()).collect();
+        
+        Self {
+            orphaned_dirs: to_strings(report.orphaned_dirs),
+            users_without_dirs: report.users_without_dirs,
+            stale_staging_items: to_strings(report.stale_staging_items),
+            orphaned_attachments: to_strings(report.orphaned_attachments),
+            dangling_records: report.dangling_records,
+            unrecorded_items: report.unrecorded_items,
+            fixed: report.is_fixed,
+        }
+    }
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct BlockedUser {
+    pub username: String,
+    pub blocked_by: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct BlockStats {
+    pub total: i64,
+    pub blockers: i64,
+    pub blocked: i64,
+    pub most_blocked: Vec<BlockedUser>,
+}
+
+impl From<db::BlockStats> for BlockStats {
+    fn from(stats: db::BlockStats) -> Self {
+        Self {
+            total: stats.total,
+            blockers: stats.blockers,
+            blocked: stats.blocked,
+            most_blocked: stats.most_blocked.into_iter()
+                .map(|(user, count)| BlockedUser { username: user.get_username(), blocked_by: count })
+                .collect(),
+        }
+    }
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct TokenStats {
+    /// all of them, the expired ones which are not purged yet included
+    pub total: i64,
+    pub sweeps: u64,
+    /// since the server has started
+    pub purged: u64,
+    pub last_purged: u64,
+    pub last_sweep_time: Option<NaiveDateTime>,
+}
+
+impl TokenStats {
+    pub fn new(total: i64, stats: tasks::TokenSweepStats) -> Self {
+        Self {
+            total,
+            sweeps: stats.runs,
+            purged: stats.purged,
+            last_purged: stats.last_purged,
+            last_sweep_time: stats.last_run_time,
+        }
+    }
+}
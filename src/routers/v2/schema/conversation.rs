@@ -0,0 +1,85 @@
+use std::collections::HashMap;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use crate::db;
+use super::Message;
+
+#[derive(Serialize, Deserialize)]
+pub struct ConversationPreview {
+    pub id: i32,
+    pub participants: Vec<String>,
+    pub last_message: Option<Message>,
+    pub unread_count: i64,
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct Conversation {
+    pub id: i32,
+    pub creation_time: NaiveDateTime,
+    pub participants: Vec<String>,
+    pub messages: Vec<Message>,
+}
+
+
+impl From<db::ConversationOverview> for ConversationPreview {
+    fn from(overview: db::ConversationOverview) -> Self {
+        let usernames = usernames_of(&overview.participants);
+        
+        Self {
+            id: overview.conversation.id,
+            participants: overview.participants.iter().map(db::User::get_username).collect(),
+            last_message: overview.last_message
+                .map(|m| Message::new(m, overview.last_message_recipients, overview.last_message_attachments, overview.last_message_reactions, &usernames)),
+            unread_count: overview.unread_count,
+        }
+    }
+}
+
+
+impl Conversation {
+    pub fn new(
+        conversation: db::Conversation,
+        participants: Vec<db::User>,
+        messages: Vec<db::Message>,
+        recipients: Vec<db::MessageRecipient>,
+        attachments: Vec<db::MessageAttachment>,
+        reactions: Vec<db::MessageReaction>,
+    ) -> Self {
+        let usernames = usernames_of(&participants);
+        
+        let mut recipients_by_message = HashMap::<i32, Vec<db::MessageRecipient>>::new();
+        for recipient in recipients {
+            recipients_by_message.entry(recipient.message_id).or_default().push(recipient);
+        };
+        
+        let mut attachments_by_message = HashMap::<i32, Vec<db::MessageAttachment>>::new();
+        for attachment in attachments {
+            attachments_by_message.entry(attachment.message_id).or_default().push(attachment);
+        };
+        
+        let mut reactions_by_message = HashMap::<i32, Vec<db::MessageReaction>>::new();
+        for reaction in reactions {
+            reactions_by_message.entry(reaction.message_id).or_default().push(reaction);
+        };
+        
+        Self {
+            id: conversation.id,
+            creation_time: conversation.creation_time,
+            participants: participants.iter().map(db::User::get_username).collect(),
+            messages: messages.into_iter()
+                .map(|m| {
+                    let recipients = recipients_by_message.remove(&m.id).unwrap_or_default();
+                    let attachments = attachments_by_message.remove(&m.id).unwrap_or_default();
+                    let reactions = reactions_by_message.remove(&m.id).unwrap_or_default();
+                    Message::new(m, recipients, attachments, reactions, &usernames)
+                })
+                .collect(),
+        }
+    }
+}
+
+
+pub fn usernames_of(users: &[db::User]) -> HashMap<i32, String> {
+    users.iter().map(|u| (u.id, u.get_username())).collect()
+}
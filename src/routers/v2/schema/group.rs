@@ -0,0 +1,31 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use crate::db;
+
+#[derive(Serialize, Deserialize)]
+pub struct Group {
+    pub name: String,
+    pub owner: String,
+    pub members: Vec<String>,
+    pub creation_time: NaiveDateTime,
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct NewGroup {
+    pub name: String,
+    #[serde(default)]
+    pub members: Vec<String>,
+}
+
+
+impl Group {
+    pub fn new(group: db::UserGroup, owner: &db::User, members: &[db::User]) -> Self {
+        Self {
+            name: group.name,
+            owner: owner.get_username(),
+            members: members.iter().map(db::User::get_username).collect(),
+            creation_time: group.creation_time,
+        }
+    }
+}
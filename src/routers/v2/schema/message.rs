@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use crate::content;
+use crate::db;
+
+#[derive(Serialize, Deserialize)]
+pub struct MessageRecipient {
+    pub username: String,
+    /// missing if the message is deleted
+    pub read: Option<bool>,
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct MessageAttachment {
+    pub id: i32,
+    pub name: String,
+    pub size: i64,
+    pub mime: Option<String>,
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct Reaction {
+    pub reaction: String,
+    pub count: usize,
+    /// the earliest first
+    pub users: Vec<String>,
+}
+
+
+#[derive(Serialize, Deserialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentType {
+    #[default]
+    Plain,
+    Markdown,
+    /// a list of `text`, `mention` and `link` spans
+    Structured,
+}
+
+
+impl From<content::ContentType> for ContentType {
+    fn from(content_type: content::ContentType) -> Self {
+        match content_type {
+            content::ContentType::Plain => Self::Plain,
+            content::ContentType::Markdown => Self::Markdown,
+            content::ContentType::Structured => Self::Structured,
+        }
+    }
+}
+
+
+impl From<ContentType> for content::ContentType {
+    fn from(content_type: ContentType) -> Self {
+        match content_type {
+            ContentType::Plain => Self::Plain,
+            ContentType::Markdown => Self::Markdown,
+            ContentType::Structured => Self::Structured,
+        }
+    }
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct Message {
+    pub id: i32,
+    pub conversation_id: i32,
+    pub sender: String,
+    pub recipients: Vec<MessageRecipient>,
+    /// missing if the message is deleted
+    pub body: Option<String>,
+    pub content_type: ContentType,
+    pub attachments: Vec<MessageAttachment>,
+    pub reactions: Vec<Reaction>,
+    pub replying_to: Option<i32>,
+    /// when the message is going to be delivered, if it's pending
+    pub sent_time: NaiveDateTime,
+    pub edited_time: Option<NaiveDateTime>,
+    /// whether the message is yet to be delivered. only its sender can see it until then
+    pub pending: bool,
+    pub expire_time: Option<NaiveDateTime>,
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct NewMessage {
+    #[serde(default)]
+    pub recipients: Vec<String>,
+    #[serde(default)]
+    pub groups: Vec<String>,
+    pub body: String,
+    #[serde(default)]
+    pub content_type: ContentType,
+    /// paths of the files from the sender's filesystem
+    #[serde(default)]
+    pub attachments: Vec<String>,
+    /// the message is delivered right away if missing
+    pub deliver_time: Option<NaiveDateTime>,
+    /// when the message gets purged
+    pub expire_time: Option<NaiveDateTime>,
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct NewReply {
+    pub body: String,
+    #[serde(default)]
+    pub content_type: ContentType,
+    /// paths of the files from the sender's filesystem
+    #[serde(default)]
+    pub attachments: Vec<String>,
+    /// the message is delivered right away if missing
+    pub deliver_time: Option<NaiveDateTime>,
+    /// when the message gets purged
+    pub expire_time: Option<NaiveDateTime>,
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct EditedMessage {
+    pub body: String,
+    /// the message keeps its content type if missing
+    pub content_type: Option<ContentType>,
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct ReadState {
+    pub read: bool,
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct BulkReadState {
+    pub ids: Vec<i32>,
+    pub read: bool,
+}
+
+
+/// how many of the messages got marked
+#[derive(Serialize, Deserialize)]
+pub struct MarkedCount {
+    pub marked: usize,
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct UnreadCount {
+    pub count: i64,
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct MessageSearchResult {
+    pub message: Message,
+    /// html, with the matches wrapped in `<mark>`
+    pub snippet: String,
+}
+
+
+/// a previous body of an edited message
+#[derive(Serialize, Deserialize)]
+pub struct MessageRevision {
+    pub body: String,
+    pub content_type: ContentType,
+    pub creation_time: NaiveDateTime,
+}
+
+
+impl From<db::MessageRevision> for MessageRevision {
+    fn from(revision: db::MessageRevision) -> Self {
+        Self { content_type: revision.get_content_type().into(), body: revision.body, creation_time: revision.creation_time }
+    }
+}
+
+
+impl Message {
+    /// the usernames are expected to contain the sender, the recipients and everyone who reacted
+    pub fn new(
+        message: db::Message,
+        recipients: Vec<db::MessageRecipient>,
+        attachments: Vec<db::MessageAttachment>,
+        reactions: Vec<db::MessageReaction>,
+        usernames: &HashMap<i32, String>,
+    ) -> Self {
+        Self {
+            id: message.id,
+            conversation_id: message.conversation_id.expect("every message belongs to a conversation"),
+            sender: usernames[&message.sender_id].clone(),
+            recipients: recipients.into_iter()
+                .map(|r| MessageRecipient { username: usernames[&r.recipient_id].clone(), read: r.is_read })
+                .collect(),
+            content_type: message.get_content_type().into(),
+            body: message.body,
+            attachments: attachments.into_iter()
+                .map(|a| MessageAttachment { id: a.id, name: a.name, size: a.size, mime: a.mime })
+                .collect(),
+            reactions: Reaction::all_of(reactions, usernames),
+            replying_to: message.replying_id,
+            sent_time: message.sent_time,
+            edited_time: message.edited_time,
+            pending: message.is_pending,
+            expire_time: message.expire_time,
+        }
+    }
+}
+
+
+impl Reaction {
+    /// the usernames are expected to contain everyone who reacted
+    pub fn all_of(reactions: Vec<db::MessageReaction>, usernames: &HashMap<i32, String>) -> Vec<Self> {
+        db::MessageReaction::summarize(reactions).into_iter()
+            .map(|s| Self {
+                reaction: s.reaction,
+                count: s.users_ids.len(),
+                users: s.users_ids.iter().map(|id| usernames[id].clone()).collect(),
+            })
+            .collect()
+    }
+}
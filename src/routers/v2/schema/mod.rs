@@ -1,7 +1,15 @@
 mod meta_info;
 mod session;
 mod user;
+mod admin;
+mod conversation;
+mod message;
+mod group;
 
 pub use meta_info::MetaInfo;
-pub use session::{Session, NewSession};
-pub use user::{NewUser, SelfUser};
+pub use session::{Session, NewSession, SecondFactorChallenge, SecondFactorLogin, SessionRefresh, ActiveSession, RevokedCount};
+pub use user::{NewUser, SelfUser, Privacy, TwoFactorStatus, TwoFactorEnrollment, TwoFactorCode, RecoveryCodes};
+pub use admin::{TemplateRollout, IntegrityReport, BlockStats, TokenStats};
+pub use conversation::{Conversation, ConversationPreview, usernames_of};
+pub use message::{Message, Reaction, NewMessage, NewReply, EditedMessage, MessageRevision, ReadState, BulkReadState, MarkedCount, UnreadCount, MessageSearchResult};
+pub use group::{Group, NewGroup};
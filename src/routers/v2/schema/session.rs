@@ -1,10 +1,14 @@
 use axum_typed_multipart::TryFromMultipart;
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use crate::db;
 
 #[derive(Serialize, Deserialize)]
 pub struct Session {
-    pub access_token: String
+    pub access_token: String,
+    pub refresh_token: String,
+    /// when the access token has to be refreshed
+    pub expiry_time: Option<NaiveDateTime>,
 }
 
 
@@ -12,12 +16,73 @@ pub struct Session {
 pub struct NewSession {
     pub username: String,
     pub password: String,
+    /// a name for the device or the app, to tell the sessions apart
+    pub label: Option<String>,
 }
 
 
+/// given instead of a session, when the user has the second factor enabled
+#[derive(Serialize, Deserialize)]
+pub struct SecondFactorChallenge {
+    pub challenge: String,
+}
+
+
+#[derive(Serialize, Deserialize, TryFromMultipart)]
+pub struct SecondFactorLogin {
+    pub challenge: String,
+    /// either from the authenticator, or one of the recovery codes
+    pub code: String,
+}
+
+
+#[derive(Serialize, Deserialize, TryFromMultipart)]
+pub struct SessionRefresh {
+    pub refresh_token: String,
+}
+
+
+/// a session as seen by its owner, without the token itself
+#[derive(Serialize, Deserialize)]
+pub struct ActiveSession {
+    pub id: i32,
+    pub label: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub creation_time: NaiveDateTime,
+    pub last_used_time: Option<NaiveDateTime>,
+    pub expiry_time: Option<NaiveDateTime>,
+    /// whether it's the session the request was made with
+    pub current: bool,
+}
+
+
+/// how many sessions were ended
+#[derive(Serialize, Deserialize)]
+pub struct RevokedCount {
+    pub revoked: usize,
+}
+
+
+
+impl From<db::IssuedToken> for Session {
+    fn from(t: db::IssuedToken) -> Self {
+        Self { access_token: t.access_token, refresh_token: t.refresh_token, expiry_time: t.token.access_expiry_time }
+    }
+}
+
 
-impl From<db::Token> for Session {
-    fn from(t: db::Token) -> Self {
-        Self { access_token: t.value }
+impl ActiveSession {
+    pub fn new(t: db::Token, current: &db::Token) -> Self {
+        Self {
+            current: t.id == current.id,
+            expiry_time: t.get_expiry_time(),
+            id: t.id,
+            label: t.label,
+            ip: t.ip,
+            user_agent: t.user_agent,
+            creation_time: t.creation_time,
+            last_used_time: t.last_used_time,
+        }
     }
 }
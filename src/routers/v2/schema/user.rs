@@ -1,10 +1,12 @@
 use serde::{Deserialize, Serialize};
+use crate::db;
 
 #[derive(Serialize, Deserialize)]
 pub struct NewUser {
     pub username: String,
     pub password: String,
     pub properties: Option<serde_json::Value>,
+    pub template: Option<String>,
 }
 
 
@@ -14,3 +16,71 @@ pub struct SelfUser {
     pub id: i32,
     pub properties: serde_json::Value,
 }
+
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum MessagePrivacy {
+    Everyone,
+    Contacts,
+    Nobody,
+}
+
+
+impl From<db::MessagePrivacy> for MessagePrivacy {
+    fn from(privacy: db::MessagePrivacy) -> Self {
+        match privacy {
+            db::MessagePrivacy::Everyone => Self::Everyone,
+            db::MessagePrivacy::Contacts => Self::Contacts,
+            db::MessagePrivacy::Nobody => Self::Nobody,
+        }
+    }
+}
+
+
+impl From<MessagePrivacy> for db::MessagePrivacy {
+    fn from(privacy: MessagePrivacy) -> Self {
+        match privacy {
+            MessagePrivacy::Everyone => Self::Everyone,
+            MessagePrivacy::Contacts => Self::Contacts,
+            MessagePrivacy::Nobody => Self::Nobody,
+        }
+    }
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct Privacy {
+    /// who may message the user
+    pub messages: MessagePrivacy,
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct TwoFactorStatus {
+    pub enabled: bool,
+    pub recovery_codes_left: i64,
+}
+
+
+/// to be added to an authenticator app, and confirmed with a code from it
+#[derive(Serialize, Deserialize)]
+pub struct TwoFactorEnrollment {
+    /// base32 encoded
+    pub secret: String,
+    /// an otpauth uri, usually shown as a qr code
+    pub uri: String,
+}
+
+
+#[derive(Serialize, Deserialize)]
+pub struct TwoFactorCode {
+    pub code: String,
+}
+
+
+/// each of them works once, they are not shown again
+#[derive(Serialize, Deserialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
@@ -1,36 +1,152 @@
+use std::net::SocketAddr;
 use std::time::Duration;
-use axum::extract::{State};
+use axum::extract::{ConnectInfo, Path, State};
 use axum::{Json};
 use axum::http::StatusCode;
-use axum::routing::post;
+use axum::response::{IntoResponse, Response};
+use axum::routing::{delete, post};
+use axum_extra::headers::UserAgent;
+use axum_extra::TypedHeader;
 use axum_typed_multipart::TypedMultipart;
 use crate::{AppState, db};
 use crate::routers::extractors::SessionToken;
-use super::schema::{NewSession, Session};
+use crate::config::AuthConfig;
+use super::schema::{NewSession, Session, SecondFactorChallenge, SecondFactorLogin, SessionRefresh, ActiveSession, RevokedCount};
 
 pub fn get_router() -> axum::Router<AppState> {
     axum::Router::new()
-        .route("/", post(create_session).delete(delete_session))
+        .route("/", post(create_session).get(get_sessions).delete(delete_session))
+        .route("/second-factor", post(complete_session))
+        .route("/refresh", post(refresh_session))
+        .route("/others", delete(delete_other_sessions))
+        .route("/:id", delete(revoke_session))
+}
+
+
+fn get_lifetimes(config: &AuthConfig) -> db::TokenLifetimes {
+    db::TokenLifetimes {
+        session: config.session_lifetime.map(Duration::from_secs),
+        access: config.access_token_lifetime.map(Duration::from_secs),
+        idle: config.idle_timeout.map(Duration::from_secs),
+    }
 }
 
 
 async fn create_session(
     State(AppState { conn_pool, config, .. }): State<AppState>,
-    TypedMultipart(NewSession { username, password }): TypedMultipart<NewSession>  // todo somehow make it support both multipart and form data
-) -> Result<Json<Session>, StatusCode> {
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    user_agent: Option<TypedHeader<UserAgent>>,
+    TypedMultipart(NewSession { username, password, label }): TypedMultipart<NewSession>  // todo somehow make it support both multipart and form data
+) -> Result<Response, StatusCode> {
+    let client = db::TokenClient {
+        label,
+        ip: Some(addr.ip().to_string()),
+        user_agent: user_agent.map(|TypedHeader(ua)| ua.to_string()),
+    };
+    
     let token = tokio::task::spawn_blocking(move || {
         let conn = &mut conn_pool.get().unwrap();
         
-        db::Token::auth(conn, &username, &password, config.auth.session_lifetime.map(Duration::from_secs))
+        db::Token::auth(conn, &username, &password, get_lifetimes(&config.auth), client)
     }).await.unwrap();
     
     match token {
         None => Err(StatusCode::UNAUTHORIZED),
-        Some(t) => Ok(Json(Session::from(t)))
+        Some(db::TokenAuth::Issued(t)) => Ok(Json(Session::from(t)).into_response()),
+        // the login is not done yet, it's to be continued with the second-factor
+        Some(db::TokenAuth::SecondFactorRequired(challenge)) => Ok((StatusCode::ACCEPTED, Json(SecondFactorChallenge { challenge })).into_response()),
+    }
+}
+
+
+enum SecondFactorLoginError {
+    ChallengeNotFound,
+    InvalidCode,
+    Locked,
+}
+
+
+impl IntoResponse for SecondFactorLoginError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::ChallengeNotFound => (StatusCode::UNAUTHORIZED, "the login has expired, start it again").into_response(),
+            Self::InvalidCode => (StatusCode::UNAUTHORIZED, "the code is invalid").into_response(),
+            Self::Locked => (StatusCode::TOO_MANY_REQUESTS, "too many invalid codes were given, try again later").into_response(),
+        }
+    }
+}
+
+
+/// the second step of the login, for the users with the second factor
+async fn complete_session(
+    State(AppState { conn_pool, config, .. }): State<AppState>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    user_agent: Option<TypedHeader<UserAgent>>,
+    TypedMultipart(SecondFactorLogin { challenge, code }): TypedMultipart<SecondFactorLogin>
+) -> Result<Json<Session>, SecondFactorLoginError> {
+    // the label was given with the password
+    let client = db::TokenClient {
+        label: None,
+        ip: Some(addr.ip().to_string()),
+        user_agent: user_agent.map(|TypedHeader(ua)| ua.to_string()),
+    };
+    
+    let token = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        db::Token::auth_second_factor(conn, &challenge, &code, get_lifetimes(&config.auth), client)
+    }).await.unwrap();
+    
+    match token {
+        Err(db::LoginChallengeError::NotFound) => Err(SecondFactorLoginError::ChallengeNotFound),
+        Err(db::LoginChallengeError::InvalidCode) => Err(SecondFactorLoginError::InvalidCode),
+        Err(db::LoginChallengeError::Locked) => Err(SecondFactorLoginError::Locked),
+        Ok(t) => Ok(Json(Session::from(t))),
     }
 }
 
 
+/// swaps the refresh token for a new pair of tokens. reusing a refresh token ends the session
+async fn refresh_session(
+    State(AppState { conn_pool, config, .. }): State<AppState>,
+    TypedMultipart(SessionRefresh { refresh_token }): TypedMultipart<SessionRefresh>
+) -> Result<Json<Session>, StatusCode> {
+    let token = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        db::Token::refresh(conn, &refresh_token, get_lifetimes(&config.auth).access)
+    }).await.unwrap();
+    
+    match token {
+        // it's all the same for the client, it has to log in again
+        Err(db::TokenRefreshError::NotFound | db::TokenRefreshError::Reused | db::TokenRefreshError::Expired) => Err(StatusCode::UNAUTHORIZED),
+        Ok(t) => Ok(Json(Session::from(t)))
+    }
+}
+
+
+/// all the sessions of the user, the newest first
+async fn get_sessions(
+    State(AppState { conn_pool, token_usage, .. }): State<AppState>,
+    SessionToken(token): SessionToken
+) -> Json<Vec<ActiveSession>> {
+    let sessions = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let owner = token.get_owner(conn);
+        
+        // the expired ones are only deleted once they are used, so there can still be some
+        db::Token::get_all_by_owner(conn, &owner).into_iter()
+            .map(|mut t| { token_usage.update(&mut t); t })
+            .filter(|t| !t.is_expired())
+            .map(|t| ActiveSession::new(t, &token))
+            .collect()
+    }).await.unwrap();
+    
+    Json(sessions)
+}
+
+
 async fn delete_session(
     State(AppState { conn_pool, .. }): State<AppState>,
     SessionToken(token): SessionToken
@@ -41,3 +157,36 @@ async fn delete_session(
         token.delete(conn);
     }).await.unwrap();
 }
+
+
+/// logs off everywhere except for the current session
+async fn delete_other_sessions(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionToken(token): SessionToken
+) -> Json<RevokedCount> {
+    let revoked = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        token.delete_all_others_of_owner(conn)
+    }).await.unwrap();
+    
+    Json(RevokedCount { revoked })
+}
+
+
+async fn revoke_session(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionToken(token): SessionToken,
+    Path(id): Path<i32>
+) -> StatusCode {
+    tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let owner = token.get_owner(conn);
+        
+        match db::Token::get_of_owner(conn, &owner, id) {
+            None => StatusCode::NOT_FOUND,
+            Some(t) => { t.delete(conn); StatusCode::NO_CONTENT }
+        }
+    }).await.unwrap()
+}
@@ -1,32 +1,38 @@
-use std::io::ErrorKind;
-use std::path::PathBuf;
-use std::str::FromStr;
-use axum::extract::State;
+use axum::extract::{Path, State};
 use axum::http::StatusCode;
 use axum::Json;
 use axum::response::{IntoResponse, Response};
-use axum::routing::{get, post};
+use axum::routing::{get, post, put};
 use crate::{AppState, db};
-use crate::filesystem::{FSError, UserScopedFS};
 use crate::routers::extractors::SessionUser;
-use super::schema::{NewUser, SelfUser};
+use super::schema::{NewUser, Privacy, SelfUser, TwoFactorStatus, TwoFactorEnrollment, TwoFactorCode, RecoveryCodes};
 
 pub fn get_router() -> axum::Router<AppState> {
     axum::Router::new()
         .route("/me", get(get_self_properties).put(set_self_properties).delete(delete_self))
+        .route("/me/privacy", get(get_self_privacy).put(set_self_privacy))
+        .route("/me/blocks", get(get_blocked_users))
+        .route("/me/blocks/:username", put(block_user).delete(unblock_user))
+        .route("/me/2fa", get(get_two_factor).post(enroll_two_factor).delete(disable_two_factor))
+        .route("/me/2fa/enable", post(enable_two_factor))
+        .route("/me/2fa/recovery-codes", post(regenerate_recovery_codes))
         .route("/", post(create_new_user))
 }
 
 
 enum UserCreationError {
     DbError(db::UserCreationError),
+    TemplateNotFound,
+    TemplateNotAllowed,
 }
 
 
 impl IntoResponse for UserCreationError {
     fn into_response(self) -> Response {
         match self {
-            Self::DbError(db::UserCreationError::SuchUsernameIsAlreadyUsed) => StatusCode::CONFLICT.into_response()
+            Self::DbError(db::UserCreationError::SuchUsernameIsAlreadyUsed) => StatusCode::CONFLICT.into_response(),
+            Self::TemplateNotFound => (StatusCode::BAD_REQUEST, "such template does not exist").into_response(),
+            Self::TemplateNotAllowed => (StatusCode::FORBIDDEN, "such template can not be chosen").into_response(),
         }
     }
 }
@@ -34,13 +40,22 @@ impl IntoResponse for UserCreationError {
 
 
 async fn create_new_user(
-    State(AppState { conn_pool, .. }): State<AppState>,
-    Json(NewUser { username, password, properties }): Json<NewUser>
+    State(AppState { conn_pool, filesystem, config, .. }): State<AppState>,
+    Json(NewUser { username, password, properties, template }): Json<NewUser>
 ) -> Result<String, UserCreationError> {
+    let template = match template {
+        Some(name) if filesystem.template(&name).is_none() => return Err(UserCreationError::TemplateNotFound),
+        // the templates might be meant for some users only, so anything else has to be allowed explicitly
+        Some(name) if filesystem.default_template() != Some(name.as_str()) && !config.filesystem.signup_templates.contains(&name) =>
+            return Err(UserCreationError::TemplateNotAllowed),
+        Some(name) => Some(name),
+        None => filesystem.default_template().map(str::to_string),
+    };
+    
     Ok(tokio::task::spawn_blocking(move || {
         let conn = &mut conn_pool.get().unwrap();
         
-        db::User::create(conn, &username, &password, properties.as_ref())
+        db::User::create(conn, &username, &password, properties.as_ref(), template.as_deref())
     }).await.unwrap().map_err(UserCreationError::DbError)?.id.to_string())
 }
 
@@ -49,18 +64,19 @@ async fn delete_self(
     State(AppState { conn_pool, filesystem, .. }): State<AppState>,
     SessionUser(mut user): SessionUser,
 ) {
-    let usfs = UserScopedFS::new(&filesystem, user.id).await.unwrap();  // i hope this doesnt ever fail
+    let user_id = user.id;
     
-    tokio::task::spawn_blocking(move || {
+    let file_keys = tokio::task::spawn_blocking(move || {
         let conn = &mut conn_pool.get().unwrap();
         
-        user.delete(conn);
+        user.delete(conn)
     }).await.unwrap();
     
-    match usfs.remove_item(".".as_ref()).await {
-        Err(FSError::HFS(err)) if err.kind() == ErrorKind::NotFound => {},
-        whatever => whatever.unwrap()   // i really hope this doesnt fail under most other circumstances as well
-    }
+    // whatever is left behind is going to be found by the integrity scan
+    filesystem.remove_attachments(&file_keys).await;
+    if let Err(err) = filesystem.remove_user_dir(user_id).await {
+        log::warn!("failed to remove the storage of deleted user {user_id}: {err}");
+    };
 }
 
 
@@ -89,3 +105,187 @@ async fn set_self_properties(
             .expect("token is valid, so user shouldn't be deleted");
     });
 }
+
+
+async fn get_self_privacy(
+    SessionUser(user): SessionUser
+) -> Json<Privacy> {
+    Json(Privacy { messages: user.get_message_privacy().into() })
+}
+
+
+async fn set_self_privacy(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(mut user): SessionUser,
+    Json(Privacy { messages }): Json<Privacy>,
+) {
+    tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        user.set_message_privacy(conn, messages.into())
+            .expect("token is valid, so user shouldn't be deleted");
+    }).await.unwrap();
+}
+
+
+async fn get_blocked_users(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+) -> Json<Vec<String>> {
+    let blocked = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        db::UserBlock::get_all_blocked_by(conn, &user)
+    }).await.unwrap();
+    
+    Json(blocked.iter().map(db::User::get_username).collect())
+}
+
+
+enum BlockError {
+    UserNotFound,
+    CannotBlockSelf,
+}
+
+
+impl IntoResponse for BlockError {
+    fn into_response(self) -> Response {
+        match self {
+            Self::UserNotFound => (StatusCode::NOT_FOUND, "the user was not found").into_response(),
+            Self::CannotBlockSelf => (StatusCode::BAD_REQUEST, "you can not block yourself").into_response(),
+        }
+    }
+}
+
+
+async fn block_user(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(username): Path<String>,
+) -> Result<(), BlockError> {
+    tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let blocked = db::User::get_by_username(conn, &username).ok_or(BlockError::UserNotFound)?;
+        
+        if blocked.id == user.id {
+            return Err(BlockError::CannotBlockSelf);
+        };
+        
+        db::UserBlock::block(conn, &user, &blocked);
+        
+        Ok(())
+    }).await.unwrap()
+}
+
+
+async fn unblock_user(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Path(username): Path<String>,
+) -> Result<(), BlockError> {
+    tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        let blocked = db::User::get_by_username(conn, &username).ok_or(BlockError::UserNotFound)?;
+        
+        // unblocking someone who isn't blocked already has the wanted outcome
+        db::UserBlock::unblock(conn, &user, &blocked);
+        
+        Ok(())
+    }).await.unwrap()
+}
+
+
+struct TwoFactorError(db::TwoFactorError);
+
+
+impl IntoResponse for TwoFactorError {
+    fn into_response(self) -> Response {
+        match self.0 {
+            db::TwoFactorError::AlreadyEnabled => (StatusCode::CONFLICT, "the second factor is already enabled").into_response(),
+            db::TwoFactorError::NotEnrolled => (StatusCode::CONFLICT, "the second factor has to be enrolled first").into_response(),
+            db::TwoFactorError::NotEnabled => (StatusCode::CONFLICT, "the second factor is not enabled").into_response(),
+            db::TwoFactorError::InvalidCode => (StatusCode::FORBIDDEN, "the code is invalid").into_response(),
+            db::TwoFactorError::Locked => (StatusCode::TOO_MANY_REQUESTS, "too many invalid codes were given, try again later").into_response(),
+        }
+    }
+}
+
+
+async fn get_two_factor(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+) -> Json<TwoFactorStatus> {
+    let status = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        TwoFactorStatus {
+            enabled: db::TwoFactor::is_enabled_for(conn, &user),
+            recovery_codes_left: db::TwoFactor::count_recovery_codes(conn, &user),
+        }
+    }).await.unwrap();
+    
+    Json(status)
+}
+
+
+/// a new secret, which takes effect once it's enabled with a code
+async fn enroll_two_factor(
+    State(AppState { conn_pool, config, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+) -> Result<Json<TwoFactorEnrollment>, TwoFactorError> {
+    let (two_factor, username) = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        db::TwoFactor::enroll(conn, &user).map(|tf| (tf, user.get_username()))
+    }).await.unwrap().map_err(TwoFactorError)?;
+    
+    Ok(Json(TwoFactorEnrollment {
+        uri: two_factor.get_uri(&config.name, &username),
+        secret: two_factor.secret,
+    }))
+}
+
+
+async fn enable_two_factor(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Json(TwoFactorCode { code }): Json<TwoFactorCode>,
+) -> Result<Json<RecoveryCodes>, TwoFactorError> {
+    let recovery_codes = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        db::TwoFactor::enable(conn, &user, &code)
+    }).await.unwrap().map_err(TwoFactorError)?;
+    
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+
+async fn disable_two_factor(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Json(TwoFactorCode { code }): Json<TwoFactorCode>,
+) -> Result<(), TwoFactorError> {
+    tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        db::TwoFactor::disable(conn, &user, &code)
+    }).await.unwrap().map_err(TwoFactorError)
+}
+
+
+async fn regenerate_recovery_codes(
+    State(AppState { conn_pool, .. }): State<AppState>,
+    SessionUser(user): SessionUser,
+    Json(TwoFactorCode { code }): Json<TwoFactorCode>,
+) -> Result<Json<RecoveryCodes>, TwoFactorError> {
+    let recovery_codes = tokio::task::spawn_blocking(move || {
+        let conn = &mut conn_pool.get().unwrap();
+        
+        db::TwoFactor::regenerate_recovery_codes(conn, &user, &code)
+    }).await.unwrap().map_err(TwoFactorError)?;
+    
+    Ok(Json(RecoveryCodes { recovery_codes }))
+}
@@ -0,0 +1,187 @@
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use chrono::{NaiveDateTime, Utc};
+use tokio::time::MissedTickBehavior;
+use crate::db;
+use crate::filesystem::{Filesystem, UserScopedFS};
+use crate::token_usage::TokenUsage;
+
+
+/// how many tokens are checked and deleted at once, so that the db wouldn't be locked for long
+const TOKEN_SWEEP_BATCH_SIZE: i64 = 500;
+
+
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TokenSweepStats {
+    pub runs: u64,
+    /// since the start
+    pub purged: u64,
+    pub last_purged: u64,
+    pub last_run_time: Option<NaiveDateTime>,
+}
+
+
+/// how the token sweeper is doing
+#[derive(Debug, Default)]
+pub struct TokenSweepMetrics {
+    stats: Mutex<TokenSweepStats>,
+}
+
+
+impl TokenSweepMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn record(&self, purged: u64) {
+        let mut stats = self.stats.lock().unwrap();
+        
+        stats.runs += 1;
+        stats.purged += purged;
+        stats.last_purged = purged;
+        stats.last_run_time = Some(Utc::now().naive_utc());
+    }
+
+    pub fn get(&self) -> TokenSweepStats {
+        *self.stats.lock().unwrap()
+    }
+}
+
+
+/// delivers the scheduled messages once they are due, and purges the expired ones
+pub fn spawn_message_scheduler(conn_pool: db::ConnPool, filesystem: Arc<Filesystem>, period: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        // a slow run shouldn't be followed by a burst of them
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        
+        loop {
+            interval.tick().await;
+            
+            let conn_pool = conn_pool.clone();
+            let (delivered, file_keys) = tokio::task::spawn_blocking(move || {
+                let conn = &mut conn_pool.get().unwrap();
+                
+                (db::Message::deliver_due(conn), db::Message::expire_due(conn))
+            }).await.unwrap();
+            
+            if delivered > 0 {
+                log::debug!("delivered {delivered} scheduled messages");
+            };
+            
+            filesystem.remove_attachments(&file_keys).await;
+        };
+    });
+}
+
+
+/// delivers the new files of a template to the users, one by one
+pub fn spawn_template_rollout(filesystem: Arc<Filesystem>, template: String, version: i32, users: Vec<db::User>) {
+    tokio::spawn(async move {
+        let (mut updated, mut failed) = (0, 0);
+        
+        for mut user in users {
+            // users without a template are using the default one, so now it's going to be recorded for them
+            user.fs_template.get_or_insert_with(|| template.clone());
+            
+            // a missing directory gets the template deployed on its own, and then nothing is left to be copied
+            let res = match UserScopedFS::new(&filesystem, &user).await {
+                Ok(usfs) => usfs.deploy_template(&user).await.expect("the template was checked to exist"),
+                Err(err) => Err(err),
+            };
+            
+            match res {
+                Ok(()) => updated += 1,
+                Err(err) => {
+                    log::warn!("failed to roll out template '{template}' to user {}: {err}", user.id);
+                    failed += 1;
+                }
+            };
+        };
+        
+        log::info!("rolled out version {version} of template '{template}': {updated} users updated, {failed} failed");
+    });
+}
+
+
+/// saves the tokens' last use times every now and then
+pub fn spawn_token_usage_flusher(conn_pool: db::ConnPool, token_usage: Arc<TokenUsage>, period: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        
+        loop {
+            interval.tick().await;
+            
+            let conn_pool = conn_pool.clone();
+            let token_usage = token_usage.clone();
+            let flushed = tokio::task::spawn_blocking(move || {
+                let conn = &mut conn_pool.get().unwrap();
+                
+                token_usage.flush(conn)
+            }).await.unwrap();
+            
+            if flushed > 0 {
+                log::debug!("saved the last use times of {flushed} tokens");
+            };
+        };
+    });
+}
+
+
+/// purges the expired tokens, as otherwise only those which someone tries to use are
+pub fn spawn_token_sweeper(conn_pool: db::ConnPool, token_usage: Arc<TokenUsage>, metrics: Arc<TokenSweepMetrics>, period: Duration) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(period);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        
+        loop {
+            interval.tick().await;
+            
+            let conn_pool = conn_pool.clone();
+            let token_usage = token_usage.clone();
+            let purged = tokio::task::spawn_blocking(move || {
+                let conn = &mut conn_pool.get().unwrap();
+                
+                let mut purged = 0;
+                let mut after_id = -1;
+                loop {
+                    let page = db::Token::get_page_of_expiring(conn, after_id, TOKEN_SWEEP_BATCH_SIZE);
+                    let Some(last) = page.last() else { break };
+                    after_id = last.id;
+                    
+                    let is_last = (page.len() as i64) < TOKEN_SWEEP_BATCH_SIZE;
+                    
+                    // the last uses, which aren't saved yet, can keep the tokens alive
+                    let expired_ids = page.into_iter()
+                        .map(|mut t| { token_usage.update(&mut t); t })
+                        .filter(|t| t.is_expired())
+                        .map(|t| t.id)
+                        .collect::<Vec<_>>();
+                    
+                    if !expired_ids.is_empty() {
+                        purged += db::Token::delete_all(conn, &expired_ids) as u64;
+                    };
+                    
+                    if is_last {
+                        break;
+                    };
+                };
+                
+                // the logins which were never finished
+                let challenges = db::LoginChallenge::delete_expired(conn);
+                if challenges > 0 {
+                    log::debug!("purged {challenges} expired login challenges");
+                };
+                
+                purged
+            }).await.unwrap();
+            
+            metrics.record(purged);
+            
+            if purged > 0 {
+                log::info!("purged {purged} expired tokens");
+            };
+        };
+    });
+}
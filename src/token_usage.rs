@@ -0,0 +1,48 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Duration;
+use chrono::NaiveDateTime;
+use crate::db;
+
+
+/// how often the times are written to the db. the sessions are checked against the unwritten ones too,
+/// so it's only about how much is lost on a crash
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(30);
+
+
+/// when the tokens were last used, kept in memory until it's written to the db in one go,
+/// so that the requests wouldn't have to write anything
+#[derive(Debug, Default)]
+pub struct TokenUsage {
+    /// by the token id
+    last_used_times: Mutex<HashMap<i32, NaiveDateTime>>,
+}
+
+
+impl TokenUsage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, token_id: i32, time: NaiveDateTime) {
+        self.last_used_times.lock().unwrap().insert(token_id, time);
+    }
+
+    /// takes the use which isn't saved yet into account
+    pub fn update(&self, token: &mut db::Token) {
+        if let Some(time) = self.last_used_times.lock().unwrap().get(&token.id) {
+            token.last_used_time = token.last_used_time.max(Some(*time));
+        };
+    }
+
+    /// WARNING: BLOCKS, AS IT USES THE DB
+    pub fn flush(&self, conn: &mut diesel::SqliteConnection) -> usize {
+        let times = std::mem::take(&mut *self.last_used_times.lock().unwrap());
+        
+        if !times.is_empty() {
+            db::Token::set_last_used_times(conn, &times);
+        };
+        
+        times.len()
+    }
+}
@@ -0,0 +1,235 @@
+use chrono::Utc;
+use sha1::{Digest, Sha1};
+
+
+/// the usual parameters, which every authenticator app supports
+const STEP_SECONDS: i64 = 30;
+const DIGITS: u32 = 6;
+const SECRET_LENGTH: usize = 20;
+
+/// how many steps the clock of the authenticator may be off by, either way
+const ALLOWED_DRIFT: i64 = 1;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+
+pub fn generate_secret() -> Vec<u8> {
+    (0..SECRET_LENGTH).map(|_| rand::random::<u8>()).collect()
+}
+
+
+/// without the padding, as the authenticator apps expect it
+pub fn encode_base32(data: &[u8]) -> String {
+    let mut encoded = String::new();
+
+    for chunk in data.chunks(5) {
+        let mut buffer = [0u8; 5];
+        buffer[..chunk.len()].copy_from_slice(chunk);
+        let bits = buffer.iter().fold(0u64, |acc, b| (acc << 8) | *b as u64);
+
+        for i in 0..(chunk.len() * 8).div_ceil(5) {
+            encoded.push(BASE32_ALPHABET[((bits >> (35 - i * 5)) & 0x1f) as usize] as char);
+        };
+    };
+
+    encoded
+}
+
+
+pub fn decode_base32(encoded: &str) -> Option<Vec<u8>> {
+    let mut decoded = Vec::new();
+    let mut bits = 0u64;
+    let mut bit_count = 0;
+
+    for c in encoded.trim_end_matches('=').chars() {
+        let value = BASE32_ALPHABET.iter().position(|a| *a as char == c.to_ascii_uppercase())?;
+        bits = (bits << 5) | value as u64;
+        bit_count += 5;
+
+        if bit_count >= 8 {
+            bit_count -= 8;
+            decoded.push((bits >> bit_count) as u8);
+        };
+    };
+
+    Some(decoded)
+}
+
+
+/// for the authenticator apps, usually shown as a qr code
+pub fn get_uri(secret: &[u8], issuer: &str, account: &str) -> String {
+    let issuer = encode_uri_component(issuer);
+
+    format!(
+        "otpauth://totp/{issuer}:{}?secret={}&issuer={issuer}&algorithm=SHA1&digits={DIGITS}&period={STEP_SECONDS}",
+        encode_uri_component(account),
+        encode_base32(secret),
+    )
+}
+
+
+fn encode_uri_component(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (b as char).to_string(),
+            _ => format!("%{b:02X}"),
+        })
+        .collect()
+}
+
+
+fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut block = [0u8; BLOCK_SIZE];
+    if key.len() > BLOCK_SIZE {
+        block[..20].copy_from_slice(&Sha1::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    };
+
+    let inner = Sha1::new()
+        .chain_update(block.map(|b| b ^ 0x36))
+        .chain_update(message)
+        .finalize();
+
+    Sha1::new()
+        .chain_update(block.map(|b| b ^ 0x5c))
+        .chain_update(inner)
+        .finalize()
+        .into()
+}
+
+
+fn get_code(secret: &[u8], step: i64) -> u32 {
+    let hash = hmac_sha1(secret, &step.to_be_bytes());
+
+    // the dynamic truncation of rfc 4226
+    let offset = (hash[19] & 0xf) as usize;
+    let value = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+
+    value % 10u32.pow(DIGITS)
+}
+
+
+pub fn get_current_step() -> i64 {
+    Utc::now().timestamp().div_euclid(STEP_SECONDS)
+}
+
+
+/// returns the step the code is for, if it's a valid one newer than the last used step.
+/// the used steps are not accepted again, so that an overheard code couldn't be replayed
+pub fn verify(secret: &[u8], code: &str, last_used_step: Option<i64>) -> Option<i64> {
+    verify_at(secret, code, last_used_step, get_current_step())
+}
+
+
+fn verify_at(secret: &[u8], code: &str, last_used_step: Option<i64>, current_step: i64) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    };
+
+    let code = code.parse::<u32>().ok()?;
+
+    (current_step - ALLOWED_DRIFT..=current_step + ALLOWED_DRIFT)
+        .filter(|step| last_used_step.is_none_or(|last| *step > last))
+        .find(|step| get_code(secret, *step) == code)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the sha1 secret of rfc 6238
+    const SECRET: &[u8] = b"12345678901234567890";
+
+    fn code_at(step: i64) -> String {
+        format!("{:0>6}", get_code(SECRET, step))
+    }
+
+    #[test]
+    fn codes_match_rfc_6238() {
+        // the last 6 of the 8 digits given there
+        for (time, code) in [
+            (59, 287082),
+            (1111111109, 81804),
+            (1111111111, 50471),
+            (1234567890, 5924),
+            (2000000000, 279037),
+            (20000000000, 353130),
+        ] {
+            assert_eq!(get_code(SECRET, time / STEP_SECONDS), code, "at {time}");
+        };
+    }
+
+    #[test]
+    fn codes_are_accepted_within_the_drift() {
+        let step = 1000;
+
+        for drift in -ALLOWED_DRIFT..=ALLOWED_DRIFT {
+            assert_eq!(verify_at(SECRET, &code_at(step + drift), None, step), Some(step + drift));
+        };
+
+        assert_eq!(verify_at(SECRET, &code_at(step - ALLOWED_DRIFT - 1), None, step), None);
+        assert_eq!(verify_at(SECRET, &code_at(step + ALLOWED_DRIFT + 1), None, step), None);
+    }
+
+    #[test]
+    fn used_steps_are_rejected() {
+        let step = 1000;
+
+        assert_eq!(verify_at(SECRET, &code_at(step), Some(step), step), None);
+        assert_eq!(verify_at(SECRET, &code_at(step - 1), Some(step - 1), step), None);
+        assert_eq!(verify_at(SECRET, &code_at(step - 1), Some(step), step), None);
+        assert_eq!(verify_at(SECRET, &code_at(step + 1), Some(step), step), Some(step + 1));
+    }
+
+    #[test]
+    fn malformed_codes_are_rejected() {
+        let step = 1000;
+        let code = code_at(step);
+
+        assert_eq!(verify_at(SECRET, &format!(" {code}\n"), None, step), Some(step));
+        assert_eq!(verify_at(SECRET, &code[1..], None, step), None);
+        assert_eq!(verify_at(SECRET, &format!("0{code}"), None, step), None);
+        assert_eq!(verify_at(SECRET, &format!("+{}", &code[1..]), None, step), None);
+        assert_eq!(verify_at(SECRET, "", None, step), None);
+    }
+
+    #[test]
+    fn base32_matches_rfc_4648() {
+        for (data, encoded) in [
+            ("", ""),
+            ("f", "MY"),
+            ("fo", "MZXQ"),
+            ("foo", "MZXW6"),
+            ("foob", "MZXW6YQ"),
+            ("fooba", "MZXW6YTB"),
+            ("foobar", "MZXW6YTBOI"),
+        ] {
+            assert_eq!(encode_base32(data.as_bytes()), encoded);
+            assert_eq!(decode_base32(encoded).unwrap(), data.as_bytes());
+        };
+
+        // the way some apps and users type it
+        assert_eq!(decode_base32("mzxw6ytboi======").unwrap(), b"foobar");
+        assert_eq!(decode_base32("MZXW6YTB1"), None);
+    }
+
+    #[test]
+    fn base32_round_trips() {
+        for _ in 0..100 {
+            let secret = generate_secret();
+
+            assert_eq!(decode_base32(&encode_base32(&secret)).unwrap(), secret);
+        };
+
+        for length in 0..=SECRET_LENGTH {
+            let data = (0..length as u8).map(|b| b.wrapping_mul(37)).collect::<Vec<_>>();
+
+            assert_eq!(decode_base32(&encode_base32(&data)).unwrap(), data);
+        };
+    }
+}